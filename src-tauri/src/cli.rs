@@ -0,0 +1,232 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+use crate::export::{self, ExportFormat};
+use crate::link_check::{self, LinkReport};
+use crate::search::{self, SearchOptions};
+use crate::settings::{self, WorkspaceSettings};
+use crate::tree::{list_files, TreeOptions};
+
+// The `identifier` in tauri.conf.json, which names the app config dir
+const APP_IDENTIFIER: &str = "com.anduscheusan.aqua";
+
+const USAGE: &str = "Usage:
+  aqua                                   open the editor
+  aqua export <file> --to html|pdf [-o <out>]
+  aqua check-links <dir> [--json]
+  aqua search <dir> <query> [--case-sensitive] [--word] [--regex] [--max <n>]";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Export { file: PathBuf, format: ExportFormat, out: Option<PathBuf> },
+    CheckLinks { dir: PathBuf, json: bool },
+    Search { dir: PathBuf, query: String, options: SearchOptions },
+    Help,
+}
+
+// The headless subcommand in `args` (without the program name), or None when the
+// editor should open. Parse errors come back as the message to print.
+pub fn parse(args: &[String]) -> Option<Result<Command, String>> {
+
+    let (name, rest) = args.split_first()?;
+    let command = match name.as_str() {
+        "export" => parse_export(rest),
+        "check-links" => parse_check_links(rest),
+        "search" => parse_search(rest),
+        "help" | "--help" | "-h" => Ok(Command::Help),
+        _ => return None,
+    };
+    Some(command)
+
+}
+
+// An option and its value, for the options that take one
+type Flag<'a> = (&'a str, Option<&'a str>);
+
+// Splits flags from positional arguments; `takes_value` names the flags followed by a value
+fn split_args<'a>(args: &'a [String], takes_value: &[&str]) -> Result<(Vec<&'a str>, Vec<Flag<'a>>), String> {
+    let mut positional = Vec::new();
+    let mut flags = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            positional.push(arg.as_str());
+        } else if takes_value.contains(&arg.as_str()) {
+            let value = iter.next().ok_or_else(|| format!("{} needs a value", arg))?;
+            flags.push((arg.as_str(), Some(value.as_str())));
+        } else {
+            flags.push((arg.as_str(), None));
+        }
+    }
+    Ok((positional, flags))
+}
+
+fn parse_export(args: &[String]) -> Result<Command, String> {
+    let (positional, flags) = split_args(args, &["--to", "-o", "--out"])?;
+    let mut format = None;
+    let mut out = None;
+    for (flag, value) in flags {
+        match (flag, value) {
+            ("--to", Some(value)) => format = Some(ExportFormat::parse(value)?),
+            ("-o" | "--out", Some(value)) => out = Some(PathBuf::from(value)),
+            (other, _) => return Err(format!("Unknown option for export: {}", other)),
+        }
+    }
+    match positional.as_slice() {
+        [file] => Ok(Command::Export { file: PathBuf::from(file), format: format.ok_or("export needs --to html or --to pdf")?, out }),
+        _ => Err("export takes one file".into()),
+    }
+}
+
+fn parse_check_links(args: &[String]) -> Result<Command, String> {
+    let (positional, flags) = split_args(args, &[])?;
+    let mut json = false;
+    for (flag, _) in flags {
+        match flag {
+            "--json" => json = true,
+            other => return Err(format!("Unknown option for check-links: {}", other)),
+        }
+    }
+    match positional.as_slice() {
+        [dir] => Ok(Command::CheckLinks { dir: PathBuf::from(dir), json }),
+        _ => Err("check-links takes one folder".into()),
+    }
+}
+
+fn parse_search(args: &[String]) -> Result<Command, String> {
+    let (positional, flags) = split_args(args, &["--max"])?;
+    let mut options = SearchOptions { context: 0, ..SearchOptions::default() };
+    for (flag, value) in flags {
+        match (flag, value) {
+            ("--case-sensitive", _) => options.case_sensitive = true,
+            ("--word", _) => options.whole_word = true,
+            ("--regex", _) => options.regex = true,
+            ("--max", Some(value)) => options.max_results = value.parse().map_err(|_| format!("Invalid --max: {}", value))?,
+            (other, _) => return Err(format!("Unknown option for search: {}", other)),
+        }
+    }
+    match positional.as_slice() {
+        [dir, query] => Ok(Command::Search { dir: PathBuf::from(dir), query: query.to_string(), options }),
+        _ => Err("search takes a folder and a query".into()),
+    }
+}
+
+// Paths in output are relative to the folder given, as in grep
+fn shown(root: &Path, path: &str) -> String {
+    let path = Path::new(path);
+    path.strip_prefix(root).unwrap_or(path).to_string_lossy().to_string()
+}
+
+fn shown_files(dir: &Path) -> Result<(PathBuf, Vec<PathBuf>), String> {
+    if !dir.is_dir() {
+        return Err(format!("Not a folder: {}", dir.display()));
+    }
+    let root = dir.canonicalize().map_err(|e| e.to_string())?;
+    let files = list_files(&root, &TreeOptions::new(&root, &workspace_settings(&root))?);
+    Ok((root, files))
+}
+
+// The folder's settings from the app, so the same files are shown as in its sidebar.
+// Resolves the config dir the way Tauri's `app_config_dir` does.
+fn workspace_settings(root: &Path) -> WorkspaceSettings {
+    match dirs::config_dir() {
+        Some(dir) => settings::load(&dir.join(APP_IDENTIFIER), root),
+        None => WorkspaceSettings::default(),
+    }
+}
+
+// Exit codes follow grep: 0 when the command found something (or had nothing to
+// report for check-links), 1 otherwise, 2 on errors
+fn execute(command: Command) -> Result<i32, String> {
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+
+    match command {
+        Command::Help => {
+            let _ = writeln!(out, "{}", USAGE);
+            Ok(0)
+        }
+        Command::Export { file, format, out: target } => {
+            let written = export::export(&file, format, target.as_deref())?;
+            let _ = writeln!(out, "{}", written.display());
+            Ok(0)
+        }
+        Command::CheckLinks { dir, json } => {
+            let (root, files) = shown_files(&dir)?;
+            let mut issues = Vec::new();
+            let summary = link_check::check_files(&root, &files, &AtomicBool::new(false), |found| issues.extend(found));
+            if json {
+                let report = LinkReport { roots: vec![root.to_string_lossy().to_string()], summary: &summary, issues: &issues };
+                let _ = writeln!(out, "{}", serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?);
+            } else {
+                for issue in &issues {
+                    let _ = writeln!(out, "{}:{}:{}: {}: {}", shown(&root, &issue.path), issue.line, issue.column, issue.problem.describe(), issue.target);
+                }
+            }
+            Ok(if issues.is_empty() { 0 } else { 1 })
+        }
+        Command::Search { dir, query, options } => {
+            let query = search::Query::new(&query, &options)?;
+            let (root, files) = shown_files(&dir)?;
+            let summary = search::search_files(&root, &files, &query, &AtomicBool::new(false), |matches| {
+                for m in matches {
+                    let _ = writeln!(out, "{}:{}:{}: {}", shown(&root, &m.path), m.line, m.column, m.text);
+                }
+            });
+            Ok(if summary.matches > 0 { 0 } else { 1 })
+        }
+    }
+
+}
+
+// Runs a headless subcommand and returns its exit code, or None when `args`
+// (the full command line) should open the editor instead
+pub fn run(args: Vec<String>) -> Option<i32> {
+    let command = parse(args.get(1..).unwrap_or_default())?;
+    Some(match command.and_then(execute) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("aqua: {}\n\n{}", e, USAGE);
+            2
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_subcommands() {
+        assert_eq!(
+            parse(&args("export notes/a.md --to pdf -o out.pdf")),
+            Some(Ok(Command::Export { file: "notes/a.md".into(), format: ExportFormat::Pdf, out: Some("out.pdf".into()) }))
+        );
+        assert_eq!(parse(&args("check-links docs --json")), Some(Ok(Command::CheckLinks { dir: "docs".into(), json: true })));
+
+        match parse(&args("search docs todo --word --max 5")) {
+            Some(Ok(Command::Search { dir, query, options })) => {
+                assert_eq!((dir, query.as_str()), (PathBuf::from("docs"), "todo"));
+                assert!(options.whole_word && !options.regex);
+                assert_eq!(options.max_results, 5);
+            }
+            other => panic!("Expected search, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_opens_editor_or_reports_errors() {
+        assert_eq!(parse(&[]), None);
+        assert_eq!(parse(&args("notes/a.md")), None);
+        assert!(matches!(parse(&args("export a.md")), Some(Err(_))));
+        assert!(matches!(parse(&args("export a.md --to docx")), Some(Err(_))));
+        assert!(matches!(parse(&args("search docs")), Some(Err(_))));
+        assert!(matches!(parse(&args("check-links docs --fix")), Some(Err(_))));
+    }
+}
@@ -0,0 +1,324 @@
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffOp {
+    Equal,
+    Insert,
+    Delete,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct DiffLine {
+    pub op: DiffOp,
+    pub text: String,
+    // 1-based line numbers on each side, None on the side the line is missing from
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+}
+
+// A run of changes with the unchanged lines around them
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+// Past this many edits in either direction, the middle snake search of a region
+// gives up and the region is reported as replaced whole. Keeps wholesale rewrites
+// of large files from costing quadratic time.
+const MAX_COST: isize = 4096;
+
+// Part of the edit graph: old[left..right] against new[top..bottom]
+#[derive(Clone, Copy)]
+struct Region {
+    left: isize,
+    top: isize,
+    right: isize,
+    bottom: isize,
+}
+
+impl Region {
+
+    fn width(&self) -> isize {
+        self.right - self.left
+    }
+
+    fn height(&self) -> isize {
+        self.bottom - self.top
+    }
+
+}
+
+// A stretch of the shortest edit path: at most one edit and a run of matching lines
+type Snake = ((isize, isize), (isize, isize));
+
+// Myers' middle snake, searched from both corners at once in linear space. None when
+// the region needs more than MAX_COST edits each way.
+fn middle_snake(old: &[&str], new: &[&str], r: Region) -> Option<Snake> {
+
+    let delta = r.width() - r.height();
+    let max = ((r.width() + r.height() + 1) / 2).min(MAX_COST);
+    let offset = max + 1;
+    let at = |k: isize| (k + offset) as usize;
+    let mut forward = vec![0isize; 2 * max as usize + 3];
+    let mut backward = vec![0isize; 2 * max as usize + 3];
+    forward[at(1)] = r.left;
+    backward[at(1)] = r.bottom;
+
+    for d in 0..=max {
+
+        for k in (-d..=d).rev().step_by(2) {
+            let (px, mut x) = if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                (forward[at(k + 1)], forward[at(k + 1)])
+            } else {
+                (forward[at(k - 1)], forward[at(k - 1)] + 1)
+            };
+            let mut y = r.top + (x - r.left) - k;
+            let py = if d == 0 || x != px { y } else { y - 1 };
+            while x < r.right && y < r.bottom && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[at(k)] = x;
+            let c = k - delta;
+            if delta % 2 != 0 && (-(d - 1)..=d - 1).contains(&c) && y >= backward[at(c)] {
+                return Some(((px, py), (x, y)));
+            }
+        }
+
+        for c in (-d..=d).rev().step_by(2) {
+            let (py, mut y) = if c == -d || (c != d && backward[at(c - 1)] > backward[at(c + 1)]) {
+                (backward[at(c + 1)], backward[at(c + 1)])
+            } else {
+                (backward[at(c - 1)], backward[at(c - 1)] - 1)
+            };
+            let k = c + delta;
+            let mut x = r.left + (y - r.top) + k;
+            let px = if d == 0 || y != py { x } else { x + 1 };
+            while x > r.left && y > r.top && old[x as usize - 1] == new[y as usize - 1] {
+                x -= 1;
+                y -= 1;
+            }
+            backward[at(c)] = y;
+            if delta % 2 == 0 && (-d..=d).contains(&k) && x <= forward[at(k)] {
+                return Some(((x, y), (px, py)));
+            }
+        }
+
+    }
+
+    None
+
+}
+
+// Collects the matching lines of a region in order, splitting it at its middle snake
+fn collect_matches(old: &[&str], new: &[&str], r: Region, pairs: &mut Vec<(usize, usize)>) {
+
+    if r.width() == 0 || r.height() == 0 {
+        return;
+    }
+    let ((sx, sy), (ex, ey)) = match middle_snake(old, new, r) {
+        Some(snake) => snake,
+        None => return,
+    };
+
+    collect_matches(old, new, Region { right: sx, bottom: sy, ..r }, pairs);
+
+    // The snake holds one edit at most, before or after its diagonal
+    let (mut x, mut y) = (sx, sy);
+    let mut diagonal = |x: &mut isize, y: &mut isize| {
+        while *x < ex && *y < ey && old[*x as usize] == new[*y as usize] {
+            pairs.push((*x as usize, *y as usize));
+            *x += 1;
+            *y += 1;
+        }
+    };
+    diagonal(&mut x, &mut y);
+    match (ex - x).cmp(&(ey - y)) {
+        std::cmp::Ordering::Less => y += 1,
+        std::cmp::Ordering::Greater => x += 1,
+        std::cmp::Ordering::Equal => {}
+    }
+    diagonal(&mut x, &mut y);
+
+    collect_matches(old, new, Region { left: ex, top: ey, ..r }, pairs);
+
+}
+
+// The matching lines of a shortest edit script, as (old index, new index) pairs in order
+fn matches(old: &[&str], new: &[&str]) -> Vec<(usize, usize)> {
+
+    // Lines shared at both ends are matched before searching what lies between
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    let region = Region {
+        left: prefix as isize,
+        top: prefix as isize,
+        right: (old.len() - suffix) as isize,
+        bottom: (new.len() - suffix) as isize,
+    };
+    collect_matches(old, new, region, &mut pairs);
+    pairs.extend((0..suffix).map(|i| (old.len() - suffix + i, new.len() - suffix + i)));
+    pairs
+
+}
+
+// Line by line difference between two texts
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    let line = |op, text: &str, old_line, new_line| DiffLine { op, text: text.to_string(), old_line, new_line };
+    for (a, b) in matches(&old, &new).into_iter().chain(std::iter::once((old.len(), new.len()))) {
+        for (offset, text) in old[i..a].iter().enumerate() {
+            lines.push(line(DiffOp::Delete, text, Some(i + offset + 1), None));
+        }
+        for (offset, text) in new[j..b].iter().enumerate() {
+            lines.push(line(DiffOp::Insert, text, None, Some(j + offset + 1)));
+        }
+        if a < old.len() {
+            lines.push(line(DiffOp::Equal, old[a], Some(a + 1), Some(b + 1)));
+        }
+        i = a + 1;
+        j = b + 1;
+    }
+
+    lines
+
+}
+
+// The changes between two texts grouped into hunks with `context` unchanged lines around each
+pub fn hunks(old: &str, new: &str, context: usize) -> Vec<DiffHunk> {
+
+    let lines = diff_lines(old, new);
+    let changed: Vec<usize> = (0..lines.len()).filter(|&i| lines[i].op != DiffOp::Equal).collect();
+    let mut hunks = Vec::new();
+    let mut start_of_group = 0;
+
+    while start_of_group < changed.len() {
+        // Changes closer than twice the context share a hunk
+        let mut end_of_group = start_of_group;
+        while end_of_group + 1 < changed.len() && changed[end_of_group + 1] - changed[end_of_group] <= 2 * context + 1 {
+            end_of_group += 1;
+        }
+        let from = changed[start_of_group].saturating_sub(context);
+        let to = (changed[end_of_group] + context + 1).min(lines.len());
+        let slice = lines[from..to].to_vec();
+
+        // A side with no lines in the hunk starts after the line before it, as in unified diffs
+        let start = |side: fn(&DiffLine) -> Option<usize>| {
+            slice.iter().find_map(side).unwrap_or_else(|| lines[..from].iter().rev().find_map(side).unwrap_or(0))
+        };
+        hunks.push(DiffHunk {
+            old_start: start(|l| l.old_line),
+            old_len: slice.iter().filter(|l| l.old_line.is_some()).count(),
+            new_start: start(|l| l.new_line),
+            new_len: slice.iter().filter(|l| l.new_line.is_some()).count(),
+            lines: slice,
+        });
+        start_of_group = end_of_group + 1;
+    }
+
+    hunks
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(old: &str, new: &str) -> String {
+        diff_lines(old, new)
+            .iter()
+            .map(|l| match l.op {
+                DiffOp::Equal => format!(" {}", l.text),
+                DiffOp::Insert => format!("+{}", l.text),
+                DiffOp::Delete => format!("-{}", l.text),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!(ops("a\nb\nc", "a\nb\nc"), " a\n b\n c");
+        assert_eq!(ops("a\nb\nc", "a\nx\nc\nd"), " a\n-b\n+x\n c\n+d");
+        assert_eq!(ops("", "new"), "+new");
+        assert_eq!(ops("old", ""), "-old");
+
+        let lines = diff_lines("one\ntwo\nthree", "zero\none\nthree");
+        let numbered: Vec<(Option<usize>, Option<usize>)> = lines.iter().map(|l| (l.old_line, l.new_line)).collect();
+        assert_eq!(numbered, vec![(None, Some(1)), (Some(1), Some(2)), (Some(2), None), (Some(3), Some(3))]);
+    }
+
+    #[test]
+    fn test_hunks_group_nearby_changes() {
+        let old: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        let new = old.replace("line 3\n", "line three\n").replace("line 5\n", "").replace("line 18\n", "line 18\nextra\n");
+
+        let found = hunks(&old, &new, 1);
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].old_start, found[0].old_len, found[0].new_start, found[0].new_len), (2, 5, 2, 4));
+        assert_eq!((found[1].old_start, found[1].old_len, found[1].new_start, found[1].new_len), (18, 2, 17, 3));
+        assert!(hunks("same", "same", 3).is_empty());
+    }
+
+    #[test]
+    fn test_diff_is_minimal() {
+        let count = |old: &str, new: &str| diff_lines(old, new).iter().filter(|l| l.op != DiffOp::Equal).count();
+        assert_eq!(count("a\nb\nc\na\nb\nb\na", "c\nb\na\nb\na\nc"), 5);
+        assert_eq!(count("x\na\ny\nb\nz", "a\nq\nb"), 4);
+        assert_eq!(ops("a\nb\nc\nd", "b\nc\nd\ne"), "-a\n b\n c\n d\n+e");
+    }
+
+    #[test]
+    fn test_diff_matches_longest_common_subsequence() {
+        // Small pseudo-random texts over a few distinct lines, checked against a full LCS table
+        let mut seed: u64 = 7;
+        let mut next = |bound: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+        for _ in 0..300 {
+            let old: Vec<String> = (0..next(12)).map(|_| ["a", "b", "c"][next(3) as usize].to_string()).collect();
+            let new: Vec<String> = (0..next(12)).map(|_| ["a", "b", "c"][next(3) as usize].to_string()).collect();
+
+            let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+            for i in (0..old.len()).rev() {
+                for j in (0..new.len()).rev() {
+                    lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+                }
+            }
+
+            let lines = diff_lines(&old.join("\n"), &new.join("\n"));
+            let kept = |op: DiffOp| lines.iter().filter(|l| l.op != op).map(|l| l.text.clone()).collect::<Vec<_>>();
+            assert_eq!(lines.iter().filter(|l| l.op == DiffOp::Equal).count(), lcs[0][0], "{:?} {:?}", old, new);
+            assert_eq!(kept(DiffOp::Insert), old);
+            assert_eq!(kept(DiffOp::Delete), new);
+        }
+    }
+
+    #[test]
+    fn test_large_rewrite_falls_back_to_replace() {
+        let old: String = (0..20_000).map(|i| format!("old {}\n", i)).collect();
+        let new: String = (0..20_000).map(|i| format!("new {}\n", i)).collect();
+
+        let lines = diff_lines(&old, &new);
+        assert_eq!(lines.len(), 40_000);
+        assert!(lines.iter().all(|l| l.op != DiffOp::Equal));
+
+        // Scattered edits in a large file still diff line by line
+        let edited = old.replace("old 100\n", "changed\n").replace("old 15000\n", "");
+        let found = hunks(&old, &edited, 0);
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].old_start, found[0].old_len, found[0].new_len), (101, 1, 1));
+        assert_eq!((found[1].old_start, found[1].old_len, found[1].new_len), (15001, 1, 0));
+    }
+}
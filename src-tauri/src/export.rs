@@ -0,0 +1,219 @@
+use std::fs;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use pulldown_cmark::{html, Options, Parser};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Html,
+    Pdf,
+}
+
+impl ExportFormat {
+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "html" => Ok(ExportFormat::Html),
+            "pdf" => Ok(ExportFormat::Pdf),
+            other => Err(format!("Unknown export format: {} (expected html or pdf)", other)),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Html => "html",
+            ExportFormat::Pdf => "pdf",
+        }
+    }
+
+}
+
+// Same look as the app's print stylesheet
+const STYLE: &str = "body { margin: 0; color: black; background: white; }
+.preview { padding: 2cm; line-height: 1.6; font-family: sans-serif; }
+h1 { color: #0ea5e9; }
+pre { background: #f4f4f4; padding: 1em; border-radius: 5px; overflow-x: auto; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ddd; padding: 0.3em 0.6em; }
+img { max-width: 100%; }";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// A standalone page for a note, with the GitHub extensions the preview renders.
+// Both the app's "Export as HTML" and the command line write this page. It lacks what
+// the preview's extensions add: KaTeX math, highlight.js code colours and -> arrows.
+pub fn render_html(markdown: &str, title: &str) -> String {
+
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let mut body = String::new();
+    html::push_html(&mut body, Parser::new_ext(markdown, options));
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"UTF-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<div class=\"preview\">\n{}</div>\n</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        body
+    )
+
+}
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .flat_map(|dir| [dir.join(name), dir.join(format!("{}.exe", name))])
+        .find(|p| p.is_file())
+}
+
+// A Chromium-based browser to print with, from AQUA_BROWSER or the usual install places
+fn find_browser() -> Option<PathBuf> {
+
+    if let Some(browser) = env::var_os("AQUA_BROWSER") {
+        return Some(PathBuf::from(browser));
+    }
+
+    let names = ["chromium", "chromium-browser", "google-chrome", "google-chrome-stable", "microsoft-edge", "msedge"];
+    let installed = [
+        "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome",
+        "/Applications/Chromium.app/Contents/MacOS/Chromium",
+        "/Applications/Microsoft Edge.app/Contents/MacOS/Microsoft Edge",
+        "C:\\Program Files\\Google\\Chrome\\Application\\chrome.exe",
+        "C:\\Program Files (x86)\\Microsoft\\Edge\\Application\\msedge.exe",
+    ];
+    names
+        .iter()
+        .find_map(|name| find_in_path(name))
+        .or_else(|| installed.iter().map(PathBuf::from).find(|p| p.is_file()))
+
+}
+
+// A file:/// URL for an absolute path, percent-encoded. Windows paths keep their
+// drive letter ("file:///C:/...") and UNC shares become the host.
+fn file_url(path: &Path) -> String {
+
+    let mut text = path.to_string_lossy().to_string();
+    if cfg!(windows) {
+        text = text.replace('\\', "/");
+    }
+    // Verbatim prefixes from canonicalize on Windows
+    if let Some(rest) = text.strip_prefix("//?/UNC/") {
+        text = format!("//{}", rest);
+    } else if let Some(rest) = text.strip_prefix("//?/") {
+        text = rest.to_string();
+    }
+
+    let (host, path) = match text.strip_prefix("//") {
+        Some(rest) => match rest.split_once('/') {
+            Some((host, path)) => (host.to_string(), format!("/{}", path)),
+            None => (rest.to_string(), "/".to_string()),
+        },
+        None if text.starts_with('/') => (String::new(), text),
+        None => (String::new(), format!("/{}", text)),
+    };
+
+    let mut url = format!("file://{}", host);
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => url.push(byte as char),
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+
+}
+
+// PDF rendering needs a browser engine; without a window we print the HTML
+// export with a headless browser
+fn print_pdf(html_file: &Path, out: &Path) -> Result<(), String> {
+
+    let browser = find_browser().ok_or("No Chromium-based browser found to print the PDF with; set AQUA_BROWSER")?;
+    let html_file = html_file.canonicalize().map_err(|e| format!("Failed to resolve {}: {}", html_file.display(), e))?;
+    let url = file_url(&html_file);
+
+    let status = Command::new(&browser)
+        .args(["--headless", "--disable-gpu", "--no-pdf-header-footer"])
+        .arg(format!("--print-to-pdf={}", out.display()))
+        .arg(url)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", browser.display(), e))?;
+
+    if !status.status.success() || !out.exists() {
+        return Err(format!("{} could not print the PDF: {}", browser.display(), String::from_utf8_lossy(&status.stderr).trim()));
+    }
+    Ok(())
+
+}
+
+// Exports a note next to itself, or to `out`, and returns where it was written
+pub fn export(file: &Path, format: ExportFormat, out: Option<&Path>) -> Result<PathBuf, String> {
+
+    let markdown = fs::read_to_string(file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+    let title = file.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let out = out.map(Path::to_path_buf).unwrap_or_else(|| file.with_extension(format.extension()));
+    let page = render_html(&markdown, &title);
+
+    match format {
+        ExportFormat::Html => fs::write(&out, page).map_err(|e| format!("Failed to write {}: {}", out.display(), e))?,
+        ExportFormat::Pdf => {
+            // Written next to the note so relative images resolve
+            let page_file = file.with_file_name(format!(".{}.aqua-export.html", title));
+            fs::write(&page_file, page).map_err(|e| e.to_string())?;
+            let printed = print_pdf(&page_file, &out);
+            let _ = fs::remove_file(&page_file);
+            printed?;
+        }
+    }
+
+    Ok(out)
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_render_html() {
+        let page = render_html("# Title\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n- [x] done ~~old~~", "Notes <draft>");
+        assert!(page.contains("<title>Notes &lt;draft&gt;</title>"));
+        assert!(page.contains("<h1>Title</h1>"));
+        assert!(page.contains("<table>"));
+        assert!(page.contains("checkbox"));
+        assert!(page.contains("<del>old</del>"));
+    }
+
+    #[test]
+    fn test_export_html_next_to_note() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let note = temp_dir.path().join("note.md");
+        fs::write(&note, "Some *text*").unwrap();
+
+        let out = export(&note, ExportFormat::Html, None).unwrap();
+        assert_eq!(out, temp_dir.path().join("note.html"));
+        assert!(fs::read_to_string(out).unwrap().contains("<em>text</em>"));
+
+        assert_eq!(ExportFormat::parse("PDF"), Ok(ExportFormat::Pdf));
+        assert!(ExportFormat::parse("docx").is_err());
+    }
+
+    #[test]
+    fn test_file_url() {
+        assert_eq!(file_url(Path::new("/home/me/my notes/a#1.md")), "file:///home/me/my%20notes/a%231.md");
+        assert_eq!(file_url(Path::new("/tmp/café.html")), "file:///tmp/caf%C3%A9.html");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_file_url_windows() {
+        assert_eq!(file_url(Path::new(r"C:\Users\me\a.html")), "file:///C:/Users/me/a.html");
+        assert_eq!(file_url(Path::new(r"\\?\C:\notes\a.html")), "file:///C:/notes/a.html");
+        assert_eq!(file_url(Path::new(r"\\?\UNC\server\share\a.html")), "file://server/share/a.html");
+    }
+}
@@ -0,0 +1,504 @@
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+pub enum FileOpError {
+    // The destination is taken; retry with `force` to replace it
+    Exists(String),
+    NotFound(String),
+    InvalidPath(String),
+    Io(String),
+}
+
+impl fmt::Display for FileOpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileOpError::Exists(path) => write!(f, "{} already exists", path),
+            FileOpError::NotFound(path) => write!(f, "{} not found", path),
+            FileOpError::InvalidPath(msg) => write!(f, "invalid path: {}", msg),
+            FileOpError::Io(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for FileOpError {}
+
+fn io_error(path: &Path, e: io::Error) -> FileOpError {
+    match e.kind() {
+        ErrorKind::AlreadyExists => FileOpError::Exists(path.to_string_lossy().to_string()),
+        ErrorKind::NotFound => FileOpError::NotFound(path.to_string_lossy().to_string()),
+        _ => FileOpError::Io(format!("{}: {}", path.to_string_lossy(), e)),
+    }
+}
+
+fn exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+pub fn create_file(path: &Path) -> Result<(), FileOpError> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map(|_| ())
+        .map_err(|e| io_error(path, e))
+}
+
+pub fn create_folder(path: &Path) -> Result<(), FileOpError> {
+    fs::create_dir(path).map_err(|e| io_error(path, e))
+}
+
+// Renames or moves `from` to `to`. An existing destination is refused unless
+// `force` is set, in which case it goes to the trash first rather than being lost.
+pub fn rename(from: &Path, to: &Path, force: bool) -> Result<(), FileOpError> {
+
+    if !exists(from) {
+        return Err(FileOpError::NotFound(from.to_string_lossy().to_string()));
+    }
+    if to.starts_with(from) && to != from {
+        return Err(FileOpError::InvalidPath("cannot move a folder into itself".into()));
+    }
+
+    // Case-only renames on case-insensitive filesystems see the source as the destination
+    let same_file = match (from.canonicalize(), to.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    };
+    if exists(to) && !same_file {
+        if !force {
+            return Err(FileOpError::Exists(to.to_string_lossy().to_string()));
+        }
+        trash(to)?;
+    }
+
+    fs::rename(from, to).map_err(|e| io_error(from, e))
+
+}
+
+// Where `path` lands when moved into the directory `dir`, keeping its name
+pub fn destination_in(path: &Path, dir: &Path) -> Result<PathBuf, FileOpError> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| FileOpError::InvalidPath(path.to_string_lossy().to_string()))?;
+    Ok(dir.join(name))
+}
+
+// "note.md" becomes "note copy.md", then "note copy 2.md" and so on
+fn copy_name(path: &Path, n: usize) -> Option<PathBuf> {
+    let stem = path.file_stem()?.to_string_lossy();
+    let suffix = if n == 1 { " copy".to_string() } else { format!(" copy {}", n) };
+    let name = match path.extension().filter(|_| !path.is_dir()) {
+        Some(ext) => format!("{}{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}{}", path.file_name()?.to_string_lossy(), suffix),
+    };
+    Some(path.with_file_name(name))
+}
+
+// Copies `from` to the new path `to`. Symlinks are recreated rather than followed,
+// and a copy that fails part way is removed again.
+fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+    if exists(to) {
+        return Err(io::Error::from(ErrorKind::AlreadyExists));
+    }
+    let copied = copy_entry(from, to);
+    if copied.is_err() {
+        let _ = match fs::symlink_metadata(to) {
+            Ok(meta) if meta.is_dir() => fs::remove_dir_all(to),
+            Ok(_) => fs::remove_file(to),
+            Err(e) => Err(e),
+        };
+    }
+    copied
+}
+
+fn copy_entry(from: &Path, to: &Path) -> io::Result<()> {
+    let file_type = fs::symlink_metadata(from)?.file_type();
+    if file_type.is_symlink() {
+        copy_symlink(from, to)
+    } else if file_type.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_entry(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(windows)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    let target = fs::read_link(from)?;
+    if from.is_dir() {
+        std::os::windows::fs::symlink_dir(target, to)
+    } else {
+        std::os::windows::fs::symlink_file(target, to)
+    }
+}
+
+// Copies a file or folder next to itself under the first free "copy" name
+pub fn duplicate(path: &Path) -> Result<PathBuf, FileOpError> {
+
+    if !exists(path) {
+        return Err(FileOpError::NotFound(path.to_string_lossy().to_string()));
+    }
+
+    let mut n = 1;
+    let target = loop {
+        let candidate = copy_name(path, n).ok_or_else(|| FileOpError::InvalidPath(path.to_string_lossy().to_string()))?;
+        if !exists(&candidate) {
+            break candidate;
+        }
+        n += 1;
+    };
+
+    copy_recursive(path, &target).map_err(|e| io_error(&target, e))?;
+    Ok(target)
+
+}
+
+// Trash implementations need the full path, which a relative one is resolved to
+fn absolute(path: &Path) -> Result<PathBuf, FileOpError> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    Ok(std::env::current_dir().map_err(|e| io_error(path, e))?.join(path))
+}
+
+// The home trash of the freedesktop.org trash spec
+#[cfg(all(unix, not(target_os = "macos")))]
+fn home_trash() -> Option<PathBuf> {
+    std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+        .map(|data| data.join("Trash"))
+}
+
+// Paths in .trashinfo files are URL-escaped, keeping the slashes
+#[cfg(all(unix, not(target_os = "macos")))]
+fn escape_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    escaped
+}
+
+// Moves `path` into a freedesktop trash directory, writing the .trashinfo that lets
+// file managers restore it. The info file is created first with create_new, which
+// reserves the name in the trash as the spec asks.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn trash_into(path: &Path, trash_dir: &Path) -> Result<PathBuf, FileOpError> {
+
+    use chrono::Local;
+    use std::io::Write;
+
+    let path = absolute(path)?;
+    if !exists(&path) {
+        return Err(FileOpError::NotFound(path.to_string_lossy().to_string()));
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| FileOpError::InvalidPath(path.to_string_lossy().to_string()))?
+        .to_string_lossy()
+        .to_string();
+
+    let files = trash_dir.join("files");
+    let info = trash_dir.join("info");
+    fs::create_dir_all(&files).map_err(|e| io_error(&files, e))?;
+    fs::create_dir_all(&info).map_err(|e| io_error(&info, e))?;
+
+    let mut n = 1;
+    let (trashed, info_path, mut info_file) = loop {
+        let candidate = if n == 1 { name.clone() } else { format!("{}.{}", name, n) };
+        let info_path = info.join(format!("{}.trashinfo", candidate));
+        match OpenOptions::new().write(true).create_new(true).open(&info_path) {
+            Ok(file) if !exists(&files.join(&candidate)) => break (files.join(&candidate), info_path, file),
+            Ok(_) => {
+                let _ = fs::remove_file(&info_path);
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(io_error(&info_path, e)),
+        }
+        n += 1;
+    };
+
+    let written = write!(
+        info_file,
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        escape_path(&path),
+        Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+
+    // Another filesystem: copy over, then remove the original
+    let moved = written.and_then(|_| match fs::rename(&path, &trashed) {
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => copy_recursive(&path, &trashed).and_then(|_| {
+            if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) }
+        }),
+        other => other,
+    });
+
+    if let Err(e) = moved {
+        let _ = fs::remove_file(&info_path);
+        return Err(io_error(&path, e));
+    }
+
+    Ok(trashed)
+
+}
+
+// The top of the mount `path` is on: its highest ancestor on the same device
+#[cfg(all(unix, not(target_os = "macos")))]
+fn mount_point(path: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+    let dev = fs::symlink_metadata(path).ok()?.dev();
+    let mut top = path;
+    while let Some(parent) = top.parent() {
+        if fs::metadata(parent).ok()?.dev() != dev {
+            break;
+        }
+        top = parent;
+    }
+    Some(top.to_path_buf())
+}
+
+// The trash at the top of a mount, per the spec: $topdir/.Trash/$uid when a sticky,
+// non-symlink .Trash is set up there, else $topdir/.Trash-$uid. None when neither can be used.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn topdir_trash(topdir: &Path) -> Option<PathBuf> {
+
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    // Never fails, it only reads the process's uid
+    let uid = unsafe { libc::getuid() };
+    let usable = |dir: &Path| fs::symlink_metadata(dir).is_ok_and(|meta| meta.is_dir() && meta.uid() == uid);
+
+    let shared = topdir.join(".Trash");
+    if fs::symlink_metadata(&shared).is_ok_and(|meta| meta.is_dir() && meta.permissions().mode() & 0o1000 != 0) {
+        let dir = shared.join(uid.to_string());
+        let _ = fs::DirBuilder::new().mode(0o700).create(&dir);
+        if usable(&dir) {
+            return Some(dir);
+        }
+    }
+
+    let own = topdir.join(format!(".Trash-{}", uid));
+    let _ = fs::DirBuilder::new().mode(0o700).create(&own);
+    usable(&own).then_some(own)
+
+}
+
+// Files on the filesystem of the home trash go there. Others go to the trash at the top
+// of their own mount, so they are moved rather than copied over; copying into the home
+// trash is left for mounts without a usable one.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn trash(path: &Path) -> Result<(), FileOpError> {
+
+    use std::os::unix::fs::MetadataExt;
+
+    let path = absolute(path)?;
+    let home = home_trash();
+    let device = fs::symlink_metadata(&path).ok().map(|meta| meta.dev());
+    // The home trash may not exist yet; its nearest existing ancestor is on the same filesystem
+    let home_device = home.as_deref().and_then(|dir| dir.ancestors().find_map(|d| fs::metadata(d).ok())).map(|meta| meta.dev());
+
+    let trash_dir = if device.is_some() && device == home_device {
+        home
+    } else {
+        mount_point(&path).and_then(|top| topdir_trash(&top)).or(home)
+    };
+    let trash_dir = trash_dir.ok_or_else(|| FileOpError::Io("no trash directory found".into()))?;
+    trash_into(&path, &trash_dir).map(|_| ())
+
+}
+
+// Finder does the move, so the item gets the metadata its Put Back needs. The path
+// goes in as an argument rather than into the script, which needs no quoting.
+#[cfg(target_os = "macos")]
+pub fn trash(path: &Path) -> Result<(), FileOpError> {
+
+    let path = absolute(path)?;
+    if !exists(&path) {
+        return Err(FileOpError::NotFound(path.to_string_lossy().to_string()));
+    }
+
+    let output = std::process::Command::new("osascript")
+        .args(["-e", "on run argv", "-e", "tell application \"Finder\" to delete (POSIX file (item 1 of argv) as alias)", "-e", "end run"])
+        .arg(&path)
+        .output()
+        .map_err(|e| FileOpError::Io(format!("Failed to run osascript: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(FileOpError::Io(format!("{}: {}", path.to_string_lossy(), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(())
+
+}
+
+// Moves `path` to the Recycle Bin through the shell, which records where it came from
+// for Restore. The shell deletes relative paths for good, so the path is made absolute.
+#[cfg(windows)]
+pub fn trash(path: &Path) -> Result<(), FileOpError> {
+
+    let path = absolute(path)?;
+    if !exists(&path) {
+        return Err(FileOpError::NotFound(path.to_string_lossy().to_string()));
+    }
+
+    trash::delete(&path).map_err(|e| FileOpError::Io(format!("{}: could not move to the Recycle Bin ({})", path.to_string_lossy(), e)))
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_create_refuses_existing() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let path = temp_dir.path().join("new.md");
+
+        create_file(&path).expect("Failed to create file");
+        create_folder(&temp_dir.path().join("sub")).expect("Failed to create folder");
+
+        assert!(path.is_file());
+        assert!(matches!(create_file(&path), Err(FileOpError::Exists(_))));
+        assert!(matches!(create_folder(&temp_dir.path().join("sub")), Err(FileOpError::Exists(_))));
+    }
+
+    #[test]
+    fn test_rename_refuses_overwrite_unless_forced() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "a").unwrap();
+        fs::write(root.join("b.md"), "b").unwrap();
+
+        assert!(matches!(rename(&root.join("a.md"), &root.join("b.md"), false), Err(FileOpError::Exists(_))));
+        assert_eq!(fs::read_to_string(root.join("b.md")).unwrap(), "b");
+
+        rename(&root.join("a.md"), &root.join("c.md"), false).expect("Failed to rename");
+        assert!(!root.join("a.md").exists());
+        assert_eq!(fs::read_to_string(root.join("c.md")).unwrap(), "a");
+    }
+
+    #[test]
+    fn test_move_into_folder() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("note.md"), "n").unwrap();
+
+        let moved = destination_in(&root.join("note.md"), &root.join("dir")).unwrap();
+        rename(&root.join("note.md"), &moved, false).expect("Failed to move");
+
+        assert_eq!(moved, root.join("dir/note.md"));
+        assert!(moved.is_file());
+        let into_itself = destination_in(&root.join("dir"), &root.join("dir")).unwrap();
+        assert!(matches!(rename(&root.join("dir"), &into_itself, false), Err(FileOpError::InvalidPath(_))));
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("note.md"), "n").unwrap();
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("dir/inner.md"), "i").unwrap();
+
+        assert_eq!(duplicate(&root.join("note.md")).unwrap(), root.join("note copy.md"));
+        assert_eq!(duplicate(&root.join("note.md")).unwrap(), root.join("note copy 2.md"));
+        assert_eq!(duplicate(&root.join("dir")).unwrap(), root.join("dir copy"));
+        assert_eq!(fs::read_to_string(root.join("dir copy/inner.md")).unwrap(), "i");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_duplicate_recreates_symlinks() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("dir/target")).unwrap();
+        std::os::unix::fs::symlink("target", root.join("dir/link")).unwrap();
+
+        duplicate(&root.join("dir")).unwrap();
+
+        let link = root.join("dir copy/link");
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_link(&link).unwrap(), PathBuf::from("target"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_duplicate_removes_partial_copy() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("dir/a.md"), "a").unwrap();
+        // A socket cannot be copied like a file
+        let _socket = std::os::unix::net::UnixListener::bind(root.join("dir/b.sock")).unwrap();
+
+        assert!(duplicate(&root.join("dir")).is_err());
+        assert!(!exists(&root.join("dir copy")));
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn test_trash_writes_trashinfo() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let trash_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("my note.md"), "first").unwrap();
+
+        let trashed = trash_into(&root.join("my note.md"), trash_dir.path()).expect("Failed to trash");
+        fs::write(root.join("my note.md"), "second").unwrap();
+        let again = trash_into(&root.join("my note.md"), trash_dir.path()).expect("Failed to trash");
+
+        assert!(!root.join("my note.md").exists());
+        assert_eq!(trashed, trash_dir.path().join("files/my note.md"));
+        assert_eq!(again, trash_dir.path().join("files/my note.md.2"));
+        assert_eq!(fs::read_to_string(&again).unwrap(), "second");
+
+        let info = fs::read_to_string(trash_dir.path().join("info/my note.md.trashinfo")).unwrap();
+        assert!(info.starts_with("[Trash Info]\n"));
+        assert!(info.contains(&format!("Path={}/my%20note.md\n", escape_path(root))));
+        assert!(info.contains("DeletionDate="));
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn test_topdir_trash() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let uid = unsafe { libc::getuid() };
+        let own = TempDir::new().expect("Failed to create temp dir");
+        let shared = TempDir::new().expect("Failed to create temp dir");
+        fs::create_dir(shared.path().join(".Trash")).unwrap();
+        fs::set_permissions(shared.path().join(".Trash"), fs::Permissions::from_mode(0o1777)).unwrap();
+        let unsticky = TempDir::new().expect("Failed to create temp dir");
+        fs::create_dir(unsticky.path().join(".Trash")).unwrap();
+
+        assert_eq!(topdir_trash(own.path()), Some(own.path().join(format!(".Trash-{}", uid))));
+        assert_eq!(topdir_trash(shared.path()), Some(shared.path().join(".Trash").join(uid.to_string())));
+        assert_eq!(topdir_trash(unsticky.path()), Some(unsticky.path().join(format!(".Trash-{}", uid))));
+        assert!(own.path().join(format!(".Trash-{}", uid)).is_dir());
+    }
+
+    #[test]
+    fn test_error_serialization() {
+        let json = serde_json::to_string(&FileOpError::Exists("/a.md".into())).unwrap();
+        assert_eq!(json, "{\"kind\":\"exists\",\"detail\":\"/a.md\"}");
+    }
+}
@@ -0,0 +1,543 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::diff::{self, DiffOp};
+use crate::links::normalize;
+use crate::tree::FileNode;
+
+// How a path differs from HEAD. Directories holding changes show as modified.
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GitStatus {
+    Modified,
+    Added,
+    Deleted,
+    Renamed,
+    Untracked,
+    Ignored,
+    Conflicted,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct StatusEntry {
+    pub path: String,
+    // In the index and in the working tree, None when that side has no change
+    pub staged: Option<GitStatus>,
+    pub unstaged: Option<GitStatus>,
+    // Where a renamed or copied file came from
+    pub from: Option<String>,
+}
+
+#[derive(serde::Serialize, Clone, Debug, Default, PartialEq)]
+pub struct RepoStatus {
+    pub workdir: String,
+    // None on a detached HEAD
+    pub branch: Option<String>,
+    pub upstream: Option<String>,
+    pub ahead: usize,
+    pub behind: usize,
+    pub entries: Vec<StatusEntry>,
+}
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GutterKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+// Changed lines of the current text, 1-based and inclusive. Deleted lines have no
+// place left, so their mark sits after `start_line` (0 before the first line).
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct GutterMark {
+    pub kind: GutterKind,
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct Commit {
+    pub id: String,
+    pub author: String,
+    pub email: String,
+    // Seconds since the epoch
+    pub time: i64,
+    pub summary: String,
+}
+
+impl StatusEntry {
+
+    // The single status the sidebar shows
+    pub fn status(&self) -> GitStatus {
+        match (self.staged, self.unstaged) {
+            (Some(GitStatus::Added), _) => GitStatus::Added,
+            (staged, unstaged) => unstaged.or(staged).unwrap_or(GitStatus::Modified),
+        }
+    }
+
+}
+
+fn change(code: u8) -> Option<GitStatus> {
+    match code {
+        b'M' | b'T' => Some(GitStatus::Modified),
+        b'A' | b'C' => Some(GitStatus::Added),
+        b'D' => Some(GitStatus::Deleted),
+        b'R' => Some(GitStatus::Renamed),
+        _ => None,
+    }
+}
+
+// Parses `git status --porcelain=v1 -z --branch`, with paths made absolute under `workdir`
+fn parse_status(output: &str, workdir: &Path) -> RepoStatus {
+
+    let mut status = RepoStatus { workdir: workdir.to_string_lossy().to_string(), ..RepoStatus::default() };
+    let absolute = |path: &str| workdir.join(path.trim_end_matches('/')).to_string_lossy().to_string();
+    let mut records = output.split('\0').filter(|record| !record.is_empty());
+
+    while let Some(record) = records.next() {
+
+        if let Some(header) = record.strip_prefix("## ") {
+            parse_branch(header, &mut status);
+            continue;
+        }
+        if record.len() < 4 {
+            continue;
+        }
+
+        let (code, path) = (record.as_bytes(), &record[3..]);
+        let (x, y) = (code[0], code[1]);
+        let (staged, unstaged) = match (x, y) {
+            (b'?', b'?') => (None, Some(GitStatus::Untracked)),
+            (b'!', b'!') => (None, Some(GitStatus::Ignored)),
+            (b'U', _) | (_, b'U') | (b'A', b'A') | (b'D', b'D') => (Some(GitStatus::Conflicted), Some(GitStatus::Conflicted)),
+            (x, y) => (change(x), change(y)),
+        };
+        // The source of a rename or copy comes as the next record
+        let from = if matches!(x, b'R' | b'C') || matches!(y, b'R' | b'C') { records.next().map(absolute) } else { None };
+        status.entries.push(StatusEntry { path: absolute(path), staged, unstaged, from });
+
+    }
+
+    status
+
+}
+
+// "main...origin/main [ahead 1, behind 2]", "No commits yet on main" or "HEAD (no branch)"
+fn parse_branch(header: &str, status: &mut RepoStatus) {
+
+    if let Some(branch) = header.strip_prefix("No commits yet on ").or_else(|| header.strip_prefix("Initial commit on ")) {
+        status.branch = Some(branch.to_string());
+        return;
+    }
+    if header.starts_with("HEAD (no branch)") {
+        return;
+    }
+
+    let (names, tracking) = header.split_once(" [").unwrap_or((header, ""));
+    let (branch, upstream) = match names.split_once("...") {
+        Some((branch, upstream)) => (branch, Some(upstream.to_string())),
+        None => (names, None),
+    };
+    status.branch = Some(branch.to_string());
+    status.upstream = upstream;
+    for part in tracking.trim_end_matches(']').split(", ") {
+        if let Some(n) = part.strip_prefix("ahead ") {
+            status.ahead = n.parse().unwrap_or(0);
+        } else if let Some(n) = part.strip_prefix("behind ") {
+            status.behind = n.parse().unwrap_or(0);
+        }
+    }
+
+}
+
+// What the sidebar shows for each path of a repository, from one status
+#[derive(Default)]
+pub struct Annotations {
+    paths: HashMap<PathBuf, GitStatus>,
+    // Directories with a changed path somewhere below them
+    changed_dirs: HashSet<PathBuf>,
+    // Ignored files and directories; everything below an ignored directory is ignored
+    ignored: HashSet<PathBuf>,
+}
+
+impl Annotations {
+
+    pub fn new(status: &RepoStatus) -> Self {
+
+        let workdir = Path::new(&status.workdir);
+        let mut annotations = Annotations::default();
+        for entry in &status.entries {
+            let path = PathBuf::from(&entry.path);
+            if entry.unstaged == Some(GitStatus::Ignored) {
+                annotations.ignored.insert(path);
+                continue;
+            }
+            for dir in path.ancestors().skip(1).take_while(|dir| dir.starts_with(workdir)) {
+                annotations.changed_dirs.insert(dir.to_path_buf());
+            }
+            annotations.paths.insert(path, entry.status());
+        }
+        annotations
+
+    }
+
+    pub fn status_of(&self, path: &Path, is_dir: bool) -> Option<GitStatus> {
+        if let Some(status) = self.paths.get(path) {
+            return Some(*status);
+        }
+        if path.ancestors().any(|p| self.ignored.contains(p)) {
+            return Some(GitStatus::Ignored);
+        }
+        (is_dir && self.changed_dirs.contains(path)).then_some(GitStatus::Modified)
+    }
+
+}
+
+// Sets the git status of the nodes and everything loaded below them
+pub fn annotate(nodes: &mut [FileNode], annotations: &Annotations) {
+    for node in nodes {
+        node.git_status = annotations.status_of(Path::new(&node.path), node.is_dir);
+        if let Some(children) = node.children.as_mut() {
+            annotate(children, annotations);
+        }
+    }
+}
+
+// Marks for the lines of `current` that differ from `head`
+pub fn gutter(head: &str, current: &str) -> Vec<GutterMark> {
+
+    let mut marks = Vec::new();
+    // Last line of `current` passed, and the run of changes since the last unchanged line
+    let mut after = 0;
+    let mut deleted = false;
+    let mut inserted: Option<(usize, usize)> = None;
+
+    let mut flush = |after: usize, deleted: &mut bool, inserted: &mut Option<(usize, usize)>| {
+        match inserted.take() {
+            Some((start, end)) => {
+                let kind = if *deleted { GutterKind::Modified } else { GutterKind::Added };
+                marks.push(GutterMark { kind, start_line: start, end_line: end });
+            }
+            None if *deleted => marks.push(GutterMark { kind: GutterKind::Deleted, start_line: after, end_line: after }),
+            None => {}
+        }
+        *deleted = false;
+    };
+
+    for line in diff::diff_lines(head, current) {
+        match line.op {
+            DiffOp::Equal => {
+                flush(after, &mut deleted, &mut inserted);
+                after = line.new_line.unwrap_or(after);
+            }
+            DiffOp::Delete => deleted = true,
+            DiffOp::Insert => {
+                let number = line.new_line.unwrap_or(after);
+                inserted = Some(inserted.map_or((number, number), |(start, _)| (start, number)));
+            }
+        }
+    }
+    flush(after, &mut deleted, &mut inserted);
+
+    marks
+
+}
+
+fn parse_log(output: &str) -> Vec<Commit> {
+    output
+        .split('\x1e')
+        .filter_map(|record| {
+            let fields: Vec<&str> = record.trim_start_matches('\n').split('\x1f').collect();
+            match fields.as_slice() {
+                [id, author, email, time, summary] => Some(Commit {
+                    id: id.to_string(),
+                    author: author.to_string(),
+                    email: email.to_string(),
+                    time: time.parse().unwrap_or(0),
+                    summary: summary.to_string(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// Runs git in `dir` and returns its output, or its error message when it fails
+fn run(dir: &Path, args: &[&str], input: Option<&str>) -> Result<String, String> {
+
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        // Paths are file names, never patterns, and nothing may wait for a password
+        .env("GIT_LITERAL_PATHSPECS", "1")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_OPTIONAL_LOCKS", "0")
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => "git is not installed or not on the PATH".to_string(),
+            _ => format!("Failed to run git: {}", e),
+        })?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes()).map_err(|e| e.to_string())?;
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+
+}
+
+// A working tree found through the git command line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Repo {
+    pub workdir: PathBuf,
+}
+
+impl Repo {
+
+    // The repository `path` is in, if any. The working tree path keeps the form of
+    // `path` rather than git's resolved one, so it matches the paths of the sidebar.
+    pub fn discover(path: &Path) -> Option<Repo> {
+        let dir = if path.is_dir() { path } else { path.parent()? };
+        let up = run(dir, &["rev-parse", "--show-cdup"], None).ok()?;
+        Some(Repo { workdir: normalize(&dir.join(up.trim())) })
+    }
+
+    fn relative<'a>(&self, path: &'a Path) -> Result<&'a str, String> {
+        path.strip_prefix(&self.workdir)
+            .ok()
+            .and_then(|rel| rel.to_str())
+            .ok_or_else(|| format!("{} is not in the repository at {}", path.display(), self.workdir.display()))
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String, String> {
+        run(&self.workdir, args, None)
+    }
+
+    fn has_head(&self) -> bool {
+        self.git(&["rev-parse", "--verify", "--quiet", "HEAD"]).is_ok()
+    }
+
+    // The directory holding HEAD and the index, which git commands run elsewhere
+    // change; for a linked worktree that is its own directory under the main one
+    pub fn git_dir(&self) -> Result<PathBuf, String> {
+        Ok(PathBuf::from(self.git(&["rev-parse", "--absolute-git-dir"])?.trim()))
+    }
+
+    pub fn status(&self) -> Result<RepoStatus, String> {
+        let output = self.git(&["status", "--porcelain=v1", "-z", "--branch", "--untracked-files=all", "--ignored=matching"])?;
+        Ok(parse_status(&output, &self.workdir))
+    }
+
+    // Changed lines of a file against HEAD, using `content` (unsaved text) when given.
+    // A file new since HEAD is all added; other failures to read it from HEAD are errors.
+    pub fn gutter(&self, path: &Path, content: Option<&str>) -> Result<Vec<GutterMark>, String> {
+        let rel = self.relative(path)?.replace('\\', "/");
+        let head = match self.git(&["show", &format!("HEAD:{}", rel)]) {
+            Ok(head) => head,
+            Err(_) if !self.has_head() || self.git(&["ls-tree", "--name-only", "HEAD", "--", &rel])?.is_empty() => String::new(),
+            Err(e) => return Err(e),
+        };
+        let current = match content {
+            Some(content) => content.to_string(),
+            None => std::fs::read_to_string(path).unwrap_or_default(),
+        };
+        Ok(gutter(&head, &current))
+    }
+
+    pub fn stage(&self, paths: &[PathBuf]) -> Result<(), String> {
+        let rels = paths.iter().map(|p| self.relative(p)).collect::<Result<Vec<_>, _>>()?;
+        self.git(&[&["add", "-A", "--"], rels.as_slice()].concat()).map(|_| ())
+    }
+
+    pub fn unstage(&self, paths: &[PathBuf]) -> Result<(), String> {
+        let rels = paths.iter().map(|p| self.relative(p)).collect::<Result<Vec<_>, _>>()?;
+        // Before the first commit there is no HEAD to reset to
+        let args: &[&str] = if self.has_head() { &["reset", "-q", "--"] } else { &["rm", "-r", "-q", "--cached", "--"] };
+        self.git(&[args, rels.as_slice()].concat()).map(|_| ())
+    }
+
+    // Commits what is staged and returns the new commit id
+    pub fn commit(&self, message: &str) -> Result<String, String> {
+        if message.trim().is_empty() {
+            return Err("Commit message is empty".into());
+        }
+        run(&self.workdir, &["commit", "-q", "-F", "-"], Some(message))?;
+        Ok(self.git(&["rev-parse", "HEAD"])?.trim().to_string())
+    }
+
+    // Commits that touched a file, newest first, following renames
+    pub fn log(&self, path: &Path, max_count: usize) -> Result<Vec<Commit>, String> {
+        let rel = self.relative(path)?;
+        if !self.has_head() {
+            return Ok(Vec::new());
+        }
+        let count = format!("-n{}", max_count);
+        let output = self.git(&["log", "--follow", &count, "--format=%H%x1f%an%x1f%ae%x1f%at%x1f%s%x1e", "--", rel])?;
+        Ok(parse_log(&output))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn init(dir: &Path) -> Repo {
+        for args in [
+            &["init", "-q"][..],
+            &["config", "user.name", "Test"],
+            &["config", "user.email", "test@example.com"],
+            &["config", "commit.gpgsign", "false"],
+        ] {
+            run(dir, args, None).expect("Failed to set up repository");
+        }
+        Repo::discover(dir).expect("Repository not found")
+    }
+
+    fn summary(status: &RepoStatus, workdir: &Path) -> Vec<(String, Option<GitStatus>, Option<GitStatus>)> {
+        let mut entries: Vec<_> = status
+            .entries
+            .iter()
+            .map(|e| (Path::new(&e.path).strip_prefix(workdir).unwrap().to_string_lossy().to_string(), e.staged, e.unstaged))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let output = "## main...origin/main [ahead 2, behind 1]\0 M a.md\0R  new.md\0old.md\0?? notes/draft.md\0!! build/\0UU both.md\0";
+        let status = parse_status(output, Path::new("/repo"));
+
+        assert_eq!(status.branch.as_deref(), Some("main"));
+        assert_eq!(status.upstream.as_deref(), Some("origin/main"));
+        assert_eq!((status.ahead, status.behind), (2, 1));
+        assert_eq!(status.entries.len(), 5);
+        assert_eq!(status.entries[0], StatusEntry { path: "/repo/a.md".into(), staged: None, unstaged: Some(GitStatus::Modified), from: None });
+        assert_eq!(status.entries[1].from.as_deref(), Some("/repo/old.md"));
+        assert_eq!(status.entries[1].status(), GitStatus::Renamed);
+        assert_eq!(status.entries[3].path, "/repo/build");
+        assert_eq!(status.entries[4].status(), GitStatus::Conflicted);
+
+        let detached = parse_status("## HEAD (no branch)\0", Path::new("/repo"));
+        assert_eq!(detached.branch, None);
+        let unborn = parse_status("## No commits yet on main\0", Path::new("/repo"));
+        assert_eq!(unborn.branch.as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn test_gutter() {
+        let marks = gutter("a\nb\nc\nd\n", "a\nB\nc\nd\ne\n");
+        assert_eq!(
+            marks,
+            vec![
+                GutterMark { kind: GutterKind::Modified, start_line: 2, end_line: 2 },
+                GutterMark { kind: GutterKind::Added, start_line: 5, end_line: 5 },
+            ]
+        );
+        assert_eq!(gutter("a\nb\nc\n", "a\nc\n"), vec![GutterMark { kind: GutterKind::Deleted, start_line: 1, end_line: 1 }]);
+        assert_eq!(gutter("a\nb\n", "b\n"), vec![GutterMark { kind: GutterKind::Deleted, start_line: 0, end_line: 0 }]);
+        assert_eq!(gutter("", "new\nfile\n"), vec![GutterMark { kind: GutterKind::Added, start_line: 1, end_line: 2 }]);
+        assert!(gutter("same\n", "same\n").is_empty());
+    }
+
+    #[test]
+    fn test_status_stage_commit_and_log() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let dir = temp_dir.path();
+        let repo = init(dir);
+        fs::write(dir.join(".gitignore"), "build/\n").unwrap();
+        fs::create_dir_all(dir.join("build")).unwrap();
+        fs::write(dir.join("build/out.html"), "").unwrap();
+        fs::write(dir.join("note.md"), "one\ntwo\n").unwrap();
+
+        assert!(repo.log(&dir.join("note.md"), 10).unwrap().is_empty());
+        repo.stage(&[dir.join("note.md"), dir.join(".gitignore")]).unwrap();
+        assert_eq!(
+            summary(&repo.status().unwrap(), dir),
+            vec![
+                (".gitignore".into(), Some(GitStatus::Added), None),
+                ("build".into(), None, Some(GitStatus::Ignored)),
+                ("note.md".into(), Some(GitStatus::Added), None),
+            ]
+        );
+
+        repo.unstage(&[dir.join(".gitignore")]).unwrap();
+        let id = repo.commit("Add note").unwrap();
+        assert_eq!(id.len(), 40);
+        assert!(repo.commit("  ").is_err());
+
+        fs::write(dir.join("note.md"), "one\n2\n").unwrap();
+        fs::write(dir.join("new.md"), "").unwrap();
+        let status = repo.status().unwrap();
+        assert_eq!(
+            summary(&status, dir),
+            vec![
+                (".gitignore".into(), None, Some(GitStatus::Untracked)),
+                ("build".into(), None, Some(GitStatus::Ignored)),
+                ("new.md".into(), None, Some(GitStatus::Untracked)),
+                ("note.md".into(), None, Some(GitStatus::Modified)),
+            ]
+        );
+        assert!(status.branch.is_some());
+
+        assert_eq!(repo.gutter(&dir.join("note.md"), None).unwrap(), vec![GutterMark { kind: GutterKind::Modified, start_line: 2, end_line: 2 }]);
+        assert!(repo.gutter(&dir.join("note.md"), Some("one\ntwo\n")).unwrap().is_empty());
+        assert_eq!(repo.gutter(&dir.join("new.md"), Some("x\n")).unwrap(), vec![GutterMark { kind: GutterKind::Added, start_line: 1, end_line: 1 }]);
+
+        let log = repo.log(&dir.join("note.md"), 10).unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!((log[0].id.as_str(), log[0].summary.as_str(), log[0].author.as_str()), (id.as_str(), "Add note", "Test"));
+        assert!(repo.stage(&[PathBuf::from("/elsewhere/x.md")]).is_err());
+
+        // A file in HEAD whose content git cannot read is an error, not all added
+        let blob = repo.git(&["rev-parse", "HEAD:note.md"]).unwrap();
+        let blob = blob.trim();
+        fs::remove_file(repo.git_dir().unwrap().join("objects").join(&blob[..2]).join(&blob[2..])).unwrap();
+        assert!(repo.gutter(&dir.join("note.md"), None).is_err());
+    }
+
+    #[test]
+    fn test_annotate() {
+        let status = parse_status(" M notes/a.md\0?? b.md\0!! build/\0", Path::new("/repo"));
+        let annotations = Annotations::new(&status);
+        let node = |path: &str, is_dir: bool, children: Option<Vec<FileNode>>| FileNode {
+            name: String::new(),
+            path: path.into(),
+            is_dir,
+            children,
+            has_children: false,
+            is_symlink: false,
+            link_target: None,
+            meta: None,
+            git_status: None,
+        };
+
+        let mut nodes = vec![
+            node("/repo/notes", true, Some(vec![node("/repo/notes/a.md", false, None), node("/repo/notes/c.md", false, None)])),
+            node("/repo/b.md", false, None),
+            node("/repo/build", true, Some(vec![node("/repo/build/out.html", false, None)])),
+            node("/repo/clean", true, None),
+        ];
+        annotate(&mut nodes, &annotations);
+
+        let children = nodes[0].children.as_ref().unwrap();
+        assert_eq!(nodes[0].git_status, Some(GitStatus::Modified));
+        assert_eq!((children[0].git_status, children[1].git_status), (Some(GitStatus::Modified), None));
+        assert_eq!(nodes[1].git_status, Some(GitStatus::Untracked));
+        assert_eq!(nodes[2].children.as_ref().unwrap()[0].git_status, Some(GitStatus::Ignored));
+        assert_eq!(nodes[3].git_status, None);
+    }
+}
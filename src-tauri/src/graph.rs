@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::links::{is_external, parse, Link, LinkKind, Notes};
+use crate::search::read_text;
+use crate::tree::{list_files, TreeOptions};
+use crate::watch::{DeltaKind, PathDelta};
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct Edge {
+    pub source: String,
+    // None for a wikilink naming no note
+    pub target: Option<String>,
+    pub kind: LinkKind,
+    // The destination as written in the source
+    pub text: String,
+    pub anchor: Option<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+// Whether a file is a note whose links the graph follows, rather than an image, a PDF
+// or another file the workspace shows
+pub fn is_note(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ["md", "markdown", "mdx"].iter().any(|note| ext.eq_ignore_ascii_case(note)))
+}
+
+// Links between the notes of a workspace. Each note's links are stored as parsed
+// and resolved when asked for, since a wikilink's target changes as notes come and go.
+pub struct LinkGraph {
+    root: PathBuf,
+    // Every file the workspace shows, which links may point to
+    files: HashSet<PathBuf>,
+    links: HashMap<PathBuf, Vec<Link>>,
+    // Link resolution and edges by target, rebuilt on demand after files changed
+    notes: Option<Notes>,
+    inbound: Option<HashMap<PathBuf, Vec<Edge>>>,
+}
+
+impl LinkGraph {
+
+    pub fn build(root: &Path, files: &[PathBuf]) -> Self {
+        let mut graph = LinkGraph {
+            root: root.to_path_buf(),
+            files: HashSet::new(),
+            links: HashMap::new(),
+            notes: None,
+            inbound: None,
+        };
+        for file in files {
+            graph.add_file(file);
+        }
+        graph
+    }
+
+    fn add_file(&mut self, path: &Path) {
+        self.files.insert(path.to_path_buf());
+        let links = if is_note(path) { read_text(path).map(|text| parse(&text)) } else { None };
+        match links {
+            Some(links) => {
+                self.links.insert(path.to_path_buf(), links);
+            }
+            None => {
+                self.links.remove(path);
+            }
+        }
+        self.notes = None;
+        self.inbound = None;
+    }
+
+    // Applies a batch of watcher deltas, walking created or renamed directories
+    pub fn apply(&mut self, deltas: &[PathDelta], options: &TreeOptions) {
+        for delta in deltas {
+            let path = Path::new(&delta.path);
+            if delta.kind == DeltaKind::Removed || !path.exists() {
+                self.files.retain(|file| !file.starts_with(path));
+                self.links.retain(|note, _| !note.starts_with(path));
+                self.notes = None;
+                self.inbound = None;
+            }
+            else if path.is_dir() {
+                for file in list_files(path, options) {
+                    self.add_file(&file);
+                }
+            }
+            else if options.is_relevant(path, delta.kind) {
+                self.add_file(path);
+            }
+        }
+    }
+
+    fn notes(&mut self) -> &Notes {
+        if self.notes.is_none() {
+            let files: Vec<PathBuf> = self.files.iter().cloned().collect();
+            self.notes = Some(Notes::new(&self.root, &files));
+        }
+        self.notes.as_ref().expect("Notes were just built")
+    }
+
+    // Links to other files, in order; external and same-note anchor links are left out
+    fn edges_of(&self, notes: &Notes, source: &Path) -> Vec<Edge> {
+        self.links
+            .get(source)
+            .map(|links| {
+                links
+                    .iter()
+                    .filter(|link| !link.target.trim().is_empty() && !is_external(&link.target))
+                    .map(|link| Edge {
+                        source: source.to_string_lossy().to_string(),
+                        target: notes.resolve(source, link).map(|t| t.to_string_lossy().to_string()),
+                        kind: link.kind,
+                        text: link.target.clone(),
+                        anchor: link.anchor.clone(),
+                        line: link.line,
+                        column: link.column,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn inbound(&mut self) -> &HashMap<PathBuf, Vec<Edge>> {
+        if self.inbound.is_none() {
+            self.notes();
+            let notes = self.notes.as_ref().expect("Notes were just built");
+            let mut sources: Vec<&PathBuf> = self.links.keys().collect();
+            sources.sort();
+            let mut inbound: HashMap<PathBuf, Vec<Edge>> = HashMap::new();
+            for source in sources {
+                for edge in self.edges_of(notes, source) {
+                    if let Some(target) = &edge.target {
+                        inbound.entry(PathBuf::from(target)).or_default().push(edge);
+                    }
+                }
+            }
+            self.inbound = Some(inbound);
+        }
+        self.inbound.as_ref().expect("Inbound edges were just built")
+    }
+
+    pub fn outgoing(&mut self, path: &Path) -> Vec<Edge> {
+        self.notes();
+        self.edges_of(self.notes.as_ref().expect("Notes were just built"), path)
+    }
+
+    // Links from other notes to `path`
+    pub fn backlinks(&mut self, path: &Path) -> Vec<Edge> {
+        self.inbound()
+            .get(path)
+            .map(|edges| edges.iter().filter(|e| Path::new(&e.source) != path).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Notes no other note links to
+    pub fn orphans(&mut self) -> Vec<String> {
+        let linked: HashSet<PathBuf> = self
+            .inbound()
+            .iter()
+            .filter(|(target, edges)| edges.iter().any(|e| Path::new(&e.source) != target.as_path()))
+            .map(|(target, _)| target.clone())
+            .collect();
+        let mut orphans: Vec<String> = self
+            .links
+            .keys()
+            .filter(|note| !linked.contains(*note))
+            .map(|note| note.to_string_lossy().to_string())
+            .collect();
+        orphans.sort();
+        orphans
+    }
+
+    // Links whose target does not exist, from one note or from all of them
+    pub fn broken(&mut self, path: Option<&Path>) -> Vec<Edge> {
+
+        self.notes();
+        let notes = self.notes.as_ref().expect("Notes were just built");
+        let mut sources: Vec<&PathBuf> = match path {
+            Some(path) => self.links.keys().filter(|k| k.as_path() == path).collect(),
+            None => self.links.keys().collect(),
+        };
+        sources.sort();
+
+        sources
+            .into_iter()
+            .flat_map(|source| self.edges_of(notes, source))
+            .filter(|edge| match &edge.target {
+                Some(target) => !self.files.contains(Path::new(target)) && !Path::new(target).exists(),
+                None => true,
+            })
+            .collect()
+
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn workspace(files: &[(&str, &str)]) -> (TempDir, LinkGraph) {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        for (name, content) in files {
+            let path = root.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+        }
+        let graph = LinkGraph::build(root, &list_files(root, &TreeOptions::default_for(root)));
+        (temp_dir, graph)
+    }
+
+    #[test]
+    fn test_outgoing_and_backlinks() {
+        let (dir, mut graph) = workspace(&[
+            ("a.md", "[b](b.md) [[c]] [web](https://example.com) [top](#top)"),
+            ("b.md", "back to [[a]]"),
+            ("sub/c.md", "[a](../a.md#intro)"),
+        ]);
+        let root = dir.path();
+
+        let outgoing = graph.outgoing(&root.join("a.md"));
+        assert_eq!(outgoing.len(), 2);
+        assert_eq!(outgoing[1].target, Some(root.join("sub/c.md").to_string_lossy().to_string()));
+
+        let backlinks = graph.backlinks(&root.join("a.md"));
+        let sources: Vec<&str> = backlinks.iter().map(|e| e.source.as_str()).collect();
+        assert_eq!(sources, vec![root.join("b.md").to_str().unwrap(), root.join("sub/c.md").to_str().unwrap()]);
+        assert_eq!(backlinks[1].anchor.as_deref(), Some("intro"));
+    }
+
+    #[test]
+    fn test_orphans_and_broken() {
+        let (dir, mut graph) = workspace(&[
+            ("a.md", "[[b]] [gone](gone.md) [[nowhere]] [self](a.md)"),
+            ("b.md", ""),
+            ("lonely.md", "![pic](pic.png)"),
+        ]);
+        let root = dir.path();
+        fs::write(root.join("pic.png"), "").unwrap();
+
+        assert_eq!(graph.orphans(), vec![root.join("a.md").to_string_lossy().to_string(), root.join("lonely.md").to_string_lossy().to_string()]);
+
+        let broken: Vec<String> = graph.broken(None).into_iter().map(|e| e.text).collect();
+        assert_eq!(broken, vec!["gone.md".to_string(), "nowhere".to_string()]);
+        assert!(graph.broken(Some(&root.join("lonely.md"))).is_empty());
+    }
+
+    #[test]
+    fn test_only_notes_are_parsed() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "[doc](doc.pdf) [[pic.png]]").unwrap();
+        fs::write(root.join("doc.pdf"), b"%PDF-1.4 [[gone]] [x](missing.md)\0\xff").unwrap();
+        fs::write(root.join("pic.png"), "[[gone]]").unwrap();
+        let files = vec![root.join("a.md"), root.join("doc.pdf"), root.join("pic.png")];
+        let mut graph = LinkGraph::build(root, &files);
+
+        assert_eq!(graph.orphans(), vec![root.join("a.md").to_string_lossy().to_string()]);
+        assert!(graph.broken(None).is_empty());
+        assert_eq!(graph.backlinks(&root.join("pic.png")).len(), 1);
+        assert!(graph.outgoing(&root.join("doc.pdf")).is_empty());
+    }
+
+    #[test]
+    fn test_apply_watcher_deltas() {
+        let (dir, mut graph) = workspace(&[("a.md", "[[later]]"), ("b.md", "[[a]]")]);
+        let root = dir.path();
+        let options = TreeOptions::default_for(root);
+        assert_eq!(graph.broken(None).len(), 1);
+
+        fs::write(root.join("later.md"), "").unwrap();
+        fs::remove_file(root.join("b.md")).unwrap();
+        graph.apply(
+            &[
+                PathDelta { path: root.join("later.md").to_string_lossy().to_string(), kind: DeltaKind::Created, renamed_from: None },
+                PathDelta { path: root.join("b.md").to_string_lossy().to_string(), kind: DeltaKind::Removed, renamed_from: None },
+            ],
+            &options,
+        );
+
+        assert!(graph.broken(None).is_empty());
+        assert!(graph.backlinks(&root.join("a.md")).is_empty());
+        assert_eq!(graph.backlinks(&root.join("later.md")).len(), 1);
+    }
+}
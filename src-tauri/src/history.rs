@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::diff::{self, DiffHunk};
+use crate::save::{content_hash, write_atomic};
+
+// Lines of context around each change in version diffs
+const DIFF_CONTEXT: usize = 3;
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+// How much history is kept. Versions beyond any limit are dropped oldest first;
+// the size limit counts each stored content once, however many versions share it.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Retention {
+    // Whether saves keep versions at all
+    pub enabled: bool,
+    // Per file
+    pub max_versions: usize,
+    pub max_age_days: u64,
+    // For the whole store
+    pub max_total_bytes: u64,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            enabled: true,
+            max_versions: 50,
+            max_age_days: 90,
+            max_total_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct Version {
+    // Hash of the content, which is stored once under objects/
+    pub id: String,
+    pub saved_ms: u64,
+    pub size: u64,
+}
+
+// The versions of one file, oldest first
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+struct FileHistory {
+    path: String,
+    versions: Vec<Version>,
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+fn object_path(store: &Path, id: &str) -> PathBuf {
+    store.join("objects").join(&id[..2]).join(&id[2..])
+}
+
+// One file per path, named by its hash
+fn history_path(store: &Path, path: &Path) -> PathBuf {
+    store.join("files").join(format!("{}.json", &content_hash(path.to_string_lossy().as_bytes())[..16]))
+}
+
+fn load(store: &Path, path: &Path) -> FileHistory {
+    fs::read(history_path(store, path))
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_else(|| FileHistory { path: path.to_string_lossy().to_string(), versions: Vec::new() })
+}
+
+fn store_history(store: &Path, path: &Path, history: &FileHistory) -> Result<(), String> {
+    let file = history_path(store, path);
+    if history.versions.is_empty() {
+        let _ = fs::remove_file(file);
+        return Ok(());
+    }
+    fs::create_dir_all(store.join("files")).map_err(|e| e.to_string())?;
+    let json = serde_json::to_string(history).map_err(|e| e.to_string())?;
+    write_atomic(&file, json.as_bytes()).map_err(|e| e.to_string())
+}
+
+fn all_histories(store: &Path) -> Vec<FileHistory> {
+    let entries = match fs::read_dir(store.join("files")) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| fs::read(entry.path()).ok())
+        .filter_map(|json| serde_json::from_slice(&json).ok())
+        .collect()
+}
+
+// Size and use count of every stored content, so saving checks the size limit and
+// finds unused contents without reading every history. Built once from the store
+// and kept up to date by `record`; contents whose count dropped to zero stay until
+// `take_garbage` hands them out for removal.
+#[derive(Default, Debug)]
+pub struct Ledger {
+    objects: HashMap<String, (u64, usize)>,
+    total_bytes: u64,
+    // No version is older than this; dropping versions leaves it until `expire` looks
+    oldest_ms: Option<u64>,
+}
+
+impl Ledger {
+
+    pub fn build(store: &Path) -> Self {
+
+        let mut ledger = Ledger::default();
+        for history in all_histories(store) {
+            for version in &history.versions {
+                ledger.add(version);
+            }
+        }
+
+        // Contents left behind by an interrupted run are collected with the rest
+        for dir in fs::read_dir(store.join("objects")).into_iter().flatten().flatten() {
+            let prefix = dir.file_name().to_string_lossy().to_string();
+            for object in fs::read_dir(dir.path()).into_iter().flatten().flatten() {
+                let id = format!("{}{}", prefix, object.file_name().to_string_lossy());
+                let size = object.metadata().map_or(0, |meta| meta.len());
+                ledger.objects.entry(id).or_insert((size, 0));
+            }
+        }
+
+        ledger
+
+    }
+
+    fn add(&mut self, version: &Version) {
+        self.oldest_ms = Some(self.oldest_ms.map_or(version.saved_ms, |oldest| oldest.min(version.saved_ms)));
+        let (size, refs) = self.objects.entry(version.id.clone()).or_insert((version.size, 0));
+        if *refs == 0 {
+            self.total_bytes += *size;
+        }
+        *refs += 1;
+    }
+
+    fn release(&mut self, version: &Version) {
+        if let Some((size, refs)) = self.objects.get_mut(&version.id) {
+            if *refs == 1 {
+                self.total_bytes -= *size;
+            }
+            *refs = refs.saturating_sub(1);
+        }
+    }
+
+    pub fn has_garbage(&self) -> bool {
+        self.objects.values().any(|(_, refs)| *refs == 0)
+    }
+
+    // Contents no version uses anymore, forgotten here; the caller removes them
+    pub fn take_garbage(&mut self) -> Vec<String> {
+        let garbage: Vec<String> = self.objects.iter().filter(|(_, (_, refs))| *refs == 0).map(|(id, _)| id.clone()).collect();
+        for id in &garbage {
+            self.objects.remove(id);
+        }
+        garbage
+    }
+
+}
+
+// Deletes contents handed out by `Ledger::take_garbage`
+pub fn remove_objects(store: &Path, ids: &[String]) {
+    for id in ids {
+        let _ = fs::remove_file(object_path(store, id));
+    }
+}
+
+// Keeps `content` as a version of `path`, unless it is the same as the latest one,
+// then applies the retention limits to the whole store. Unused contents are left for `remove_objects`.
+pub fn record(store: &Path, ledger: &mut Ledger, path: &Path, content: &[u8], retention: &Retention) -> Result<Option<Version>, String> {
+
+    let id = content_hash(content);
+    let mut history = load(store, path);
+    if history.versions.last().is_some_and(|latest| latest.id == id) {
+        return Ok(None);
+    }
+
+    let object = object_path(store, &id);
+    if !object.exists() {
+        fs::create_dir_all(object.parent().unwrap()).map_err(|e| e.to_string())?;
+        write_atomic(&object, content).map_err(|e| e.to_string())?;
+    }
+
+    let version = Version { id, saved_ms: now_ms(), size: content.len() as u64 };
+    history.versions.push(version.clone());
+    ledger.add(&version);
+    prune_file(&mut history, ledger, retention);
+    store_history(store, path, &history)?;
+    expire(store, ledger, retention, version.saved_ms)?;
+    fit_size(store, ledger, retention)?;
+
+    Ok(Some(version))
+
+}
+
+// Drops the versions of one file that are over the count limit
+fn prune_file(history: &mut FileHistory, ledger: &mut Ledger, retention: &Retention) {
+    let over = history.versions.len().saturating_sub(retention.max_versions);
+    for version in history.versions.drain(..over) {
+        ledger.release(&version);
+    }
+}
+
+// Drops the versions of every file that are over the age limit, whether the file is
+// saved again or not. Only reads the histories when the ledger's oldest version is due.
+pub fn expire(store: &Path, ledger: &mut Ledger, retention: &Retention, now: u64) -> Result<(), String> {
+
+    let oldest_kept = now.saturating_sub(retention.max_age_days.saturating_mul(DAY_MS));
+    if ledger.oldest_ms.is_none_or(|oldest| oldest >= oldest_kept) {
+        return Ok(());
+    }
+
+    let mut oldest = None;
+    for mut history in all_histories(store) {
+        let aged = history.versions.iter().take_while(|version| version.saved_ms < oldest_kept).count();
+        if aged > 0 {
+            for version in history.versions.drain(..aged) {
+                ledger.release(&version);
+            }
+            store_history(store, Path::new(&history.path), &history)?;
+        }
+        for version in &history.versions {
+            oldest = Some(oldest.map_or(version.saved_ms, |o: u64| o.min(version.saved_ms)));
+        }
+    }
+    ledger.oldest_ms = oldest;
+    Ok(())
+
+}
+
+// Drops the oldest versions across all files until the distinct contents fit the
+// size limit. Only reads the histories when the ledger says the store is too big.
+fn fit_size(store: &Path, ledger: &mut Ledger, retention: &Retention) -> Result<(), String> {
+
+    if ledger.total_bytes <= retention.max_total_bytes {
+        return Ok(());
+    }
+
+    let mut histories = all_histories(store);
+    let mut changed = vec![false; histories.len()];
+    while ledger.total_bytes > retention.max_total_bytes {
+        let oldest = histories
+            .iter()
+            .enumerate()
+            .filter_map(|(i, h)| h.versions.first().map(|v| (v.saved_ms, i)))
+            .min();
+        match oldest {
+            Some((_, i)) => {
+                let version = histories[i].versions.remove(0);
+                ledger.release(&version);
+                changed[i] = true;
+            }
+            None => break,
+        }
+    }
+
+    for (history, _) in histories.iter().zip(changed).filter(|(_, changed)| *changed) {
+        store_history(store, Path::new(&history.path), history)?;
+    }
+    Ok(())
+
+}
+
+// The versions of a file, newest first
+pub fn versions(store: &Path, path: &Path) -> Vec<Version> {
+    let mut versions = load(store, path).versions;
+    versions.reverse();
+    versions
+}
+
+// The content of one version of a file; ids not in its history are refused
+pub fn read(store: &Path, path: &Path, id: &str) -> Result<String, String> {
+    if !load(store, path).versions.iter().any(|version| version.id == id) {
+        return Err(format!("No version {} of {}", id, path.display()));
+    }
+    fs::read_to_string(object_path(store, id)).map_err(|e| e.to_string())
+}
+
+// The changes from one version to another, where None stands for the file as it is on disk
+pub fn diff(store: &Path, path: &Path, from: Option<&str>, to: Option<&str>) -> Result<Vec<DiffHunk>, String> {
+    let content = |id: Option<&str>| match id {
+        Some(id) => read(store, path, id),
+        None => fs::read_to_string(path).map_err(|e| e.to_string()),
+    };
+    Ok(diff::hunks(&content(from)?, &content(to)?, DIFF_CONTEXT))
+}
+
+// Puts a version back on disk. What the file held is kept as a version first, so a
+// restore can itself be undone. Returns the restored content.
+pub fn restore(store: &Path, ledger: &mut Ledger, path: &Path, id: &str, retention: &Retention) -> Result<String, String> {
+    let content = read(store, path, id)?;
+    if let Ok(current) = fs::read(path) {
+        record(store, ledger, path, &current, retention)?;
+    }
+    write_atomic(path, content.as_bytes()).map_err(|e| e.to_string())?;
+    Ok(content)
+}
+
+fn retention_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("history.json")
+}
+
+// Missing or unreadable limits fall back to the defaults
+pub fn load_retention(config_dir: &Path) -> Retention {
+    fs::read_to_string(retention_path(config_dir))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn store_retention(config_dir: &Path, retention: &Retention) -> Result<(), String> {
+    fs::create_dir_all(config_dir).map_err(|e| e.to_string())?;
+    let json = serde_json::to_string_pretty(retention).map_err(|e| e.to_string())?;
+    write_atomic(&retention_path(config_dir), json.as_bytes()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn object_count(store: &Path) -> usize {
+        fs::read_dir(store.join("objects"))
+            .map(|dirs| dirs.flatten().map(|dir| fs::read_dir(dir.path()).unwrap().count()).sum())
+            .unwrap_or(0)
+    }
+
+    fn collect(store: &Path, ledger: &mut Ledger) {
+        remove_objects(store, &ledger.take_garbage());
+    }
+
+    #[test]
+    fn test_record_deduplicates() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let store = temp_dir.path().join("history");
+        let (a, b) = (temp_dir.path().join("a.md"), temp_dir.path().join("b.md"));
+        let retention = Retention::default();
+        let ledger = &mut Ledger::build(&store);
+
+        assert!(record(&store, ledger, &a, b"first", &retention).unwrap().is_some());
+        assert!(record(&store, ledger, &a, b"first", &retention).unwrap().is_none());
+        record(&store, ledger, &a, b"second", &retention).unwrap();
+        record(&store, ledger, &b, b"first", &retention).unwrap();
+
+        let found = versions(&store, &a);
+        assert_eq!(found.len(), 2);
+        assert_eq!(read(&store, &a, &found[0].id).unwrap(), "second");
+        assert_eq!(read(&store, &a, &found[1].id).unwrap(), "first");
+        assert_eq!(object_count(&store), 2);
+
+        // Another file's version is not readable through this one
+        let only_b = record(&store, ledger, &b, b"third", &retention).unwrap().unwrap();
+        assert!(read(&store, &a, &only_b.id).is_err());
+    }
+
+    #[test]
+    fn test_retention_limits() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let store = temp_dir.path().join("history");
+        let (a, b) = (temp_dir.path().join("a.md"), temp_dir.path().join("b.md"));
+        let ledger = &mut Ledger::build(&store);
+
+        let by_count = Retention { max_versions: 2, ..Retention::default() };
+        for content in ["one", "two", "three"] {
+            record(&store, ledger, &a, content.as_bytes(), &by_count).unwrap();
+        }
+        let kept: Vec<String> = versions(&store, &a).iter().map(|v| read(&store, &a, &v.id).unwrap()).collect();
+        assert_eq!(kept, vec!["three", "two"]);
+        // "one" stays on disk until garbage is collected
+        assert_eq!(object_count(&store), 3);
+        collect(&store, ledger);
+        assert_eq!(object_count(&store), 2);
+
+        // "two" and "three" take 6 bytes; the oldest version goes to make room for "four"
+        let by_size = Retention { max_total_bytes: 9, ..Retention::default() };
+        record(&store, ledger, &b, b"four", &by_size).unwrap();
+        let kept: Vec<String> = versions(&store, &a).iter().map(|v| read(&store, &a, &v.id).unwrap()).collect();
+        assert_eq!(kept, vec!["three"]);
+        assert_eq!(versions(&store, &b).len(), 1);
+
+    }
+
+    #[test]
+    fn test_age_limit_applies_to_every_file() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let store = temp_dir.path().join("history");
+        let (a, b) = (temp_dir.path().join("a.md"), temp_dir.path().join("b.md"));
+        let ledger = &mut Ledger::build(&store);
+        let retention = Retention { max_age_days: 1, ..Retention::default() };
+        record(&store, ledger, &a, b"one", &retention).unwrap();
+        record(&store, ledger, &b, b"two", &retention).unwrap();
+
+        // Nothing is due yet
+        expire(&store, ledger, &retention, now_ms()).unwrap();
+        assert_eq!((versions(&store, &a).len(), versions(&store, &b).len()), (1, 1));
+
+        // Neither file is saved again, and both age out
+        expire(&store, ledger, &retention, now_ms() + 2 * DAY_MS).unwrap();
+        assert!(versions(&store, &a).is_empty());
+        assert!(versions(&store, &b).is_empty());
+        assert_eq!(ledger.oldest_ms, None);
+        collect(&store, ledger);
+        assert_eq!(object_count(&store), 0);
+        assert_eq!(ledger.total_bytes, 0);
+        assert_eq!(Ledger::build(&store).objects.len(), 0);
+    }
+
+    #[test]
+    fn test_ledger_build_matches_records() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let store = temp_dir.path().join("history");
+        let (a, b) = (temp_dir.path().join("a.md"), temp_dir.path().join("b.md"));
+        let retention = Retention { max_versions: 1, ..Retention::default() };
+        let ledger = &mut Ledger::build(&store);
+
+        record(&store, ledger, &a, b"shared", &retention).unwrap();
+        record(&store, ledger, &b, b"shared", &retention).unwrap();
+        record(&store, ledger, &a, b"newer", &retention).unwrap();
+
+        // "shared" is still used by b, so nothing is garbage yet
+        assert!(!ledger.has_garbage());
+        let rebuilt = Ledger::build(&store);
+        assert_eq!(rebuilt.objects, ledger.objects);
+        assert_eq!(rebuilt.total_bytes, 11);
+
+        // An object no history knows of, as left by an interrupted save, is collected
+        let stray = object_path(&store, &content_hash(b"stray"));
+        fs::create_dir_all(stray.parent().unwrap()).unwrap();
+        fs::write(&stray, "stray").unwrap();
+        let mut rebuilt = Ledger::build(&store);
+        collect(&store, &mut rebuilt);
+        assert!(!stray.exists());
+        assert_eq!(object_count(&store), 2);
+    }
+
+    #[test]
+    fn test_diff_and_restore() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let store = temp_dir.path().join("history");
+        let note = temp_dir.path().join("note.md");
+        let retention = Retention::default();
+        let ledger = &mut Ledger::build(&store);
+
+        let old = record(&store, ledger, &note, b"line one\nline two\n", &retention).unwrap().unwrap();
+        fs::write(&note, "line one\nline 2\n").unwrap();
+
+        let hunks = diff(&store, &note, Some(&old.id), None).unwrap();
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].old_start, hunks[0].old_len, hunks[0].new_len), (1, 2, 2));
+
+        assert_eq!(restore(&store, ledger, &note, &old.id, &retention).unwrap(), "line one\nline two\n");
+        assert_eq!(fs::read_to_string(&note).unwrap(), "line one\nline two\n");
+        // The overwritten content became a version
+        let found = versions(&store, &note);
+        assert_eq!(found.len(), 2);
+        assert_eq!(read(&store, &note, &found[0].id).unwrap(), "line one\nline 2\n");
+    }
+
+    #[test]
+    fn test_retention_store_and_load() {
+        let config_dir = TempDir::new().expect("Failed to create temp dir");
+        assert_eq!(load_retention(config_dir.path()), Retention::default());
+
+        let retention = Retention { enabled: false, max_versions: 5, max_age_days: 7, max_total_bytes: 1024 };
+        store_retention(config_dir.path(), &retention).unwrap();
+        assert_eq!(load_retention(config_dir.path()), retention);
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+// Read in this order, so within one directory later files win
+pub const IGNORE_FILES: [&str; 3] = [".gitignore", ".ignore", ".aquaignore"];
+
+pub fn is_ignore_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| IGNORE_FILES.iter().any(|f| name == *f))
+}
+
+// Ignore files of a workspace, loaded lazily per directory. Like git, the rules of
+// the deepest directory that has an opinion decide, so nested files can negate.
+#[derive(Debug)]
+pub struct IgnoreRules {
+    root: PathBuf,
+    cache: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+}
+
+fn load_dir(dir: &Path) -> Option<Arc<Gitignore>> {
+
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+
+    for name in IGNORE_FILES {
+        let file = dir.join(name);
+        if file.is_file() {
+            found = true;
+            if let Some(e) = builder.add(&file) {
+                println!("ignore file error: {:?}", e);
+            }
+        }
+    }
+
+    if !found {
+        return None;
+    }
+
+    builder.build().ok().map(Arc::new)
+
+}
+
+impl IgnoreRules {
+
+    pub fn new(root: &Path) -> Self {
+        IgnoreRules {
+            root: root.to_path_buf(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn matcher(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        let mut cache = self.cache.lock().unwrap();
+        cache
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| load_dir(dir))
+            .clone()
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+
+        if !path.starts_with(&self.root) || path == self.root {
+            return false;
+        }
+
+        // Deepest directory first, stopping at the workspace root
+        for dir in path.ancestors().skip(1).take_while(|d| d.starts_with(&self.root)) {
+            if let Some(matcher) = self.matcher(dir) {
+                match matcher.matched_path_or_any_parents(path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+        }
+
+        false
+
+    }
+
+    // Forgets loaded rules after an ignore file changed on disk
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_gitignore_patterns() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join(".gitignore"), "target/\n*.log.md\n").unwrap();
+        let rules = IgnoreRules::new(root);
+
+        assert!(rules.is_ignored(&root.join("target"), true));
+        assert!(rules.is_ignored(&root.join("target/doc/index.md"), false));
+        assert!(rules.is_ignored(&root.join("notes/build.log.md"), false));
+        assert!(!rules.is_ignored(&root.join("notes/readme.md"), false));
+    }
+
+    #[test]
+    fn test_nested_negation() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("drafts")).unwrap();
+        fs::write(root.join(".gitignore"), "drafts/*.md\n").unwrap();
+        fs::write(root.join("drafts/.gitignore"), "!keep.md\n").unwrap();
+        let rules = IgnoreRules::new(root);
+
+        assert!(rules.is_ignored(&root.join("drafts/wip.md"), false));
+        assert!(!rules.is_ignored(&root.join("drafts/keep.md"), false));
+    }
+
+    #[test]
+    fn test_aquaignore_overrides_gitignore() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join(".gitignore"), "generated/\n").unwrap();
+        fs::write(root.join(".aquaignore"), "!generated/\nscratch.md\n").unwrap();
+        let rules = IgnoreRules::new(root);
+
+        assert!(!rules.is_ignored(&root.join("generated"), true));
+        assert!(rules.is_ignored(&root.join("scratch.md"), false));
+    }
+
+    #[test]
+    fn test_clear_reloads_rules() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let rules = IgnoreRules::new(root);
+        assert!(!rules.is_ignored(&root.join("out.md"), false));
+
+        fs::write(root.join(".ignore"), "out.md\n").unwrap();
+        rules.clear();
+
+        assert!(rules.is_ignored(&root.join("out.md"), false));
+    }
+
+    #[test]
+    fn test_is_ignore_file() {
+        assert!(is_ignore_file(Path::new("/vault/sub/.aquaignore")));
+        assert!(is_ignore_file(Path::new("/vault/.gitignore")));
+        assert!(!is_ignore_file(Path::new("/vault/gitignore.md")));
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use crate::links::normalize;
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct OpenRequest {
+    pub path: String,
+    pub is_dir: bool,
+    // 1-based position to jump to, from +line:col
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+// "+12" or "+12:4"
+fn position(arg: &str) -> Option<(usize, Option<usize>)> {
+    let rest = arg.strip_prefix('+')?;
+    let (line, column) = match rest.split_once(':') {
+        Some((line, column)) => (line, Some(column.parse().ok()?)),
+        None => (rest, None),
+    };
+    Some((line.parse().ok()?, column))
+}
+
+pub fn request_for(path: &Path) -> Option<OpenRequest> {
+    let metadata = path.metadata().ok()?;
+    Some(OpenRequest { path: path.to_string_lossy().to_string(), is_dir: metadata.is_dir(), line: None, column: None })
+}
+
+// Files and folders to open from a command line (without the program name), with
+// relative paths taken from `cwd`. A +line:col applies to the path after it, or to
+// the one before when it comes last. Options and missing paths are skipped.
+pub fn requests_from_args(args: &[String], cwd: &Path) -> Vec<OpenRequest> {
+
+    let mut requests: Vec<OpenRequest> = Vec::new();
+    let mut pending = None;
+
+    for arg in args {
+        if let Some(jump) = position(arg) {
+            pending = Some(jump);
+            continue;
+        }
+        if arg.starts_with('-') {
+            continue;
+        }
+        match request_for(&normalize(&cwd.join(arg))) {
+            Some(mut request) => {
+                if let Some((line, column)) = pending.take().filter(|_| !request.is_dir) {
+                    request.line = Some(line);
+                    request.column = column;
+                }
+                requests.push(request);
+            }
+            None => println!("open error: {} does not exist", arg),
+        }
+    }
+
+    if let (Some((line, column)), Some(last)) = (pending, requests.last_mut()) {
+        if !last.is_dir {
+            last.line = Some(line);
+            last.column = column;
+        }
+    }
+
+    requests
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_requests_from_args() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let cwd = temp_dir.path();
+        fs::create_dir(cwd.join("notes")).unwrap();
+        fs::write(cwd.join("notes/a.md"), "").unwrap();
+        fs::write(cwd.join("b.md"), "").unwrap();
+
+        let requests = requests_from_args(&args("-psn_0_1 +12:4 notes/a.md notes missing.md ./b.md +7"), cwd);
+        let found: Vec<(String, bool, Option<usize>, Option<usize>)> = requests
+            .into_iter()
+            .map(|r| (r.path, r.is_dir, r.line, r.column))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                (cwd.join("notes/a.md").to_string_lossy().to_string(), false, Some(12), Some(4)),
+                (cwd.join("notes").to_string_lossy().to_string(), true, None, None),
+                (cwd.join("b.md").to_string_lossy().to_string(), false, Some(7), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_position() {
+        assert_eq!(position("+3"), Some((3, None)));
+        assert_eq!(position("+3:9"), Some((3, Some(9))));
+        assert_eq!(position("+x"), None);
+        assert_eq!(position("3:9"), None);
+    }
+}
@@ -1,247 +1,1899 @@
+pub mod cli;
+mod diff;
+mod export;
+mod file_ops;
+mod git;
+mod graph;
+mod history;
+mod ignore_rules;
+mod launch;
+mod link_check;
+mod links;
+mod recovery;
+mod replace;
+mod save;
+mod search;
+mod search_index;
+mod session;
+mod settings;
+mod sort;
+mod tree;
+mod watch;
+
 use std::panic;
 use chrono::Local;
 use std::io::Write;
 use std::fs::{self};
 use tokio::fs::File;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
 use std::fs::OpenOptions;
 use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
 use tauri_plugin_dialog::DialogExt;
-use notify::{Watcher, RecursiveMode};
 use tokio::io::{AsyncReadExt, BufReader};
 use tauri_plugin_clipboard_manager::ClipboardExt;
 use tauri::menu::{Menu, MenuItemBuilder, PredefinedMenuItem, Submenu};
-use tauri::{generate_context, generate_handler, AppHandle, Builder, Emitter, Manager, Window};
+use sha2::{Digest, Sha256};
+use tauri::{generate_context, generate_handler, AppHandle, Builder, Emitter, Manager, State, Window};
+use diff::DiffHunk;
+use file_ops::FileOpError;
+use git::{Annotations, Commit, GutterMark, Repo, RepoStatus};
+use graph::{Edge, LinkGraph};
+use history::{Ledger, Retention, Version};
+use launch::OpenRequest;
+use link_check::{CheckSummary, LinkIssue};
+use recovery::Recoverable;
+use replace::{FileReplacement, ReplaceBatch, UndoResult};
+use save::{fingerprint_file, fingerprint_with_hash, write_atomic, OpenFiles, SaveError};
+use search::{SearchMatch, SearchOptions, SearchSummary};
+use search_index::{write_stored, IndexHit, SearchIndex};
+use session::{Geometry, Session, WindowSession};
+use settings::WorkspaceSettings;
+use sort::SortOrder;
+use tree::{list_files, read_dir_with, FileNode, TextCounts, TreeIndex, TreeOptions, TreePatch};
+use watch::{DeltaKind, PathDelta};
+
+#[derive(serde::Serialize, Clone)]
+struct FolderResult {
+    path: String,
+    tree: Vec<FileNode>,
+}
+
+#[derive(serde::Serialize)]
+struct OpenedFile {
+    path: String,
+    content: String,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct FileChunk {
+    content: String,
+    is_last: bool,
+}
+
+#[derive(serde::Serialize)]
+struct MoveResult {
+    patches: Vec<TreePatch>,
+    // The rewrite of links to and from the moved path, undone with `undo_replace`
+    links: Option<ReplaceBatch>,
+    // Why the links could not be rewritten; the move itself still happened
+    links_error: Option<String>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct SearchResults {
+    id: u64,
+    matches: Vec<SearchMatch>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct SearchDone {
+    id: u64,
+    summary: SearchSummary,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct LinkCheckResults {
+    id: u64,
+    issues: Vec<LinkIssue>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct LinkCheckDone {
+    id: u64,
+    summary: CheckSummary,
+    // Where the JSON report was written, if one was asked for
+    report: Option<String>,
+    error: Option<String>,
+}
+
+// A root folder in the sidebar of a window. Every window has its own roots, each
+// with its own watcher, search index and link graph.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct RootKey {
+    window: String,
+    root: PathBuf,
+}
+
+struct WatcherState(Mutex<HashMap<RootKey, notify::RecommendedWatcher>>);
+
+// Id and cancel flag of the latest workspace search, per window
+struct SearchState(Mutex<HashMap<String, (u64, Arc<AtomicBool>)>>);
+
+// Id and cancel flag of the latest link check, per window
+struct LinkCheckState(Mutex<HashMap<String, (u64, Arc<AtomicBool>)>>);
+
+// The latest replace preview of each window with its id, applied as shown by `replace_in_workspace`
+struct ReplacePreviewState(Mutex<HashMap<String, (u64, Vec<FileReplacement>)>>);
+
+#[derive(serde::Serialize)]
+struct ReplacePreview {
+    // Pass to `replace_in_workspace` to write these changes
+    id: u64,
+    files: Vec<FileReplacement>,
+}
+
+// Cancels the window's running job of a kind, if any, and hands out the id and cancel flag of the next
+fn next_job(jobs: &Mutex<HashMap<String, (u64, Arc<AtomicBool>)>>, window: &str) -> (u64, Arc<AtomicBool>) {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let mut jobs = jobs.lock().unwrap();
+    let id = match jobs.get(window) {
+        Some((last, previous)) => {
+            previous.store(true, Ordering::Relaxed);
+            last + 1
+        }
+        None => 1,
+    };
+    jobs.insert(window.to_string(), (id, cancel.clone()));
+    (id, cancel)
+}
+
+fn cancel_job(jobs: &Mutex<HashMap<String, (u64, Arc<AtomicBool>)>>, window: &str) {
+    if let Some((_, cancel)) = jobs.lock().unwrap().get(window) {
+        cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+// Trees of the root folders of each window, in sidebar order, patched from watcher events
+struct TreeState(Mutex<HashMap<String, Vec<TreeIndex>>>);
+
+impl TreeState {
+
+    fn is_open(&self, key: &RootKey) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&key.window)
+            .is_some_and(|roots| roots.iter().any(|tree| tree.root == key.root))
+    }
+
+    // Options of every root folder of a window
+    fn roots(&self, window: &str) -> Vec<TreeOptions> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(window)
+            .map(|roots| roots.iter().map(|tree| tree.options.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    // Options of the window's root folder holding `path`, the innermost when roots nest
+    fn root_of(&self, window: &str, path: &Path) -> Option<TreeOptions> {
+        self.roots(window)
+            .into_iter()
+            .filter(|options| path.starts_with(&options.root))
+            .max_by_key(|options| options.root.components().count())
+    }
+
+    // Runs `f` on the tree of a root folder, if it is still open
+    fn with_tree<T>(&self, key: &RootKey, f: impl FnOnce(&mut TreeIndex) -> T) -> Option<T> {
+        self.0
+            .lock()
+            .unwrap()
+            .get_mut(&key.window)
+            .and_then(|roots| roots.iter_mut().find(|tree| tree.root == key.root))
+            .map(f)
+    }
+
+}
+
+// Options of the root folder a command names, or of the window's first one
+fn pick_root(tree_state: &TreeState, window: &str, root: Option<&str>) -> Result<TreeOptions, String> {
+    let roots = tree_state.roots(window);
+    match root {
+        Some(root) => roots.into_iter().find(|options| options.root == Path::new(root)).ok_or_else(|| "Folder is not open".into()),
+        None => roots.into_iter().next().ok_or_else(|| "No folder open".into()),
+    }
+}
+
+// Full-text index of each root folder, missing until it has been loaded and synced
+struct IndexState(Mutex<HashMap<RootKey, SearchIndex>>);
+
+// Root folders whose index changed and has a write scheduled
+struct IndexPersistState(Mutex<HashSet<RootKey>>);
+
+// How long index changes collect before the index is written again
+const INDEX_PERSIST_DELAY: Duration = Duration::from_secs(5);
+
+// Links between the notes of each root folder, missing until every note has been parsed
+struct GraphState(Mutex<HashMap<RootKey, LinkGraph>>);
+
+// The repository of each root folder inside one, with the sidebar status from its last `git status`
+struct GitState(Mutex<HashMap<RootKey, GitRoot>>);
+
+// Watches HEAD and the index in the git directory of each root folder inside a
+// repository, which the folder watcher leaves out, so git commands run elsewhere
+// refresh the status
+struct GitWatcherState(Mutex<HashMap<RootKey, notify::RecommendedWatcher>>);
+
+// Root folders with a `git status` scheduled
+struct GitRefreshState(Mutex<HashSet<RootKey>>);
+
+// How long status refreshes asked for by file changes collect before git runs
+const GIT_REFRESH_DELAY: Duration = Duration::from_secs(1);
+
+struct GitRoot {
+    repo: Repo,
+    annotations: Annotations,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct GitStatusEvent {
+    root: String,
+    status: RepoStatus,
+}
+
+// Paths from the command line or the OS waiting for the page of the window they
+// are meant for to ask for them; None once it has, after which new ones are sent as
+// "open-paths" events
+struct LaunchState(Mutex<Option<PendingLaunch>>);
+
+struct PendingLaunch {
+    window: String,
+    requests: Vec<OpenRequest>,
+}
+
+// The window menu commands and opened paths go to: the focused one, else the main one
+fn active_window(app: &AppHandle) -> Option<tauri::WebviewWindow> {
+    let windows = app.webview_windows();
+    windows
+        .values()
+        .find(|win| win.is_focused().unwrap_or(false))
+        .or_else(|| windows.get("main"))
+        .or_else(|| windows.values().next())
+        .cloned()
+}
+
+// Hands paths to open to the frontend, or queues them while it is still loading.
+// With every window closed a new one is opened, and its page takes them from the queue.
+// A launch without paths still brings the running app to the front.
+fn open_requests(app: &AppHandle, requests: Vec<OpenRequest>) {
+
+    let state = app.state::<LaunchState>();
+    if let Some(pending) = state.0.lock().unwrap().as_mut() {
+        pending.requests.extend(requests);
+        return;
+    }
+
+    match active_window(app) {
+        Some(win) => {
+            let _ = win.unminimize();
+            let _ = win.show();
+            let _ = win.set_focus();
+            if !requests.is_empty() {
+                let _ = win.emit_to(win.label(), "open-paths", requests);
+            }
+        }
+        None => match new_window(app) {
+            Ok(win) => {
+                *state.0.lock().unwrap() = Some(PendingLaunch { window: win.label().to_string(), requests });
+            }
+            Err(e) => println!("window error: {}", e),
+        },
+    }
+
+}
+
+static WINDOW_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+// Another editor window with a workspace of its own, recorded in the session
+fn new_window(app: &AppHandle) -> tauri::Result<tauri::WebviewWindow> {
+    let win = build_window(app)?;
+    record_window(app, win.label());
+    Ok(win)
+}
+
+fn build_window(app: &AppHandle) -> tauri::Result<tauri::WebviewWindow> {
+    let label = format!("window-{}", WINDOW_COUNT.fetch_add(1, Ordering::Relaxed));
+    tauri::WebviewWindowBuilder::new(app, label, tauri::WebviewUrl::default())
+        .title("Aqua")
+        .inner_size(1200.0, 1000.0)
+        .build()
+}
+
+// Drops the workspace of a closed window, which stops its watchers
+fn forget_window(app: &AppHandle, window: &str) {
+    app.state::<TreeState>().0.lock().unwrap().remove(window);
+    app.state::<WatcherState>().0.lock().unwrap().retain(|key, _| key.window != window);
+    app.state::<IndexState>().0.lock().unwrap().retain(|key, _| key.window != window);
+    app.state::<GraphState>().0.lock().unwrap().retain(|key, _| key.window != window);
+    app.state::<GitState>().0.lock().unwrap().retain(|key, _| key.window != window);
+    app.state::<GitWatcherState>().0.lock().unwrap().retain(|key, _| key.window != window);
+    app.state::<FileWatcherState>().0.lock().unwrap().remove(window);
+    app.state::<ReplacePreviewState>().0.lock().unwrap().remove(window);
+    cancel_job(&app.state::<SearchState>().0, window);
+    cancel_job(&app.state::<LinkCheckState>().0, window);
+}
+
+// What each window had open, saved to the app config dir so the next launch can restore it
+struct SessionState(Mutex<Session>);
+
+// Paths of the restored session that were gone at launch, per window, for the frontend to mention
+struct MissingPaths(Mutex<HashMap<String, Vec<String>>>);
+
+#[derive(serde::Serialize)]
+struct RestoredSession {
+    session: WindowSession,
+    missing: Vec<String>,
+}
+
+fn window_geometry(win: &tauri::Window) -> Option<Geometry> {
+    let position = win.outer_position().ok()?;
+    let size = win.inner_size().ok()?;
+    Some(Geometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+        maximized: win.is_maximized().unwrap_or(false),
+    })
+}
+
+// Puts a window back where it was, unless that spot is on a monitor that is gone
+fn apply_geometry(win: &tauri::Window, geometry: &Geometry) {
+
+    let on_screen = win.available_monitors().unwrap_or_default().iter().any(|monitor| {
+        let (position, size) = (monitor.position(), monitor.size());
+        (position.x..position.x + size.width as i32).contains(&geometry.x)
+            && (position.y..position.y + size.height as i32).contains(&geometry.y)
+    });
+
+    if on_screen {
+        let _ = win.set_position(tauri::PhysicalPosition::new(geometry.x, geometry.y));
+    }
+    if geometry.width > 0 && geometry.height > 0 {
+        let _ = win.set_size(tauri::PhysicalSize::new(geometry.width, geometry.height));
+    }
+    if geometry.maximized {
+        let _ = win.maximize();
+    }
+
+}
+
+// Writes the session with the current geometry of every window
+fn store_session(app: &AppHandle) {
+
+    let state = app.state::<SessionState>();
+    let mut session = state.0.lock().unwrap();
+    for (label, win) in app.webview_windows() {
+        let geometry = window_geometry(&win.as_ref().window());
+        if let Some(saved) = session.windows.iter_mut().find(|saved| saved.label == label) {
+            saved.geometry = geometry.or(saved.geometry);
+        }
+    }
+
+    let stored = app.path().app_config_dir().map_err(|e| e.to_string()).and_then(|dir| session::store(&dir, &session));
+    if let Err(e) = stored {
+        println!("session error: {}", e);
+    }
+
+}
+
+// Records the root folders a window has open, whenever they change, and writes the session
+fn record_window(app: &AppHandle, window: &str) {
+
+    let roots = app
+        .state::<TreeState>()
+        .roots(window)
+        .into_iter()
+        .map(|options| options.root.to_string_lossy().to_string())
+        .collect();
+
+    {
+        let state = app.state::<SessionState>();
+        let mut session = state.0.lock().unwrap();
+        let saved = session.window(window).cloned().unwrap_or_else(|| WindowSession { label: window.to_string(), ..WindowSession::default() });
+        session.update(WindowSession { roots, ..saved });
+    }
+
+    store_session(app);
+
+}
+
+// Reopens the windows and root folders of the last session. Open files, cursors and
+// sidebar state are handed to each window's frontend through `get_window_session`.
+// Windows come back right away; their folders are opened on a background thread and
+// each one is sent to its window as a "folder-opened" event.
+fn restore_session(app: &AppHandle) {
+
+    let saved = match app.path().app_config_dir() {
+        Ok(dir) => session::load(&dir),
+        Err(_) => return,
+    };
+
+    let mut restored = Session::default();
+    let mut missing = HashMap::new();
+    for mut window in saved.windows {
+
+        let gone = window.prune();
+        if window.is_empty() && !restored.windows.is_empty() {
+            continue;
+        }
+
+        // The first saved window takes the main one, the others get new windows
+        let win = if restored.windows.is_empty() {
+            app.get_webview_window("main")
+        } else {
+            build_window(app).map_err(|e| println!("session error: {}", e)).ok()
+        };
+        let win = match win {
+            Some(win) => win,
+            None => continue,
+        };
+
+        window.label = win.label().to_string();
+        if let Some(geometry) = &window.geometry {
+            apply_geometry(&win.as_ref().window(), geometry);
+        }
+
+        missing.insert(window.label.clone(), gone);
+        restored.windows.push(window);
+
+    }
+
+    let roots: Vec<(String, Vec<String>)> = restored.windows.iter().map(|window| (window.label.clone(), window.roots.clone())).collect();
+    *app.state::<SessionState>().0.lock().unwrap() = restored;
+    *app.state::<MissingPaths>().0.lock().unwrap() = missing;
+
+    let app = app.clone();
+    std::thread::spawn(move || {
+        for (label, roots) in roots {
+            for root in roots {
+                match open_root(&app, &label, PathBuf::from(root), None, None, true) {
+                    Ok(opened) => {
+                        let _ = app.emit_to(label.as_str(), "folder-opened", opened);
+                    }
+                    Err(e) => println!("session error: {}", e),
+                }
+            }
+        }
+    });
+
+}
+
+// The saved state of this window from the last session, with the paths that had disappeared
+#[tauri::command]
+fn get_window_session(window: Window, session_state: State<'_, SessionState>, missing: State<'_, MissingPaths>) -> Option<RestoredSession> {
+    let session = session_state.0.lock().unwrap().window(window.label()).cloned()?;
+    let missing = missing.0.lock().unwrap().remove(window.label()).unwrap_or_default();
+    Some(RestoredSession { session, missing })
+}
+
+// The root folders open in this window with their trees, for a page that loads after
+// some of them were opened, as when a session is restored
+#[tauri::command]
+fn list_open_folders(app: AppHandle, window: Window, tree_state: State<'_, TreeState>) -> Vec<FolderResult> {
+    tree_state
+        .roots(window.label())
+        .into_iter()
+        .filter_map(|options| {
+            let key = RootKey { window: window.label().to_string(), root: options.root };
+            let mut tree = tree_state.with_tree(&key, |index| index.nodes.clone())?;
+            annotate_git(&app, &key, &mut tree);
+            Some(FolderResult { path: key.root.to_string_lossy().to_string(), tree })
+        })
+        .collect()
+}
+
+// Records what the window has open. Root folders and geometry come from the backend,
+// the rest from the frontend.
+#[tauri::command]
+fn save_window_session(app: AppHandle, window: Window, tree_state: State<'_, TreeState>, session: WindowSession) {
+    let session = WindowSession {
+        label: window.label().to_string(),
+        roots: tree_state.roots(window.label()).into_iter().map(|options| options.root.to_string_lossy().to_string()).collect(),
+        geometry: window_geometry(&window),
+        ..session
+    };
+    app.state::<SessionState>().0.lock().unwrap().update(session);
+    store_session(&app);
+}
+
+// Watches the document open in each window, separately from the folder watchers
+struct FileWatcherState(Mutex<HashMap<String, (PathBuf, notify::RecommendedWatcher)>>);
+
+// Starts watching the document open in a window for changes made by other programs,
+// replacing the watch on the one open there before
+fn watch_open_file(app: &AppHandle, window: &str, path: &Path) {
+
+    let app_for_hash = app.clone();
+    let app_handle = app.clone();
+    let label = window.to_string();
+
+    let watcher = watch::watch_file(
+        path,
+        move |current| app_for_hash.state::<OpenFiles>().get(current).map(|fp| fp.hash),
+        move |change| { let _ = app_handle.emit_to(label.as_str(), "file-changed-externally", change); },
+    );
+
+    let state = app.state::<FileWatcherState>();
+    let mut managed_watch = state.0.lock().unwrap();
+    match watcher {
+        Ok(watcher) => {
+            managed_watch.insert(window.to_string(), (path.to_path_buf(), watcher));
+        }
+        Err(e) => {
+            println!("watch error: {:?}", e);
+            managed_watch.remove(window);
+        }
+    }
+
+}
+
+// Loads the stored search index of a folder on a background thread and catches
+// it up with the files on disk, so only notes changed since the last run are read
+fn open_search_index(app: &AppHandle, key: RootKey, options: TreeOptions) {
+
+    app.state::<IndexState>().0.lock().unwrap().remove(&key);
+    let app = app.clone();
+
+    std::thread::spawn(move || {
+        let data_dir = match app.path().app_data_dir() {
+            Ok(dir) => dir,
+            Err(e) => return println!("index error: {}", e),
+        };
+
+        let mut index = SearchIndex::load(&data_dir, &options.root);
+        if index.sync(&list_files(&options.root, &options)) {
+            if let Err(e) = index.store(&data_dir) {
+                println!("index error: {}", e);
+            }
+        }
+
+        // The folder may have been closed in the meantime
+        if app.state::<TreeState>().is_open(&key) {
+            app.state::<IndexState>().0.lock().unwrap().insert(key, index);
+        }
+    });
+
+}
+
+// Parses the links of every note in a folder on a background thread
+fn open_link_graph(app: &AppHandle, key: RootKey, options: TreeOptions) {
+
+    app.state::<GraphState>().0.lock().unwrap().remove(&key);
+    let app = app.clone();
+
+    std::thread::spawn(move || {
+        let graph = LinkGraph::build(&options.root, &list_files(&options.root, &options));
+        if app.state::<TreeState>().is_open(&key) {
+            app.state::<GraphState>().0.lock().unwrap().insert(key, graph);
+        }
+    });
+
+}
+
+// Keeps the link graph in step with a watcher batch, like `update_search_index`
+fn update_link_graph(app: &AppHandle, key: &RootKey, batch: &[watch::PathDelta], full: bool) {
+
+    let options = match app.state::<TreeState>().with_tree(key, |tree| tree.options.clone()) {
+        Some(options) => options,
+        None => return,
+    };
+
+    // Re-reading every note takes a while in a large vault, so the new graph is built
+    // on its own thread and swapped in; queries meanwhile get the old one
+    if full {
+        let (app, key) = (app.clone(), key.clone());
+        std::thread::spawn(move || {
+            let graph = LinkGraph::build(&options.root, &list_files(&options.root, &options));
+            if let Some(current) = app.state::<GraphState>().0.lock().unwrap().get_mut(&key) {
+                *current = graph;
+            }
+        });
+        return;
+    }
+
+    let state = app.state::<GraphState>();
+    let mut guard = state.0.lock().unwrap();
+    if let Some(graph) = guard.get_mut(key) {
+        graph.apply(batch, &options);
+    }
+
+}
+
+// Keeps the search index in step with a watcher batch, or re-syncs it with every
+// shown file when the rules for what is shown changed
+fn update_search_index(app: &AppHandle, key: &RootKey, batch: &[watch::PathDelta], full: bool) {
+
+    let options = match app.state::<TreeState>().with_tree(key, |tree| tree.options.clone()) {
+        Some(options) => options,
+        None => return,
+    };
+
+    let state = app.state::<IndexState>();
+    let mut guard = state.0.lock().unwrap();
+    let index = match guard.get_mut(key) {
+        Some(index) => index,
+        None => return,
+    };
+
+    let changed = if full {
+        index.sync(&list_files(&options.root, &options))
+    } else {
+        index.apply(batch, &options)
+    };
+
+    drop(guard);
+    if changed {
+        schedule_index_persist(app, key);
+    }
+
+}
+
+// Writes the index of a root folder once changes stopped arriving for a while.
+// Only serializing holds the index lock; the file is written after it is released.
+// Changes not yet written when the folder closes are picked up by the next sync.
+fn schedule_index_persist(app: &AppHandle, key: &RootKey) {
+
+    if !app.state::<IndexPersistState>().0.lock().unwrap().insert(key.clone()) {
+        return;
+    }
+
+    let app = app.clone();
+    let key = key.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(INDEX_PERSIST_DELAY);
+        app.state::<IndexPersistState>().0.lock().unwrap().remove(&key);
+
+        let snapshot = app
+            .state::<IndexState>()
+            .0
+            .lock()
+            .unwrap()
+            .get(&key)
+            .map(|index| (index.root().to_path_buf(), index.to_bytes()));
+        let (root, bytes) = match snapshot {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+
+        let result = bytes.and_then(|bytes| {
+            let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+            write_stored(&dir, &root, &bytes)
+        });
+        if let Err(e) = result {
+            println!("index error: {}", e);
+        }
+    });
+
+}
+
+#[tauri::command]
+async fn pick_file(app: AppHandle) -> Result<Option<String>, String> {
+
+    let path = tokio::task::spawn_blocking(move || {
+        app.dialog()
+            .file()
+            .add_filter("Markdown", &["md"])
+            .blocking_pick_file()    
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    match path {
+        Some(p) => Ok(Some(p.to_string())),
+        None => Ok(None),
+    }
+
+}
+
+#[tauri::command]
+async fn read_file_chunked(window: Window, open_files: State<'_, OpenFiles>, path: String) -> Result<(), String> {
+
+    let file = File::open(&path).await.map_err(|e| e.to_string())?;
+    let meta = file.metadata().await.map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(file);
+    let mut buffer = [0; 65536]; // 64KB buffer
+    let mut hasher = Sha256::new();
+
+    loop {
+        
+        let bytes_read = reader.read(&mut buffer).await.map_err(|e| e.to_string())?;
+
+        // EOF
+        if bytes_read == 0 { 
+            open_files.record(Path::new(&path), fingerprint_with_hash(&meta, format!("{:x}", hasher.finalize())));
+            watch_open_file(window.app_handle(), window.label(), Path::new(&path));
+            window.emit_to(window.label(), "file-chunk", FileChunk {
+                content: "".to_string(),
+                is_last: true,
+            }).map_err(|e| e.to_string())?;
+            break;
+        }
+
+        hasher.update(&buffer[..bytes_read]);
+        let chunk_str = String::from_utf8_lossy(&buffer[..bytes_read]).to_string();
+
+        window.emit_to(window.label(), "file-chunk", FileChunk {
+            content: chunk_str,
+            is_last: false,
+        }).map_err(|e| e.to_string())?;
+
+    }
+
+    Ok(())
+
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+
+    // Convert the string path to PathBuf
+    let p = PathBuf::from(path);
+
+    // canonicalize
+    let actual_path = p.canonicalize().map_err(|_| format!("File not found or invalid path: {}", path))?;
+
+    fs::read_to_string(actual_path).map_err(|e| e.to_string())
+
+}
+
+#[tauri::command]
+async fn load_file(app: AppHandle, window: Window, open_files: State<'_, OpenFiles>, path: String) -> Result<String, String> {
+
+    let content = read_file(&path)?;
+
+    if let Ok(meta) = fs::metadata(&path) {
+        open_files.record(Path::new(&path), fingerprint_with_hash(&meta, save::content_hash(content.as_bytes())));
+    }
+    watch_open_file(&app, window.label(), Path::new(&path));
+
+    Ok(content)
+
+}
+
+// Saves content to a specified file path, refusing with a conflict error when the
+// file changed on disk since it was opened unless `force` is set. Unless `history`
+// or the history settings say otherwise, what the file held before is kept as a
+// version. The disk work runs on a blocking thread so fsync and the history write
+// do not stall the async runtime.
+#[tauri::command]
+async fn save_file(app: AppHandle, path: String, content: String, force: Option<bool>, history: Option<bool>) -> Result<(), SaveError> {
+
+    tokio::task::spawn_blocking(move || {
+        let p = PathBuf::from(&path);
+        let open_files = app.state::<OpenFiles>();
+
+        if !force.unwrap_or(false) {
+            open_files.check(&p)?;
+        }
+
+        let enabled = || history_store(&app).is_ok_and(|(_, retention)| retention.enabled);
+        if history.unwrap_or_else(enabled) {
+            keep_version(&app, &p);
+        }
+
+        write_atomic(&p, content.as_bytes())?;
+        open_files.record_written(&p, content.as_bytes());
+        if let Some(dir) = RECOVERY_DIR.get() {
+            recovery::discard(dir, &path);
+        }
+
+        Ok(())
+    })
+    .await
+    .map_err(|e| SaveError::Write(e.to_string()))?
+
+}
+
+// Serializes changes to the version store, with its ledger once the first change built it
+struct HistoryState(Mutex<Option<Ledger>>);
+
+// The version store under the app data dir and the retention limits from the config dir
+fn history_store(app: &AppHandle) -> Result<(PathBuf, Retention), String> {
+    let store = app.path().app_data_dir().map_err(|e| e.to_string())?.join("history");
+    let retention = history::load_retention(&app.path().app_config_dir().map_err(|e| e.to_string())?);
+    Ok((store, retention))
+}
+
+// Keeps the content a save is about to replace. Failures are logged rather than
+// failing the save.
+fn keep_version(app: &AppHandle, path: &Path) {
+    let current = match fs::read(path) {
+        Ok(current) => current,
+        Err(_) => return,
+    };
+    let state = app.state::<HistoryState>();
+    let mut ledger = state.0.lock().unwrap();
+    let recorded = history_store(app).and_then(|(store, retention)| {
+        let ledger = ledger.get_or_insert_with(|| Ledger::build(&store));
+        history::record(&store, ledger, path, &current, &retention)
+    });
+    drop(ledger);
+    match recorded {
+        Ok(_) => collect_history_garbage(app),
+        Err(e) => println!("history error: {}", e),
+    }
+}
+
+// Deletes the contents no version uses anymore on a background thread. The lock is
+// held while deleting so a save cannot bring a content back in between.
+fn collect_history_garbage(app: &AppHandle) {
+
+    let state = app.state::<HistoryState>();
+    if !state.0.lock().unwrap().as_ref().is_some_and(Ledger::has_garbage) {
+        return;
+    }
+
+    let app = app.clone();
+    std::thread::spawn(move || {
+        let store = match history_store(&app) {
+            Ok((store, _)) => store,
+            Err(e) => return println!("history error: {}", e),
+        };
+        let state = app.state::<HistoryState>();
+        let mut ledger = state.0.lock().unwrap();
+        if let Some(ledger) = ledger.as_mut() {
+            history::remove_objects(&store, &ledger.take_garbage());
+        }
+    });
+
+}
+
+// Applies the age limit to the whole store once per launch, so versions of files that
+// are not saved again still age out. Runs on a background thread like the collection.
+fn expire_history(app: &AppHandle) {
+
+    let app = app.clone();
+    std::thread::spawn(move || {
+        let (store, retention) = match history_store(&app) {
+            Ok(store) => store,
+            Err(e) => return println!("history error: {}", e),
+        };
+        let state = app.state::<HistoryState>();
+        let mut ledger = state.0.lock().unwrap();
+        let expired = history::expire(&store, ledger.get_or_insert_with(|| Ledger::build(&store)), &retention, history::now_ms());
+        drop(ledger);
+        match expired {
+            Ok(()) => collect_history_garbage(&app),
+            Err(e) => println!("history error: {}", e),
+        }
+    });
+
+}
+
+// The kept versions of a file, newest first. Like the other history commands it
+// reads the store on a blocking thread.
+#[tauri::command]
+async fn list_versions(app: AppHandle, path: String) -> Result<Vec<Version>, String> {
+    let (store, _) = history_store(&app)?;
+    tokio::task::spawn_blocking(move || history::versions(&store, Path::new(&path)))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn read_version(app: AppHandle, path: String, id: String) -> Result<String, String> {
+    let (store, _) = history_store(&app)?;
+    tokio::task::spawn_blocking(move || history::read(&store, Path::new(&path), &id))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// Differences between two versions of a file; a missing id stands for the file on disk
+#[tauri::command]
+async fn diff_versions(app: AppHandle, path: String, from: Option<String>, to: Option<String>) -> Result<Vec<DiffHunk>, String> {
+    let (store, _) = history_store(&app)?;
+    tokio::task::spawn_blocking(move || history::diff(&store, Path::new(&path), from.as_deref(), to.as_deref()))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// Writes a version back to its file and returns the content for the editor. Like a
+// save, the write is recorded as ours, so the next save of the file is not refused
+// as a conflict.
+#[tauri::command]
+async fn restore_version(app: AppHandle, path: String, id: String) -> Result<String, String> {
+
+    tokio::task::spawn_blocking(move || {
+        let (store, retention) = history_store(&app)?;
+        let p = PathBuf::from(&path);
+        let state = app.state::<HistoryState>();
+        let mut ledger = state.0.lock().unwrap();
+        let restored = history::restore(&store, ledger.get_or_insert_with(|| Ledger::build(&store)), &p, &id, &retention);
+        drop(ledger);
+        if let Ok(content) = &restored {
+            app.state::<OpenFiles>().record_written(&p, content.as_bytes());
+        }
+        collect_history_garbage(&app);
+        restored
+    })
+    .await
+    .map_err(|e| e.to_string())?
+
+}
+
+#[tauri::command]
+fn get_history_retention(app: AppHandle) -> Result<Retention, String> {
+    Ok(history_store(&app)?.1)
+}
+
+#[tauri::command]
+fn set_history_retention(app: AppHandle, retention: Retention) -> Result<(), String> {
+    history::store_retention(&app.path().app_config_dir().map_err(|e| e.to_string())?, &retention)
+}
+
+// The page "Export as HTML" writes, the same one `aqua export` does
+#[tauri::command]
+fn render_html(markdown: String, title: String) -> String {
+    export::render_html(&markdown, &title)
+}
+
+// Returns the on-disk fingerprint of a file so the frontend can compare it after a conflict
+#[tauri::command]
+async fn file_fingerprint(path: String) -> Result<save::FileFingerprint, String> {
+    fingerprint_file(Path::new(&path)).map_err(|e| e.to_string())
+}
+
+// How the editor's content differs from the file on disk, for a save refused as a
+// conflict. A file deleted on disk counts as empty.
+#[tauri::command]
+async fn diff_with_disk(path: String, content: String) -> Result<Vec<DiffHunk>, String> {
+    tokio::task::spawn_blocking(move || {
+        let disk = fs::read_to_string(&path).unwrap_or_default();
+        diff::hunks(&disk, &content, 3)
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+// Where unsaved documents are journaled, set in setup so the panic hook can reach it
+static RECOVERY_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// Documents left unsaved by a run that crashed, until the frontend takes them
+struct RecoveryState(Mutex<Vec<Recoverable>>);
+
+fn recovery_dir() -> Result<&'static PathBuf, String> {
+    RECOVERY_DIR.get().ok_or_else(|| "Recovery journal unavailable".to_string())
+}
+
+// Snapshots the unsaved content of a document, keyed by its path or, for an
+// untitled one, the editor's id
+#[tauri::command]
+async fn journal_buffer(path_or_id: String, content: String) -> Result<(), String> {
+    let dir = recovery_dir()?;
+    tokio::task::spawn_blocking(move || recovery::journal(dir, &path_or_id, &content))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// Drops the snapshot of a document that was saved, closed without saving, or recovered
+#[tauri::command]
+fn discard_buffer(path_or_id: String) -> Result<(), String> {
+    recovery::discard(recovery_dir()?, &path_or_id);
+    Ok(())
+}
+
+// Documents to offer for recovery after a crash, with their difference from disk.
+// Handed out once; their snapshots stay until discarded.
+#[tauri::command]
+fn take_recoverable_buffers(recovery_state: State<'_, RecoveryState>) -> Vec<Recoverable> {
+    std::mem::take(&mut *recovery_state.0.lock().unwrap())
+}
+
+// Paths the app was launched with, taken once the frontend is ready for them. Only
+// the window they were queued for gets them; restored windows alongside it get none.
+#[tauri::command]
+fn take_launch_requests(window: Window, launch_state: State<'_, LaunchState>) -> Vec<OpenRequest> {
+    let mut pending = launch_state.0.lock().unwrap();
+    if pending.as_ref().is_some_and(|pending| pending.window == window.label()) {
+        pending.take().map(|pending| pending.requests).unwrap_or_default()
+    } else {
+        Vec::new()
+    }
+}
+
+// Stops tracking a file once the editor closes it
+#[tauri::command]
+fn close_file(window: Window, open_files: State<'_, OpenFiles>, file_watcher: State<'_, FileWatcherState>, path: String) {
+    open_files.forget(Path::new(&path));
+    file_watcher.0.lock().unwrap().remove(window.label());
+}
+
+// Opens a folder in the window's sidebar, in place of the open ones or, with `add`,
+// as one more root folder
+#[tauri::command]
+async fn open_folder_and_list_files(app: AppHandle, window: Window, debounce_ms: Option<u64>, depth: Option<usize>, add: Option<bool>) -> Result<FolderResult, String> {
+    
+    let app_for_dialog = app.clone();
+    
+    let folder_path = tokio::task::spawn_blocking(move || {
+        app_for_dialog.dialog()
+            .file()
+            .blocking_pick_folder()
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let path = folder_path.ok_or("cancelled")?.to_string();
+    let window = window.label().to_string();
+    tokio::task::spawn_blocking(move || open_folder_at(&app, &window, PathBuf::from(path), debounce_ms, depth, add.unwrap_or(false)))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// Opens a folder given by path, as from the command line or a file association
+#[tauri::command]
+async fn open_folder(app: AppHandle, window: Window, path: String, debounce_ms: Option<u64>, depth: Option<usize>, add: Option<bool>) -> Result<FolderResult, String> {
+    let path_buf = PathBuf::from(&path);
+    if !path_buf.is_dir() {
+        return Err("Invalid directory path".into());
+    }
+    let window = window.label().to_string();
+    tokio::task::spawn_blocking(move || open_folder_at(&app, &window, path_buf, debounce_ms, depth, add.unwrap_or(false)))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// Takes a root folder out of the window's sidebar
+#[tauri::command]
+fn close_folder(app: AppHandle, window: Window, path: String) {
+    let key = RootKey { window: window.label().to_string(), root: PathBuf::from(path) };
+    close_root(&app, &key);
+    record_window(&app, window.label());
+}
+
+fn close_root(app: &AppHandle, key: &RootKey) {
+    if let Some(roots) = app.state::<TreeState>().0.lock().unwrap().get_mut(&key.window) {
+        roots.retain(|tree| tree.root != key.root);
+    }
+    app.state::<WatcherState>().0.lock().unwrap().remove(key);
+    app.state::<IndexState>().0.lock().unwrap().remove(key);
+    app.state::<GraphState>().0.lock().unwrap().remove(key);
+    app.state::<GitState>().0.lock().unwrap().remove(key);
+    app.state::<GitWatcherState>().0.lock().unwrap().remove(key);
+}
+
+// Builds the tree of a folder and starts indexing and watching it for a window.
+// Without `add` it replaces the window's open folders. Walks the folder and runs git,
+// so it is called off the async runtime.
+fn open_folder_at(app: &AppHandle, window: &str, path_buf: PathBuf, debounce_ms: Option<u64>, depth: Option<usize>, add: bool) -> Result<FolderResult, String> {
+    let opened = open_root(app, window, path_buf, debounce_ms, depth, add)?;
+    record_window(app, window);
+    Ok(opened)
+}
+
+// `open_folder_at` without recording the session, for restoring it
+fn open_root(app: &AppHandle, window: &str, path_buf: PathBuf, debounce_ms: Option<u64>, depth: Option<usize>, add: bool) -> Result<FolderResult, String> {
+
+    let path_string = path_buf.to_string_lossy().to_string();
+    let key = RootKey { window: window.to_string(), root: path_buf.clone() };
+    let open: Vec<PathBuf> = app.state::<TreeState>().roots(window).into_iter().map(|options| options.root).collect();
+    for root in open.into_iter().filter(|root| !add || *root == path_buf) {
+        close_root(app, &RootKey { window: window.to_string(), root });
+    }
+
+    let config_dir = app.path().app_config_dir().map_err(|e| e.to_string())?;
+    let workspace_settings = settings::load(&config_dir, &path_buf);
+    let options = TreeOptions::new(&path_buf, &workspace_settings)
+        .unwrap_or_else(|_| TreeOptions::default_for(&path_buf));
+
+    let index = TreeIndex::build(options.clone(), depth);
+    let mut tree = index.nodes.clone();
+    app.state::<TreeState>().0.lock().unwrap().entry(window.to_string()).or_default().push(index);
+    refresh_git(app, &key);
+    watch_git(app, &key);
+    annotate_git(app, &key, &mut tree);
+    open_search_index(app, key.clone(), options.clone());
+    open_link_graph(app, key.clone(), options);
+
+    // Set-up Watcher, batching events so bulk changes trigger one refresh
+    let app_handle = app.clone();
+    let app_for_filter = app.clone();
+    let key_for_filter = key.clone();
+    let debounce = debounce_ms.map_or(watch::DEFAULT_FOLDER_DEBOUNCE, Duration::from_millis);
+
+    let is_relevant = move |path: &Path, kind| {
+        app_for_filter
+            .state::<TreeState>()
+            .with_tree(&key_for_filter, |index| index.is_relevant(path, kind))
+            .unwrap_or(false)
+            || ignore_rules::is_ignore_file(path)
+            || sort::is_order_file(path)
+    };
+
+    let key_for_batch = key.clone();
+    let watcher = watch::watch_folder(&path_buf, debounce, is_relevant, move |batch| {
+        let key = &key_for_batch;
+        let rules_changed = batch.iter().any(|d| ignore_rules::is_ignore_file(Path::new(&d.path)));
+        let mut patches = app_handle
+            .state::<TreeState>()
+            .with_tree(key, |index| {
+                if rules_changed {
+                    index.rebuild()
+                } else if index.options.sort.key == sort::SortKey::Manual && batch.iter().any(|d| sort::is_order_file(Path::new(&d.path))) {
+                    // Only a manual order reads the order files
+                    let mut patches = index.set_sort(index.options.sort);
+                    patches.extend(index.apply(&batch));
+                    patches
+                } else {
+                    index.apply(&batch)
+                }
+            })
+            .unwrap_or_default();
+        schedule_git_refresh(&app_handle, key);
+        if !patches.is_empty() {
+            annotate_patches(&app_handle, key, &mut patches);
+            let _ = app_handle.emit_to(key.window.as_str(), "tree-patch", patches);
+        }
+        update_search_index(&app_handle, key, &batch, rules_changed);
+        update_link_graph(&app_handle, key, &batch, rules_changed);
+        let _ = app_handle.emit_to(key.window.as_str(), "refresh-files", batch);
+    }).map_err(|e| e.to_string())?;
+
+    app.state::<WatcherState>().0.lock().unwrap().insert(key, watcher);
+
+    Ok(FolderResult { path: path_string, tree })
+
+}
+
+// #[tauri::command]
+// fn get_directory_tree(path: String) -> Result<Vec<FileNode>, String> {
+//     let p = Path::new(&path);
+//     if p.exists() && p.is_dir() {
+//         Ok(read_dir_recursive(p))
+//     } else {
+//         Err("Invalid directory path".into())
+//     }
+// }
+
+fn directory_tree(path: &str, options: &TreeOptions) -> Result<Vec<FileNode>, String> {
+    let p = std::path::Path::new(path);
+    if p.exists() && p.is_dir() {
+        Ok(read_dir_with(p, options))
+    } else {
+        Err("Invalid directory path".into())
+    }
+}
+
+// Settings of a root folder apply to anything inside it, defaults elsewhere
+fn tree_options_for(tree_state: &TreeState, window: &str, path: &Path) -> TreeOptions {
+    tree_state.root_of(window, path).unwrap_or_else(|| TreeOptions::default_for(path))
+}
+
+// Root folders are answered from the watcher-maintained index, anything else is walked.
+// A `sort` given for a root folder sticks, so watcher patches keep that order.
+#[tauri::command]
+async fn get_directory_tree(app: AppHandle, window: Window, tree_state: State<'_, TreeState>, path: String, sort: Option<SortOrder>) -> Result<Vec<FileNode>, String> {
+
+    let key = RootKey { window: window.label().to_string(), root: PathBuf::from(&path) };
+    let indexed = tree_state.with_tree(&key, |index| {
+        if let Some(order) = sort.filter(|order| *order != index.options.sort) {
+            index.set_sort(order);
+        }
+        index.nodes.clone()
+    });
+    if let Some(mut nodes) = indexed {
+        annotate_git(&app, &key, &mut nodes);
+        return Ok(nodes);
+    }
+
+    let mut options = tree_options_for(&tree_state, window.label(), Path::new(&path));
+    if let Some(order) = sort {
+        options.sort = order;
+    }
 
-#[derive(serde::Serialize, Clone, Debug)]
-struct FileNode {
-    name: String,
-    path: String,
-    is_dir: bool,
-    children: Option<Vec<FileNode>>,
+    let key = RootKey { window: key.window, root: options.root.clone() };
+    let mut nodes = tokio::task::spawn_blocking(move || directory_tree(&path, &options))
+        .await
+        .map_err(|e| e.to_string())??;
+    annotate_git(&app, &key, &mut nodes);
+    Ok(nodes)
 }
 
-#[derive(serde::Serialize)]
-struct FolderResult {
-    path: String,
-    tree: Vec<FileNode>,
+// Lists `depth` levels below `path` (one by default) so the sidebar can expand
+// directories on demand instead of walking the whole folder up front
+#[tauri::command]
+async fn list_directory(app: AppHandle, window: Window, tree_state: State<'_, TreeState>, path: String, depth: Option<usize>) -> Result<Vec<FileNode>, String> {
+
+    let dir = PathBuf::from(&path);
+    let options = tree_options_for(&tree_state, window.label(), &dir);
+    let mut listing = tokio::task::spawn_blocking(move || {
+        if dir.is_dir() {
+            Ok(tree::list_directory(&dir, depth.unwrap_or(1), &options))
+        } else {
+            Err("Invalid directory path".to_string())
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    if let Some(root) = tree_state.root_of(window.label(), Path::new(&path)) {
+        let key = RootKey { window: window.label().to_string(), root: root.root };
+        tree_state.with_tree(&key, |index| index.graft(Path::new(&path), listing.clone()));
+        annotate_git(&app, &key, &mut listing);
+    }
+
+    Ok(listing)
+
 }
 
-#[derive(serde::Serialize)]
-struct OpenedFile {
-    path: String,
-    content: String,
+// Line and word counts of text files, for the ones the sidebar shows. Tree walks
+// only stat files, so the counts are read here when asked for.
+#[tauri::command]
+async fn get_text_counts(paths: Vec<String>) -> Result<HashMap<String, TextCounts>, String> {
+    tokio::task::spawn_blocking(move || {
+        paths
+            .into_iter()
+            .filter_map(|path| tree::count_text(Path::new(&path)).map(|counts| (path, counts)))
+            .collect()
+    })
+    .await
+    .map_err(|e| e.to_string())
 }
 
-#[derive(Clone, serde::Serialize)]
-struct FileChunk {
-    content: String,
-    is_last: bool,
+// Settings of the root folder `root`, or of the window's first one
+#[tauri::command]
+async fn get_workspace_settings(app: AppHandle, window: Window, tree_state: State<'_, TreeState>, root: Option<String>) -> Result<WorkspaceSettings, String> {
+
+    let root = pick_root(&tree_state, window.label(), root.as_deref())?.root;
+    let config_dir = app.path().app_config_dir().map_err(|e| e.to_string())?;
+    Ok(settings::load(&config_dir, &root))
+
+}
+
+// Persists the settings of a root folder and returns its tree rebuilt with them
+#[tauri::command]
+async fn set_workspace_settings(
+    app: AppHandle,
+    window: Window,
+    tree_state: State<'_, TreeState>,
+    settings: WorkspaceSettings,
+    root: Option<String>,
+) -> Result<Vec<FileNode>, String> {
+
+    let current = pick_root(&tree_state, window.label(), root.as_deref())?;
+    let key = RootKey { window: window.label().to_string(), root: current.root.clone() };
+    let depth = tree_state.with_tree(&key, |index| index.depth).ok_or("Folder is not open")?;
+
+    let mut options = TreeOptions::new(&key.root, &settings)?;
+    options.sort = current.sort;
+    let config_dir = app.path().app_config_dir().map_err(|e| e.to_string())?;
+    settings::store(&config_dir, &key.root, &settings)?;
+
+    let index = tokio::task::spawn_blocking(move || TreeIndex::build(options, depth))
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut tree = index.nodes.clone();
+    tree_state.with_tree(&key, |current| *current = index);
+    update_search_index(&app, &key, &[], true);
+    update_link_graph(&app, &key, &[], true);
+    annotate_git(&app, &key, &mut tree);
+
+    Ok(tree)
+
 }
 
-struct WatcherState(Mutex<Option<notify::RecommendedWatcher>>);
+// Searches the files shown in the window's root folders on a background thread and
+// returns the search id right away. Matches stream in as "search-results" events, one
+// per file, followed by a "search-done" summary. Starting a search cancels the last one.
+#[tauri::command]
+fn search_workspace(
+    window: Window,
+    tree_state: State<'_, TreeState>,
+    search_state: State<'_, SearchState>,
+    query: String,
+    options: Option<SearchOptions>,
+) -> Result<u64, String> {
+
+    let query = search::Query::new(&query, &options.unwrap_or_default())?;
+    let roots = tree_state.roots(window.label());
+    if roots.is_empty() {
+        return Err("No folder open".into());
+    }
+
+    let (id, cancel) = next_job(&search_state.0, window.label());
+
+    std::thread::spawn(move || {
+        let label = window.label().to_string();
+        let mut summary = SearchSummary { matches: 0, files: 0, cancelled: false, truncated: false };
+        for tree_options in roots {
+            let root = tree_options.root.clone();
+            let files = list_files(&root, &tree_options);
+            // The limit is shared by all roots
+            let budget = query.max_results() - summary.matches;
+            let found = search::search_within(&root, &files, &query, budget, &cancel, |matches| {
+                let _ = window.emit_to(label.as_str(), "search-results", SearchResults { id, matches });
+            });
+            summary.matches += found.matches;
+            summary.files += found.files;
+            summary.cancelled |= found.cancelled;
+            summary.truncated |= found.truncated;
+            if summary.cancelled || summary.truncated {
+                break;
+            }
+        }
+        let _ = window.emit_to(label.as_str(), "search-done", SearchDone { id, summary });
+    });
+
+    Ok(id)
+
+}
 
+// Checks the links of every shown note in the window's root folders on a background
+// thread and returns the check's id. Problems arrive per file as "link-check-results"
+// events, then a "link-check-done" event; with `report` set the whole result is
+// also written there as JSON.
 #[tauri::command]
-async fn pick_file(app: AppHandle) -> Result<Option<String>, String> {
+fn check_links(
+    window: Window,
+    tree_state: State<'_, TreeState>,
+    check_state: State<'_, LinkCheckState>,
+    report: Option<String>,
+) -> Result<u64, String> {
+
+    let roots = tree_state.roots(window.label());
+    if roots.is_empty() {
+        return Err("No folder open".into());
+    }
+    let (id, cancel) = next_job(&check_state.0, window.label());
+
+    std::thread::spawn(move || {
+        let label = window.label().to_string();
+        let mut all = Vec::new();
+        let mut summary = CheckSummary { issues: 0, files: 0, cancelled: false };
+        for tree_options in &roots {
+            let root = tree_options.root.clone();
+            let files = list_files(&root, tree_options);
+            let checked = link_check::check_files(&root, &files, &cancel, |issues| {
+                if report.is_some() {
+                    all.extend(issues.iter().cloned());
+                }
+                let _ = window.emit_to(label.as_str(), "link-check-results", LinkCheckResults { id, issues });
+            });
+            summary.issues += checked.issues;
+            summary.files += checked.files;
+            summary.cancelled |= checked.cancelled;
+            if summary.cancelled {
+                break;
+            }
+        }
+        let roots: Vec<PathBuf> = roots.into_iter().map(|options| options.root).collect();
+        let error = report
+            .as_ref()
+            .filter(|_| !summary.cancelled)
+            .and_then(|path| link_check::write_report(Path::new(path), &roots, &summary, &all).err());
+        let _ = window.emit_to(label.as_str(), "link-check-done", LinkCheckDone { id, summary, report, error });
+    });
 
-    let path = tokio::task::spawn_blocking(move || {
-        app.dialog()
-            .file()
-            .add_filter("Markdown", &["md"])
-            .blocking_pick_file()    
+    Ok(id)
+
+}
+
+#[tauri::command]
+fn cancel_link_check(window: Window, check_state: State<'_, LinkCheckState>) {
+    cancel_job(&check_state.0, window.label());
+}
+
+fn replace_journal_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app.path().app_data_dir().map_err(|e| e.to_string())?.join("replace-journal"))
+}
+
+// Plans a replace over the shown files of the window's root folders, off the async runtime
+async fn plan_replace(tree_state: &TreeState, window: &str, query: String, replacement: String, options: Option<SearchOptions>) -> Result<Vec<FileReplacement>, String> {
+
+    let query = search::Query::new(&query, &options.unwrap_or_default())?;
+    let roots = tree_state.roots(window);
+    if roots.is_empty() {
+        return Err("No folder open".into());
+    }
+
+    tokio::task::spawn_blocking(move || {
+        roots
+            .iter()
+            .flat_map(|tree_options| {
+                let files = list_files(&tree_options.root, tree_options);
+                replace::plan(&tree_options.root, &files, &query, &replacement)
+            })
+            .collect()
     })
     .await
-    .map_err(|e| e.to_string())?;
+    .map_err(|e| e.to_string())
 
-    match path {
-        Some(p) => Ok(Some(p.to_string())),
-        None => Ok(None),
-    }
+}
+
+// Dry run of a workspace replace: the changed lines of every file, nothing is written.
+// It replaces the window's previous preview.
+#[tauri::command]
+async fn preview_replace(
+    window: Window,
+    tree_state: State<'_, TreeState>,
+    preview_state: State<'_, ReplacePreviewState>,
+    query: String,
+    replacement: String,
+    options: Option<SearchOptions>,
+) -> Result<ReplacePreview, String> {
+
+    let files = plan_replace(&tree_state, window.label(), query, replacement, options).await?;
+
+    let mut previews = preview_state.0.lock().unwrap();
+    let id = previews.get(window.label()).map_or(1, |(last, _)| last + 1);
+    previews.insert(window.label().to_string(), (id, files.clone()));
+
+    Ok(ReplacePreview { id, files })
 
 }
 
+// Writes the changes of a preview, as shown. Files edited since abort the whole batch.
+// The returned batch id reverts it with `undo_replace`.
 #[tauri::command]
-async fn read_file_chunked(window: Window, path: String) -> Result<(), String> {
+async fn replace_in_workspace(app: AppHandle, window: Window, preview_state: State<'_, ReplacePreviewState>, preview: u64) -> Result<ReplaceBatch, String> {
 
-    let file = File::open(&path).await.map_err(|e| e.to_string())?;
-    let mut reader = BufReader::new(file);
-    let mut buffer = [0; 65536]; // 64KB buffer
+    let plans = {
+        let mut previews = preview_state.0.lock().unwrap();
+        match previews.get(window.label()) {
+            Some((id, _)) if *id == preview => previews.remove(window.label()).map(|(_, plans)| plans).unwrap_or_default(),
+            _ => return Err("The replace preview is out of date".into()),
+        }
+    };
+    let journal_dir = replace_journal_dir(&app)?;
 
-    loop {
-        
-        let bytes_read = reader.read(&mut buffer).await.map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || replace::apply(&journal_dir, &plans))
+        .await
+        .map_err(|e| e.to_string())?
 
-        // EOF
-        if bytes_read == 0 { 
-            window.emit("file-chunk", FileChunk {
-                content: "".to_string(),
-                is_last: true,
-            }).map_err(|e| e.to_string())?;
-            break;
+}
+
+#[tauri::command]
+async fn undo_replace(app: AppHandle, id: String, force: Option<bool>) -> Result<UndoResult, String> {
+    let journal_dir = replace_journal_dir(&app)?;
+    tokio::task::spawn_blocking(move || replace::undo(&journal_dir, &id, force.unwrap_or(false)))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// Ranked lookup in the search indexes of the window's root folders. Words must all appear;
+// "quoted words" match as a phrase and a trailing * matches any word with that prefix.
+#[tauri::command]
+fn query_index(window: Window, tree_state: State<'_, TreeState>, index_state: State<'_, IndexState>, query: String, limit: Option<usize>) -> Result<Vec<IndexHit>, String> {
+
+    let limit = limit.unwrap_or(50);
+    let indexes = index_state.0.lock().unwrap();
+    let mut per_root = Vec::new();
+    for options in tree_state.roots(window.label()) {
+        let key = RootKey { window: window.label().to_string(), root: options.root };
+        match indexes.get(&key) {
+            Some(index) => per_root.push(index),
+            None => return Err("Search index is not ready".into()),
         }
+    }
 
-        let chunk_str = String::from_utf8_lossy(&buffer[..bytes_read]).to_string();
+    Ok(search_index::query_all(&per_root, &query, limit))
 
-        window.emit("file-chunk", FileChunk {
-            content: chunk_str,
-            is_last: false,
-        }).map_err(|e| e.to_string())?;
+}
+
+// Runs `f` on the link graph of the window's root folder holding `path`
+fn with_graph<T>(window: &Window, tree_state: &TreeState, graph_state: &GraphState, path: &Path, f: impl FnOnce(&mut LinkGraph) -> T) -> Result<T, String> {
+    let root = tree_state.root_of(window.label(), path).ok_or("Path is not in an open folder")?.root;
+    let key = RootKey { window: window.label().to_string(), root };
+    graph_state.0.lock().unwrap().get_mut(&key).map(f).ok_or_else(|| "Link graph is not ready".into())
+}
 
+// Runs `f` on the link graph of every root folder of the window
+fn with_graphs<T>(window: &Window, tree_state: &TreeState, graph_state: &GraphState, mut f: impl FnMut(&mut LinkGraph) -> Vec<T>) -> Result<Vec<T>, String> {
+    let mut graphs = graph_state.0.lock().unwrap();
+    let mut found = Vec::new();
+    for options in tree_state.roots(window.label()) {
+        let key = RootKey { window: window.label().to_string(), root: options.root };
+        found.extend(f(graphs.get_mut(&key).ok_or("Link graph is not ready")?));
     }
+    Ok(found)
+}
 
-    Ok(())
+// Links to `path` from the other notes of its root folder
+#[tauri::command]
+fn get_backlinks(window: Window, tree_state: State<'_, TreeState>, graph_state: State<'_, GraphState>, path: String) -> Result<Vec<Edge>, String> {
+    with_graph(&window, &tree_state, &graph_state, Path::new(&path), |graph| graph.backlinks(Path::new(&path)))
+}
 
+#[tauri::command]
+fn get_outgoing_links(window: Window, tree_state: State<'_, TreeState>, graph_state: State<'_, GraphState>, path: String) -> Result<Vec<Edge>, String> {
+    with_graph(&window, &tree_state, &graph_state, Path::new(&path), |graph| graph.outgoing(Path::new(&path)))
 }
 
-fn read_dir_recursive(path: &Path) -> Vec<FileNode> {
-    let mut nodes = Vec::new();
-    if let Ok(entries) = fs::read_dir(path) {
-        for entry in entries.flatten() {
-            let p = entry.path();
-            let name = p.file_name().unwrap_or_default().to_string_lossy().to_string();
+// Notes of the window's root folders that no other note links to
+#[tauri::command]
+fn get_orphan_notes(window: Window, tree_state: State<'_, TreeState>, graph_state: State<'_, GraphState>) -> Result<Vec<String>, String> {
+    with_graphs(&window, &tree_state, &graph_state, |graph| graph.orphans())
+}
 
-            if name.starts_with('.') {
-                continue;
-            }
+// Links to missing files from `path`, or from every note when no path is given
+#[tauri::command]
+fn get_broken_links(window: Window, tree_state: State<'_, TreeState>, graph_state: State<'_, GraphState>, path: Option<String>) -> Result<Vec<Edge>, String> {
+    match path {
+        Some(path) => with_graph(&window, &tree_state, &graph_state, Path::new(&path), |graph| graph.broken(Some(Path::new(&path)))),
+        None => with_graphs(&window, &tree_state, &graph_state, |graph| graph.broken(None)),
+    }
+}
 
-            if p.is_dir() {
-                let children = read_dir_recursive(&p);
-                nodes.push(FileNode {
-                    name,
-                    path: p.to_string_lossy().to_string(),
-                    is_dir: true,
-                    children: Some(children),
-                });
-            }
-            else if p.extension().map_or(false, |ext| ext == "md") {
-                nodes.push(FileNode {
-                    name,
-                    path: p.to_string_lossy().to_string(),
-                    is_dir: false,
-                    children: None,
-                });
+// Runs `git status` for a root folder, keeps what the sidebar shows from it and sends it
+// to the window as a "git-status" event, which also covers tree patches made before it.
+// Folders outside a repository have no status.
+fn refresh_git(app: &AppHandle, key: &RootKey) -> Option<RepoStatus> {
+
+    let known = app.state::<GitState>().0.lock().unwrap().get(key).map(|git| git.repo.clone());
+    let repo = known.or_else(|| Repo::discover(&key.root));
+    let status = repo.as_ref().map(|repo| repo.status());
+
+    let state = app.state::<GitState>();
+    let mut git_state = state.0.lock().unwrap();
+    match (repo, status) {
+        (Some(repo), Some(Ok(status))) => {
+            git_state.insert(key.clone(), GitRoot { repo, annotations: Annotations::new(&status) });
+            drop(git_state);
+            let event = GitStatusEvent { root: key.root.to_string_lossy().to_string(), status: status.clone() };
+            let _ = app.emit_to(key.window.as_str(), "git-status", event);
+            Some(status)
+        }
+        (_, status) => {
+            if let Some(Err(e)) = status {
+                println!("git error: {}", e);
             }
+            git_state.remove(key);
+            None
         }
     }
 
-    nodes.sort_by(|a, b | {
-        match (a.is_dir, b.is_dir) {
-            (true, false) => std::cmp::Ordering::Less,
-            (false, true) => std::cmp::Ordering::Greater,
-            _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+}
+
+// Runs `refresh_git` once for every request made within GIT_REFRESH_DELAY, so a burst
+// of watcher batches starts one `git status`. Requests made while it runs schedule
+// another. Folders closed meanwhile are skipped.
+fn schedule_git_refresh(app: &AppHandle, key: &RootKey) {
+
+    if !app.state::<GitRefreshState>().0.lock().unwrap().insert(key.clone()) {
+        return;
+    }
+
+    let app = app.clone();
+    let key = key.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(GIT_REFRESH_DELAY);
+        app.state::<GitRefreshState>().0.lock().unwrap().remove(&key);
+        if app.state::<TreeState>().with_tree(&key, |_| ()).is_some() {
+            refresh_git(&app, &key);
         }
     });
 
-    nodes
-
 }
 
-#[tauri::command]
-async fn load_file(path: String) -> Result<String, String> {
-    
-    // Convert the string path to PathBuf
-    let p = PathBuf::from(&path);
-
-    // canonicalize
-    let actual_path = p.canonicalize().map_err(|_| format!("File not found or invalid path: {}", path))?;
+// Refreshes the status of a root folder whenever HEAD or the index of its repository change
+fn watch_git(app: &AppHandle, key: &RootKey) {
+
+    let repo = match app.state::<GitState>().0.lock().unwrap().get(key) {
+        Some(git) => git.repo.clone(),
+        None => return,
+    };
+    let git_dir = match repo.git_dir() {
+        Ok(dir) => dir,
+        Err(e) => return println!("git error: {}", e),
+    };
+
+    let is_state = |path: &Path, _| matches!(path.file_name().and_then(|name| name.to_str()), Some("HEAD" | "index"));
+    let app_handle = app.clone();
+    let key_for_batch = key.clone();
+    match watch::watch_entries(&git_dir, watch::DEFAULT_FOLDER_DEBOUNCE, is_state, move |_| {
+        schedule_git_refresh(&app_handle, &key_for_batch);
+    }) {
+        Ok(watcher) => {
+            app.state::<GitWatcherState>().0.lock().unwrap().insert(key.clone(), watcher);
+        }
+        Err(e) => println!("watch error: {}", e),
+    }
 
-    fs::read_to_string(actual_path).map_err(|e| e.to_string())
+}
 
+fn annotate_git(app: &AppHandle, key: &RootKey, nodes: &mut [FileNode]) {
+    if let Some(git) = app.state::<GitState>().0.lock().unwrap().get(key) {
+        git::annotate(nodes, &git.annotations);
+    }
 }
 
-// Saves content to a specified file path
-#[tauri::command]
-async fn save_file(_app: AppHandle, path: String, content: String) -> Result<(), String> {
-    
-    let p = PathBuf::from(&path);
+fn annotate_patches(app: &AppHandle, key: &RootKey, patches: &mut [TreePatch]) {
+    for patch in patches {
+        match patch {
+            TreePatch::Insert { node, .. } | TreePatch::Rename { node, .. } | TreePatch::Update { node, .. } => {
+                annotate_git(app, key, std::slice::from_mut(node));
+            }
+            TreePatch::Reset { tree } => annotate_git(app, key, tree),
+            TreePatch::Remove { .. } => {}
+        }
+    }
+}
 
-    if let Some(parent) = p.parent() {
-        parent.canonicalize().map_err(|_| "Invalid destination directory")?;
+// After staging or committing, every root of the window in that repository
+fn refresh_window_git(app: &AppHandle, window: &str, repo: &Repo) {
+    for options in app.state::<TreeState>().roots(window) {
+        if options.root.starts_with(&repo.workdir) {
+            refresh_git(app, &RootKey { window: window.to_string(), root: options.root });
+        }
     }
+}
 
-    fs::write(p, content).map_err(|e| e.to_string())
+fn repo_of(path: &Path) -> Result<Repo, String> {
+    Repo::discover(path).ok_or_else(|| format!("{} is not in a git repository", path.display()))
+}
 
+// Status of the repository of a root folder, or of the window's first one. None when
+// the folder is not in a repository.
+#[tauri::command]
+async fn git_status(app: AppHandle, window: Window, tree_state: State<'_, TreeState>, root: Option<String>) -> Result<Option<RepoStatus>, String> {
+    let key = RootKey { window: window.label().to_string(), root: pick_root(&tree_state, window.label(), root.as_deref())?.root };
+    tokio::task::spawn_blocking(move || refresh_git(&app, &key))
+        .await
+        .map_err(|e| e.to_string())
 }
 
+// Changed lines of a file against HEAD, for the editor gutter. `content` is the
+// unsaved text when given, otherwise the file on disk is compared.
 #[tauri::command]
-async fn open_folder_and_list_files(app: AppHandle) -> Result<FolderResult, String> {
-    
-    let app_for_dialog = app.clone();
-    
-    let folder_path = tokio::task::spawn_blocking(move || {
-        app_for_dialog.dialog()
-            .file()
-            .blocking_pick_folder()
+async fn git_gutter(path: String, content: Option<String>) -> Result<Vec<GutterMark>, String> {
+    tokio::task::spawn_blocking(move || match Repo::discover(Path::new(&path)) {
+        Some(repo) => repo.gutter(Path::new(&path), content.as_deref()),
+        None => Ok(Vec::new()),
     })
     .await
-    .map_err(|e| e.to_string())?;
+    .map_err(|e| e.to_string())?
+}
 
-    match folder_path {
-        Some(path) => {
-            let path_string = path.to_string();
-            let path_buf = std::path::PathBuf::from(&path_string);
-
-            // Set-up Watcher
-            let app_handle = app.clone();
-            let path_to_watch = path_buf.clone();
-
-            let mut watcher = notify::recommended_watcher(move | res: Result<notify::Event, notify::Error> | {
-                match res {
-                    // Ok(_) => { let _ = app_handle.emit("refresh-files", ()); },
-                    Ok(event) => {
-                        if event.kind.is_modify() || event.kind.is_create() || event.kind.is_remove() {
-                            let _ = app_handle.emit("refresh-files", ());
-                        }
-                    },
-                    Err(e) => println!("watch error: {:?}", e),
-                }
-            }).map_err(|e| e.to_string())?;
+#[tauri::command]
+async fn git_stage(app: AppHandle, window: Window, paths: Vec<String>) -> Result<(), String> {
+    tokio::task::spawn_blocking(move || {
+        let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+        let repo = repo_of(paths.first().ok_or("Nothing to stage")?)?;
+        repo.stage(&paths)?;
+        refresh_window_git(&app, window.label(), &repo);
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn git_unstage(app: AppHandle, window: Window, paths: Vec<String>) -> Result<(), String> {
+    tokio::task::spawn_blocking(move || {
+        let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+        let repo = repo_of(paths.first().ok_or("Nothing to unstage")?)?;
+        repo.unstage(&paths)?;
+        refresh_window_git(&app, window.label(), &repo);
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
 
-            // Changed to Recursive watching
-            watcher.watch(&path_to_watch, RecursiveMode::Recursive).map_err(|e| e.to_string())?;
+// Commits what is staged in the repository of a root folder and returns the commit id
+#[tauri::command]
+async fn git_commit(app: AppHandle, window: Window, tree_state: State<'_, TreeState>, root: Option<String>, message: String) -> Result<String, String> {
+    let root = pick_root(&tree_state, window.label(), root.as_deref())?.root;
+    tokio::task::spawn_blocking(move || {
+        let repo = repo_of(&root)?;
+        let id = repo.commit(&message)?;
+        refresh_window_git(&app, window.label(), &repo);
+        Ok(id)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
 
-            let state = app.state::<WatcherState>();
-            let mut managed_watch = state.0.lock().unwrap();
-            *managed_watch = Some(watcher);
+// Commits that touched a file, newest first, 100 unless `max_count` says otherwise
+#[tauri::command]
+async fn git_log(path: String, max_count: Option<usize>) -> Result<Vec<Commit>, String> {
+    tokio::task::spawn_blocking(move || repo_of(Path::new(&path))?.log(Path::new(&path), max_count.unwrap_or(100)))
+        .await
+        .map_err(|e| e.to_string())?
+}
 
-            let tree = read_dir_recursive(&path_buf);
+#[tauri::command]
+fn cancel_search(window: Window, search_state: State<'_, SearchState>) {
+    cancel_job(&search_state.0, window.label());
+}
 
-            Ok(FolderResult { path: path_string, tree: tree })
+// Brings the tree index in line with a change made by one of the file commands, so
+// the sidebar gets its patch right away. The watcher sees the same change later and
+// finds nothing left to do.
+fn patch_tree(tree_state: &TreeState, window: &str, changes: &[(&Path, DeltaKind)]) -> Vec<TreePatch> {
+    let mut patches = Vec::new();
+    for (path, kind) in changes {
+        if let Some(options) = tree_state.root_of(window, path) {
+            let key = RootKey { window: window.to_string(), root: options.root };
+            let delta = PathDelta { path: path.to_string_lossy().to_string(), kind: *kind, renamed_from: None };
+            patches.extend(tree_state.with_tree(&key, |index| index.apply(&[delta])).unwrap_or_default());
         }
-        None => Err("cancelled".into()),
     }
+    patches
 }
 
-// #[tauri::command]
-// fn get_directory_tree(path: String) -> Result<Vec<FileNode>, String> {
-//     let p = Path::new(&path);
-//     if p.exists() && p.is_dir() {
-//         Ok(read_dir_recursive(p))
-//     } else {
-//         Err("Invalid directory path".into())
-//     }
-// }
+#[tauri::command]
+async fn new_file(window: Window, tree_state: State<'_, TreeState>, path: String) -> Result<Vec<TreePatch>, FileOpError> {
+    let path = PathBuf::from(path);
+    let created = path.clone();
+    tokio::task::spawn_blocking(move || file_ops::create_file(&created))
+        .await
+        .map_err(|e| FileOpError::Io(e.to_string()))??;
+    Ok(patch_tree(&tree_state, window.label(), &[(&path, DeltaKind::Created)]))
+}
 
 #[tauri::command]
-async fn get_directory_tree(path: String) -> Result<Vec<FileNode>, String> {
+async fn new_folder(window: Window, tree_state: State<'_, TreeState>, path: String) -> Result<Vec<TreePatch>, FileOpError> {
+    let path = PathBuf::from(path);
+    let created = path.clone();
+    tokio::task::spawn_blocking(move || file_ops::create_folder(&created))
+        .await
+        .map_err(|e| FileOpError::Io(e.to_string()))??;
+    Ok(patch_tree(&tree_state, window.label(), &[(&path, DeltaKind::Created)]))
+}
+
+// Links across the root folder of `from` that moving it to `to` would break, and their fix
+async fn plan_link_updates(tree_state: &TreeState, window: &str, from: PathBuf, to: PathBuf) -> Result<Vec<FileReplacement>, String> {
+
+    let tree_options = tree_state.root_of(window, &from).ok_or("Path is not in an open folder")?;
+
     tokio::task::spawn_blocking(move || {
-        let p = std::path::Path::new(&path);
-        if p.exists() && p.is_dir() {
-            Ok(read_dir_recursive(p))
-        } else {
-            Err("Invalid directory path".into())
-        }
+        let root = tree_options.root.clone();
+        let files = list_files(&root, &tree_options);
+        links::plan_move(&root, &files, &from, &to)
     })
     .await
-    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+
+}
+
+// Preview for a rename or move with `update_links`: the files whose links would change
+#[tauri::command]
+async fn preview_move_links(window: Window, tree_state: State<'_, TreeState>, from: String, to: String) -> Result<Vec<FileReplacement>, String> {
+    plan_link_updates(&tree_state, window.label(), PathBuf::from(from), PathBuf::from(to)).await
+}
+
+// Carries what is known about the files below `from` over to their path after a
+// move to `to`, so their next save is not refused and their watch follows them
+fn follow_move(app: &AppHandle, from: &Path, to: &Path) {
+    app.state::<OpenFiles>().rename(from, to);
+    let watched: Vec<(String, PathBuf)> = app
+        .state::<FileWatcherState>()
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(window, (path, _))| links::moved(path, from, to).map(|moved| (window.clone(), moved)))
+        .collect();
+    for (window, path) in watched {
+        watch_open_file(app, &window, &path);
+    }
+}
+
+// Moves `from` to `to`, then rewrites the links the move would break. The rewrite
+// is planned before the move, while links still resolve, and written after it.
+async fn move_and_relink(app: &AppHandle, window: &str, from: PathBuf, to: PathBuf, force: bool, update_links: bool) -> Result<MoveResult, FileOpError> {
+
+    let tree_state = app.state::<TreeState>();
+    let plans = if update_links {
+        plan_link_updates(&tree_state, window, from.clone(), to.clone()).await.map_err(FileOpError::Io)?
+    } else {
+        Vec::new()
+    };
+
+    // Replacing with `force` may trash a whole folder, so this runs on a blocking thread
+    let (moved_from, moved_to) = (from.clone(), to.clone());
+    tokio::task::spawn_blocking(move || file_ops::rename(&moved_from, &moved_to, force))
+        .await
+        .map_err(|e| FileOpError::Io(e.to_string()))??;
+    follow_move(app, &from, &to);
+    // A replaced path leaves the tree too, hence the extra removal
+    let patches = patch_tree(&tree_state, window, &[(&from, DeltaKind::Removed), (&to, DeltaKind::Removed), (&to, DeltaKind::Created)]);
+    if plans.is_empty() {
+        return Ok(MoveResult { patches, links: None, links_error: None });
+    }
+
+    // The move cannot be taken back without the caller knowing, so a failed rewrite
+    // is reported next to the patches instead of replacing them. Open files it
+    // rewrites keep their fingerprint, so a save of the stale buffer is a conflict.
+    let applied = match replace_journal_dir(app) {
+        Ok(journal_dir) => tokio::task::spawn_blocking(move || links::apply_move(&journal_dir, plans, &from, &to))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|result| result),
+        Err(e) => Err(e),
+    };
+
+    Ok(match applied {
+        Ok(batch) => MoveResult { patches, links: Some(batch), links_error: None },
+        Err(e) => MoveResult { patches, links: None, links_error: Some(e) },
+    })
+
+}
+
+// Refuses to replace an existing path unless `force` is set. With `update_links`,
+// links to and from the renamed path are rewritten across its root folder.
+#[tauri::command]
+async fn rename_path(
+    app: AppHandle,
+    window: Window,
+    from: String,
+    to: String,
+    force: Option<bool>,
+    update_links: Option<bool>,
+) -> Result<MoveResult, FileOpError> {
+    move_and_relink(&app, window.label(), PathBuf::from(from), PathBuf::from(to), force.unwrap_or(false), update_links.unwrap_or(false)).await
+}
+
+// Drag and drop in the sidebar: moves `path` into the folder `dir`
+#[tauri::command]
+async fn move_path(
+    app: AppHandle,
+    window: Window,
+    path: String,
+    dir: String,
+    force: Option<bool>,
+    update_links: Option<bool>,
+) -> Result<MoveResult, FileOpError> {
+    let from = PathBuf::from(path);
+    let to = file_ops::destination_in(&from, Path::new(&dir))?;
+    move_and_relink(&app, window.label(), from, to, force.unwrap_or(false), update_links.unwrap_or(false)).await
+}
+
+#[tauri::command]
+async fn duplicate_path(window: Window, tree_state: State<'_, TreeState>, path: String) -> Result<Vec<TreePatch>, FileOpError> {
+    let copy = tokio::task::spawn_blocking(move || file_ops::duplicate(Path::new(&path)))
+        .await
+        .map_err(|e| FileOpError::Io(e.to_string()))??;
+    Ok(patch_tree(&tree_state, window.label(), &[(&copy, DeltaKind::Created)]))
+}
+
+// Moves a file or folder to the trash rather than deleting it
+#[tauri::command]
+async fn trash_path(window: Window, tree_state: State<'_, TreeState>, path: String) -> Result<Vec<TreePatch>, FileOpError> {
+    let path = PathBuf::from(path);
+    let trashed = path.clone();
+    tokio::task::spawn_blocking(move || file_ops::trash(&trashed))
+        .await
+        .map_err(|e| FileOpError::Io(e.to_string()))??;
+    Ok(patch_tree(&tree_state, window.label(), &[(&path, DeltaKind::Removed)]))
+}
+
+// Saves the manual order of a folder's entries. In the opened folder the watcher
+// picks the sidecar up and sends the re-sorted tree as a patch.
+#[tauri::command]
+async fn set_manual_order(path: String, names: Vec<String>) -> Result<(), String> {
+    let dir = PathBuf::from(&path);
+    if !dir.is_dir() {
+        return Err("Invalid directory path".into());
+    }
+    tokio::task::spawn_blocking(move || sort::write_order(&dir, &names))
+        .await
+        .map_err(|e| e.to_string())?
 }
 
 // Opens a file dialog to select a markdown file and reads its content
 #[tauri::command]
-async fn open_file(app: AppHandle) -> Result<OpenedFile, String> {
+async fn open_file(app: AppHandle, window: Window) -> Result<OpenedFile, String> {
+
+    let app_for_dialog = app.clone();
 
     let path = tokio::task::spawn_blocking(move || {
-        app.dialog()
+        app_for_dialog.dialog()
             .file()
             .add_filter("Markdown", &["md"])
             .blocking_pick_file()  
@@ -255,6 +1907,11 @@ async fn open_file(app: AppHandle) -> Result<OpenedFile, String> {
             let content_str = tokio::fs::read_to_string(&path_str)
                 .await
                 .map_err(|e| e.to_string())?;
+            if let Ok(meta) = fs::metadata(&path_str) {
+                let fingerprint = fingerprint_with_hash(&meta, save::content_hash(content_str.as_bytes()));
+                app.state::<OpenFiles>().record(Path::new(&path_str), fingerprint);
+            }
+            watch_open_file(&app, window.label(), Path::new(&path_str));
             Ok(OpenedFile {
                 path: path_str,
                 content: content_str,
@@ -269,23 +1926,33 @@ async fn open_file(app: AppHandle) -> Result<OpenedFile, String> {
 
 // Opens a save file dialog and saves the provided text to the selected file
 #[tauri::command]
-async fn save_file_dialog(app: AppHandle, text: String) -> Result<String, String> {
+async fn save_file_dialog(app: AppHandle, window: Window, text: String) -> Result<String, SaveError> {
+    let app_for_dialog = app.clone();
     let path = tokio::task::spawn_blocking(move || {
-        app.dialog()
+        app_for_dialog.dialog()
             .file()
             .add_filter("Markdown", &["md"])
             .blocking_save_file()
     })
     .await
-    .map_err(|e| e.to_string())?;
+    .map_err(|e| SaveError::Write(e.to_string()))?;
 
     match path {
         Some(p) => {
             let path_str = p.to_string();
-            tokio::fs::write(&path_str, text).await.map_err(|e| e.to_string())?;
+            let app_for_write = app.clone();
+            let written = path_str.clone();
+            tokio::task::spawn_blocking(move || {
+                write_atomic(Path::new(&written), text.as_bytes())?;
+                app_for_write.state::<OpenFiles>().record_written(Path::new(&written), text.as_bytes());
+                Ok::<(), SaveError>(())
+            })
+            .await
+            .map_err(|e| SaveError::Write(e.to_string()))??;
+            watch_open_file(&app, window.label(), Path::new(&path_str));
             Ok(path_str)
         }
-        None => Err("cancelled".into()),
+        None => Err(SaveError::Cancelled),
     }
 }
 
@@ -351,10 +2018,39 @@ pub fn run() {
             );
         }
 
+        if let Some(dir) = RECOVERY_DIR.get() {
+            recovery::mark_panicked(dir);
+        }
+
     }));
 
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let launch_requests = launch::requests_from_args(&args, &cwd);
+
     Builder::default()
-        .manage(WatcherState(Mutex::new(None)))
+        // Registered first so a second launch exits before setting anything up
+        .plugin(tauri_plugin_single_instance::init(|app, argv, cwd| {
+            open_requests(app, launch::requests_from_args(argv.get(1..).unwrap_or_default(), Path::new(&cwd)));
+        }))
+        .manage(LaunchState(Mutex::new(Some(PendingLaunch { window: "main".into(), requests: launch_requests }))))
+        .manage(SessionState(Mutex::new(Session::default())))
+        .manage(MissingPaths(Mutex::new(HashMap::new())))
+        .manage(WatcherState(Mutex::new(HashMap::new())))
+        .manage(FileWatcherState(Mutex::new(HashMap::new())))
+        .manage(TreeState(Mutex::new(HashMap::new())))
+        .manage(OpenFiles::default())
+        .manage(SearchState(Mutex::new(HashMap::new())))
+        .manage(IndexState(Mutex::new(HashMap::new())))
+        .manage(IndexPersistState(Mutex::new(HashSet::new())))
+        .manage(GraphState(Mutex::new(HashMap::new())))
+        .manage(LinkCheckState(Mutex::new(HashMap::new())))
+        .manage(ReplacePreviewState(Mutex::new(HashMap::new())))
+        .manage(RecoveryState(Mutex::new(Vec::new())))
+        .manage(HistoryState(Mutex::new(None)))
+        .manage(GitState(Mutex::new(HashMap::new())))
+        .manage(GitWatcherState(Mutex::new(HashMap::new())))
+        .manage(GitRefreshState(Mutex::new(HashSet::new())))
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_clipboard_manager::init())
@@ -370,6 +2066,10 @@ pub fn run() {
                         .id("new")
                         .accelerator("CmdOrCtrl+N")
                         .build(app)?,
+                    &MenuItemBuilder::new("New Window")
+                        .id("new_window")
+                        .accelerator("CmdOrCtrl+Shift+N")
+                        .build(app)?,
                     &PredefinedMenuItem::separator(app)?,
                     &MenuItemBuilder::new("Open…")
                         .id("open")
@@ -379,6 +2079,9 @@ pub fn run() {
                         .id("open_folder")
                         .accelerator("CmdOrCtrl+Shift+O")
                         .build(app)?,
+                    &MenuItemBuilder::new("Add Folder to Workspace...")
+                        .id("add_folder")
+                        .build(app)?,
                     &MenuItemBuilder::new("Save")
                         .id("save")
                         .accelerator("CmdOrCtrl+S")
@@ -448,45 +2151,146 @@ pub fn run() {
                 )?;
 
             app.set_menu(Menu::with_items(app, &[&file_menu, &edit_menu, &window_menu])?)?;
+            restore_session(app.handle());
+            expire_history(app.handle());
+
+            // Snapshots outlive a clean run only when the frontend forgot them
+            if let Ok(data_dir) = app.path().app_data_dir() {
+                let dir = RECOVERY_DIR.get_or_init(|| data_dir.join("recovery"));
+                if recovery::start(dir) {
+                    *app.state::<RecoveryState>().0.lock().unwrap() = recovery::recoverable(dir);
+                } else {
+                    recovery::clear(dir);
+                }
+            }
 
             Ok(())
         })
         .on_menu_event(|app, event| {
-            if let Some(win) = app.get_webview_window("main") {
+            if event.id().as_ref() == "new_window" {
+                if let Err(e) = new_window(app) {
+                    println!("window error: {}", e);
+                }
+                return;
+            }
+            // Menu commands go to the window they were picked in
+            if let Some(win) = active_window(app) {
+                let label = win.label().to_string();
+                let emit = |event: &str| app.emit_to(label.as_str(), event, ());
                 let _ = match event.id().as_ref() {
-                    "new" => win.emit("menu-new", ()),
-                    "open" => win.emit("menu-open", ()),
-                    "open_folder" => win.emit("menu-open-folder", ()),
-                    "save" => win.emit("menu-save", ()),
-                    "quit" => Ok(app.exit(0)),
-                    "menu-export-html" => win.emit("menu-export-html", ()),
-                    "menu-print-pdf" => win.emit("menu-print-pdf", ()),
-                    "undo" => win.emit("undo", ()),
-                    "redo" => win.emit("redo", ()),
-                    "cut" => win.emit("cut", ()),
-                    "copy" => win.emit("copy", ()),
-                    "paste" => win.emit("paste", ()),
-                    "select-all" => win.emit("select-all", ()),
-                    "toggle-sidebar" => win.emit("menu-toggle-sidebar", ()),
+                    "new" => emit("menu-new"),
+                    "open" => emit("menu-open"),
+                    "open_folder" => emit("menu-open-folder"),
+                    "add_folder" => emit("menu-add-folder"),
+                    "save" => emit("menu-save"),
+                    "quit" => {
+                        app.exit(0);
+                        Ok(())
+                    }
+                    "menu-export-html" => emit("menu-export-html"),
+                    "menu-print-pdf" => emit("menu-print-pdf"),
+                    "undo" => emit("undo"),
+                    "redo" => emit("redo"),
+                    "cut" => emit("cut"),
+                    "copy" => emit("copy"),
+                    "paste" => emit("paste"),
+                    "select-all" => emit("select-all"),
+                    "toggle-sidebar" => emit("menu-toggle-sidebar"),
                     _ => Ok(()),
                 };
             }
         })
+        .on_window_event(|window, event| match event {
+            // Closing one of several windows drops it from the session; the last one stays
+            tauri::WindowEvent::CloseRequested { .. } if window.app_handle().webview_windows().len() > 1 => {
+                window.app_handle().state::<SessionState>().0.lock().unwrap().remove(window.label());
+                store_session(window.app_handle());
+            }
+            tauri::WindowEvent::Destroyed => forget_window(window.app_handle(), window.label()),
+            _ => {}
+        })
         .invoke_handler(generate_handler![
             open_file,
             save_file,
             save_file_dialog,
+            render_html,
             clipboard_write,
             clipboard_read,
             load_file,
             open_folder_and_list_files,
+            open_folder,
+            close_folder,
+            take_launch_requests,
+            get_window_session,
+            list_open_folders,
+            save_window_session,
             get_directory_tree,
+            list_directory,
+            get_text_counts,
+            get_workspace_settings,
+            set_workspace_settings,
+            set_manual_order,
+            search_workspace,
+            cancel_search,
+            query_index,
+            get_backlinks,
+            get_outgoing_links,
+            get_orphan_notes,
+            get_broken_links,
+            check_links,
+            cancel_link_check,
+            preview_replace,
+            replace_in_workspace,
+            undo_replace,
+            new_file,
+            new_folder,
+            rename_path,
+            move_path,
+            preview_move_links,
+            duplicate_path,
+            trash_path,
             read_file_chunked,
             pick_file,
             log_crash,
+            file_fingerprint,
+            diff_with_disk,
+            close_file,
+            journal_buffer,
+            discard_buffer,
+            take_recoverable_buffers,
+            list_versions,
+            read_version,
+            diff_versions,
+            restore_version,
+            get_history_retention,
+            set_history_retention,
+            git_status,
+            git_gutter,
+            git_stage,
+            git_unstage,
+            git_commit,
+            git_log,
         ])
-        .run(generate_context!())
-        .expect("error while running tauri application");
+        .build(generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::ExitRequested { .. } = &event {
+                store_session(app);
+                if let Some(dir) = RECOVERY_DIR.get() {
+                    recovery::finish(dir);
+                }
+            }
+            // macOS hands files opened from Finder to the running app instead of argv
+            #[cfg(any(target_os = "macos", target_os = "ios"))]
+            if let tauri::RunEvent::Opened { urls } = event {
+                let requests = urls
+                    .iter()
+                    .filter_map(|url| url.to_file_path().ok())
+                    .filter_map(|path| launch::request_for(&path))
+                    .collect();
+                open_requests(app, requests);
+            }
+        });
 }
 
 #[cfg(test)]
@@ -494,6 +2298,7 @@ mod tests {
     use super::*;
     use std::fs;
     use tempfile::TempDir;
+    use crate::tree::read_dir_recursive;
     use serial_test::serial;
 
     // ===== File Operation Tests =====
@@ -506,7 +2311,7 @@ mod tests {
         
         fs::write(&file_path, content).expect("Failed to write test file");
 
-        let result = futures::executor::block_on(load_file(file_path.to_string_lossy().to_string()));
+        let result = read_file(&file_path.to_string_lossy());
         
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), content);
@@ -514,7 +2319,7 @@ mod tests {
 
     #[test]
     fn test_load_file_not_found() {
-        let result = futures::executor::block_on(load_file("/nonexistent/path/file.md".to_string()));
+        let result = read_file("/nonexistent/path/file.md");
         
         assert!(result.is_err());
     }
@@ -526,7 +2331,7 @@ mod tests {
         
         fs::write(&file_path, "").expect("Failed to write empty file");
 
-        let result = futures::executor::block_on(load_file(file_path.to_string_lossy().to_string()));
+        let result = read_file(&file_path.to_string_lossy());
         
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "");
@@ -682,7 +2487,7 @@ mod tests {
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
         fs::write(temp_dir.path().join("test.md"), "content").expect("Failed to create file");
 
-        let result = get_directory_tree(temp_dir.path().to_string_lossy().to_string());
+        let result = directory_tree(&temp_dir.path().to_string_lossy(), &TreeOptions::default_for(temp_dir.path()));
 
         assert!(result.is_ok());
         let tree = result.unwrap();
@@ -692,7 +2497,7 @@ mod tests {
 
     #[test]
     fn test_get_directory_tree_nonexistent_path() {
-        let result = get_directory_tree("/nonexistent/directory".to_string());
+        let result = directory_tree("/nonexistent/directory", &TreeOptions::default_for(Path::new("/nonexistent/directory")));
 
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), "Invalid directory path");
@@ -704,7 +2509,7 @@ mod tests {
         let file_path = temp_dir.path().join("test.md");
         fs::write(&file_path, "content").expect("Failed to create file");
 
-        let result = get_directory_tree(file_path.to_string_lossy().to_string());
+        let result = directory_tree(&file_path.to_string_lossy(), &TreeOptions::default_for(&file_path));
 
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), "Invalid directory path");
@@ -818,6 +2623,11 @@ mod tests {
             path: "/path/to/test.md".to_string(),
             is_dir: false,
             children: None,
+            has_children: false,
+            is_symlink: false,
+            link_target: None,
+            meta: None,
+            git_status: None,
         };
 
         let json = serde_json::to_string(&node).expect("Failed to serialize FileNode");
@@ -832,6 +2642,11 @@ mod tests {
             path: "/path/to/child.md".to_string(),
             is_dir: false,
             children: None,
+            has_children: false,
+            is_symlink: false,
+            link_target: None,
+            meta: None,
+            git_status: None,
         };
 
         let parent = FileNode {
@@ -839,6 +2654,11 @@ mod tests {
             path: "/path/to/parent".to_string(),
             is_dir: true,
             children: Some(vec![child]),
+            has_children: true,
+            is_symlink: false,
+            link_target: None,
+            meta: None,
+            git_status: None,
         };
 
         let json = serde_json::to_string(&parent).expect("Failed to serialize FileNode");
@@ -854,6 +2674,11 @@ mod tests {
             path: "/path/test.md".to_string(),
             is_dir: false,
             children: None,
+            has_children: false,
+            is_symlink: false,
+            link_target: None,
+            meta: None,
+            git_status: None,
         }];
 
         let result = FolderResult {
@@ -876,7 +2701,7 @@ mod tests {
 
         fs::write(&file_path, content).expect("Failed to write file");
 
-        let result = futures::executor::block_on(load_file(file_path.to_string_lossy().to_string()));
+        let result = read_file(&file_path.to_string_lossy());
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), content);
     }
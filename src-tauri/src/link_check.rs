@@ -0,0 +1,324 @@
+use std::fs;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::links::{decode, is_external, parse, Link, LinkKind, Notes};
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    MissingFile,
+    MissingAnchor,
+    MissingImage,
+    MalformedUrl,
+}
+
+impl Problem {
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Problem::MissingFile => "missing file",
+            Problem::MissingAnchor => "missing heading",
+            Problem::MissingImage => "missing image",
+            Problem::MalformedUrl => "malformed URL",
+        }
+    }
+
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct LinkIssue {
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    pub problem: Problem,
+    // The destination as written, with its #anchor
+    pub target: String,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct CheckSummary {
+    pub issues: usize,
+    pub files: usize,
+    pub cancelled: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct LinkReport<'a> {
+    pub roots: Vec<String>,
+    pub summary: &'a CheckSummary,
+    pub issues: &'a [LinkIssue],
+}
+
+// GitHub's heading ids: lowercased, punctuation dropped and spaces turned into dashes
+pub fn slug(heading: &str) -> String {
+    heading
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+// Ids of the ATX headings of a note, outside code fences. Repeated headings get
+// -1, -2 and so on, like on GitHub.
+pub fn heading_ids(text: &str) -> HashSet<String> {
+
+    let mut ids = HashSet::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut fence: Option<&str> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        let marker = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m));
+        match (fence, marker) {
+            (Some(open), Some(m)) if open == m => fence = None,
+            (None, Some(m)) => fence = Some(m),
+            _ => {}
+        }
+        if fence.is_some() || marker.is_some() {
+            continue;
+        }
+
+        let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+        let rest = &trimmed[hashes..];
+        if hashes == 0 || hashes > 6 || !(rest.is_empty() || rest.starts_with(' ')) {
+            continue;
+        }
+        let title = rest.trim().trim_end_matches('#').trim_end();
+        let id = slug(title);
+        let count = seen.entry(id.clone()).or_insert(0);
+        ids.insert(if *count == 0 { id } else { format!("{}-{}", id, count) });
+        *count += 1;
+    }
+
+    ids
+
+}
+
+// URLs that cannot be what was meant: whitespace, a web scheme without a host,
+// a mailto: without an address, or nothing after the scheme
+pub fn is_malformed_url(target: &str) -> bool {
+
+    if target.chars().any(char::is_whitespace) {
+        return true;
+    }
+    let host_ok = |rest: &str| {
+        let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+        !host.is_empty() && host.chars().all(|c| c.is_alphanumeric() || "-._~%@:[]".contains(c))
+    };
+    if let Some(rest) = target.strip_prefix("//") {
+        return !host_ok(rest);
+    }
+
+    let (scheme, rest) = target.split_once(':').unwrap_or((target, ""));
+    match scheme.to_lowercase().as_str() {
+        "http" | "https" | "ftp" => !rest.strip_prefix("//").is_some_and(host_ok),
+        "mailto" => !rest.contains('@'),
+        _ => rest.is_empty(),
+    }
+
+}
+
+// Checks the links of notes against the files of a workspace, reading each
+// linked note's headings once
+pub struct Checker {
+    root: PathBuf,
+    notes: Notes,
+    headings: HashMap<PathBuf, HashSet<String>>,
+}
+
+impl Checker {
+
+    pub fn new(root: &Path, files: &[PathBuf]) -> Self {
+        Checker { root: root.to_path_buf(), notes: Notes::new(root, files), headings: HashMap::new() }
+    }
+
+    fn has_heading(&mut self, note: &Path, anchor: &str) -> bool {
+        let ids = self.headings.entry(note.to_path_buf()).or_insert_with(|| {
+            fs::read(note).map(|bytes| heading_ids(&String::from_utf8_lossy(&bytes))).unwrap_or_default()
+        });
+        // Markdown links use the id, wikilinks the heading as written
+        let anchor = decode(anchor);
+        ids.contains(&anchor) || ids.contains(&slug(&anchor))
+    }
+
+    // A wikilink to something other than a note, like ![[pic.png]], names a file
+    // next to the source or at the root
+    fn find_file(&self, source: &Path, name: &str) -> Option<PathBuf> {
+        let dir = source.parent().unwrap_or(&self.root);
+        [dir.join(name), self.root.join(name)].into_iter().find(|p| p.exists())
+    }
+
+    fn problem(&mut self, source: &Path, link: &Link) -> Option<Problem> {
+
+        let target = link.target.trim();
+        if is_external(target) || target.starts_with("//") {
+            return is_malformed_url(target).then_some(Problem::MalformedUrl);
+        }
+
+        let resolved = if target.is_empty() {
+            Some(source.to_path_buf())
+        } else {
+            self.notes.resolve(source, link).or_else(|| self.find_file(source, target))
+        };
+        let path = match resolved {
+            Some(path) if path.exists() => path,
+            _ if link.kind == LinkKind::Image => return Some(Problem::MissingImage),
+            _ => return Some(Problem::MissingFile),
+        };
+
+        let is_note = path.extension().is_some_and(|e| e == "md" || e == "markdown");
+        match &link.anchor {
+            Some(anchor) if is_note && !anchor.is_empty() && !self.has_heading(&path, anchor) => Some(Problem::MissingAnchor),
+            _ => None,
+        }
+
+    }
+
+    pub fn check(&mut self, source: &Path, text: &str) -> Vec<LinkIssue> {
+        parse(text)
+            .iter()
+            .filter_map(|link| {
+                let problem = self.problem(source, link)?;
+                Some(LinkIssue {
+                    path: source.to_string_lossy().to_string(),
+                    line: link.line,
+                    column: link.column,
+                    problem,
+                    target: match &link.anchor {
+                        Some(anchor) => format!("{}#{}", link.target, anchor),
+                        None => link.target.clone(),
+                    },
+                })
+            })
+            .collect()
+    }
+
+}
+
+// Checks `files` one by one, handing each file's issues to `on_issues` as soon
+// as it is read. `cancel` is checked between files.
+pub fn check_files<F>(root: &Path, files: &[PathBuf], cancel: &AtomicBool, mut on_issues: F) -> CheckSummary
+where
+    F: FnMut(Vec<LinkIssue>),
+{
+
+    let mut checker = Checker::new(root, files);
+    let mut summary = CheckSummary { issues: 0, files: 0, cancelled: false };
+
+    for file in files {
+
+        if cancel.load(Ordering::Relaxed) {
+            summary.cancelled = true;
+            break;
+        }
+
+        let text = match fs::read(file) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(_) => continue,
+        };
+        summary.files += 1;
+
+        let issues = checker.check(file, &text);
+        if !issues.is_empty() {
+            summary.issues += issues.len();
+            on_issues(issues);
+        }
+
+    }
+
+    summary
+
+}
+
+pub fn write_report(path: &Path, roots: &[PathBuf], summary: &CheckSummary, issues: &[LinkIssue]) -> Result<(), String> {
+    let roots = roots.iter().map(|root| root.to_string_lossy().to_string()).collect();
+    let report = LinkReport { roots, summary, issues };
+    let json = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{list_files, TreeOptions};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_heading_ids() {
+        let ids = heading_ids("# Intro\n## What's new? ##\n```\n# not a heading\n```\n# Intro\n#hashtag");
+        let mut ids: Vec<String> = ids.into_iter().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["intro", "intro-1", "whats-new"]);
+    }
+
+    #[test]
+    fn test_malformed_urls() {
+        assert!(!is_malformed_url("https://example.com/a?b#c"));
+        assert!(!is_malformed_url("mailto:me@example.com"));
+        assert!(!is_malformed_url("//cdn.example.com/x.js"));
+        assert!(is_malformed_url("https:/example.com"));
+        assert!(is_malformed_url("http://"));
+        assert!(is_malformed_url("https://exa mple.com"));
+        assert!(is_malformed_url("mailto:nobody"));
+        assert!(is_malformed_url("tel:"));
+    }
+
+    #[test]
+    fn test_check_files_reports_problems() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("img")).unwrap();
+        fs::write(root.join("img/ok.png"), "").unwrap();
+        fs::write(root.join("other.md"), "# Setup Guide\n").unwrap();
+        fs::write(
+            root.join("index.md"),
+            "# Top\n[a](other.md#setup-guide) [b](other.md#nope) [[other#Setup Guide]]\n\
+             [c](missing.md) ![ok](img/ok.png) ![gone](img/gone.png)\n\
+             [up](#top) [down](#bottom) [web](http:/broken) [[nowhere]] ![[img/ok.png]]\n",
+        )
+        .unwrap();
+
+        let files = list_files(root, &TreeOptions::default_for(root));
+        let mut issues = Vec::new();
+        let summary = check_files(root, &files, &AtomicBool::new(false), |found| issues.extend(found));
+
+        let found: Vec<(usize, usize, Problem, &str)> =
+            issues.iter().map(|i| (i.line, i.column, i.problem, i.target.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (2, 27, Problem::MissingAnchor, "other.md#nope"),
+                (3, 1, Problem::MissingFile, "missing.md"),
+                (3, 36, Problem::MissingImage, "img/gone.png"),
+                (4, 12, Problem::MissingAnchor, "#bottom"),
+                (4, 28, Problem::MalformedUrl, "http:/broken"),
+                (4, 48, Problem::MissingFile, "nowhere"),
+            ]
+        );
+        assert_eq!(summary, CheckSummary { issues: 6, files: 2, cancelled: false });
+
+        let report = root.join("report.json");
+        write_report(&report, &[root.to_path_buf()], &summary, &issues).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report).unwrap()).unwrap();
+        assert_eq!(json["issues"][0]["problem"], "missing_anchor");
+        assert_eq!(json["summary"]["issues"], 6);
+    }
+
+    #[test]
+    fn test_check_files_cancel() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "[x](gone.md)").unwrap();
+
+        let files = list_files(root, &TreeOptions::default_for(root));
+        let summary = check_files(root, &files, &AtomicBool::new(true), |_| panic!("No file should be read"));
+        assert!(summary.cancelled);
+    }
+}
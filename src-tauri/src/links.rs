@@ -0,0 +1,633 @@
+use std::fs;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::replace::{self, FileReplacement, ReplaceBatch, Span};
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    Markdown,
+    Image,
+    Wiki,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct Link {
+    pub kind: LinkKind,
+    // Destination as written, without the #anchor or a wikilink's |alias
+    pub target: String,
+    pub anchor: Option<String>,
+    // 1-based, the column counted in characters from the opening bracket
+    pub line: usize,
+    pub column: usize,
+    // Byte range of `target` in the text
+    #[serde(skip)]
+    pub range: (usize, usize),
+    // Written as <destination>, which may contain spaces
+    #[serde(skip)]
+    pub bracketed: bool,
+}
+
+// Splits "path#anchor" at the first #
+fn split_anchor(dest: &str) -> (&str, Option<String>) {
+    match dest.find('#') {
+        Some(i) => (&dest[..i], Some(dest[i + 1..].to_string())),
+        None => (dest, None),
+    }
+}
+
+struct LineScan<'a> {
+    line: &'a str,
+    // Byte offset of the line in the whole text
+    offset: usize,
+    number: usize,
+}
+
+impl LineScan<'_> {
+
+    fn push(&self, links: &mut Vec<Link>, kind: LinkKind, start: usize, dest: (usize, usize), bracketed: bool) {
+        let (path, anchor) = split_anchor(&self.line[dest.0..dest.1]);
+        links.push(Link {
+            kind,
+            target: path.to_string(),
+            anchor,
+            line: self.number,
+            column: self.line[..start].chars().count() + 1,
+            range: (self.offset + dest.0, self.offset + dest.0 + path.len()),
+            bracketed,
+        });
+    }
+
+    // Destination of an inline link starting right after its "(". Returns the
+    // destination range, whether it was <bracketed>, and where the link ends.
+    fn destination(&self, open: usize) -> Option<((usize, usize), bool, usize)> {
+
+        let bytes = self.line.as_bytes();
+        let mut i = open;
+        while i < bytes.len() && bytes[i] == b' ' {
+            i += 1;
+        }
+
+        let (dest, bracketed) = if bytes.get(i) == Some(&b'<') {
+            let close = i + 1 + self.line[i + 1..].find('>')?;
+            let dest = (i + 1, close);
+            i = close + 1;
+            (dest, true)
+        } else {
+            let start = i;
+            let mut depth = 0;
+            while i < bytes.len() {
+                match bytes[i] {
+                    b'\\' => i += 1,
+                    b'(' => depth += 1,
+                    b')' if depth == 0 => break,
+                    b')' => depth -= 1,
+                    b' ' | b'\t' => break,
+                    _ => {}
+                }
+                i += 1;
+            }
+            ((start, i.min(bytes.len())), false)
+        };
+
+        // An optional "title" follows before the closing parenthesis
+        let close = i + self.line.get(i..)?.find(')')?;
+        Some((dest, bracketed, close + 1))
+
+    }
+
+    // Finds the "]" closing the "[" at `open`, allowing nested brackets
+    fn closing_bracket(&self, open: usize, end: usize) -> Option<usize> {
+        let bytes = self.line.as_bytes();
+        let mut depth = 0;
+        let mut i = open;
+        while i < end {
+            match bytes[i] {
+                b'\\' => i += 1,
+                b'[' => depth += 1,
+                b']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        None
+    }
+
+    fn scan(&self, from: usize, to: usize, links: &mut Vec<Link>) {
+
+        let bytes = self.line.as_bytes();
+        let mut i = from;
+
+        while i < to {
+            match bytes[i] {
+                b'\\' => i += 2,
+                // Code spans end at the next run of as many backticks
+                b'`' => {
+                    let run = self.line[i..to].bytes().take_while(|b| *b == b'`').count();
+                    let fence = &self.line[i..i + run];
+                    i = match self.line[i + run..to].find(fence) {
+                        Some(close) => i + run + close + run,
+                        None => i + run,
+                    };
+                }
+                b'[' if bytes.get(i + 1) == Some(&b'[') => {
+                    match self.line[i + 2..to].find("]]") {
+                        Some(len) if len > 0 => {
+                            let inner = &self.line[i + 2..i + 2 + len];
+                            let dest_len = inner.find('|').unwrap_or(inner.len());
+                            self.push(links, LinkKind::Wiki, i, (i + 2, i + 2 + dest_len), false);
+                            i += len + 4;
+                        }
+                        _ => i += 2,
+                    }
+                }
+                b'[' => {
+                    let close = match self.closing_bracket(i, to) {
+                        Some(close) => close,
+                        None => {
+                            i += 1;
+                            continue;
+                        }
+                    };
+                    match bytes.get(close + 1) {
+                        Some(b'(') => match self.destination(close + 2) {
+                            Some((dest, bracketed, end)) => {
+                                let kind = if i > 0 && bytes[i - 1] == b'!' { LinkKind::Image } else { LinkKind::Markdown };
+                                self.scan(i + 1, close, links);
+                                self.push(links, kind, i, dest, bracketed);
+                                i = end;
+                            }
+                            None => i = close + 1,
+                        },
+                        _ => {
+                            self.scan(i + 1, close, links);
+                            i = close + 1;
+                        }
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+
+    }
+
+    // "[label]: destination" on a line of its own
+    fn definition(&self, links: &mut Vec<Link>) -> bool {
+
+        let indent = self.line.len() - self.line.trim_start().len();
+        let rest = &self.line[indent..];
+        if indent > 3 || !rest.starts_with('[') || rest.starts_with("[[") {
+            return false;
+        }
+        let close = match rest.find("]:") {
+            Some(close) if close > 1 => indent + close,
+            _ => return false,
+        };
+
+        let start = close + 2 + self.line[close + 2..].len() - self.line[close + 2..].trim_start().len();
+        let (dest, bracketed) = if self.line[start..].starts_with('<') {
+            match self.line[start + 1..].find('>') {
+                Some(len) => ((start + 1, start + 1 + len), true),
+                None => return false,
+            }
+        } else {
+            let len = self.line[start..].find(char::is_whitespace).unwrap_or(self.line.len() - start);
+            ((start, start + len), false)
+        };
+        if dest.0 == dest.1 {
+            return false;
+        }
+
+        self.push(links, LinkKind::Markdown, indent, dest, bracketed);
+        true
+
+    }
+
+}
+
+// Inline links, images, reference definitions and wikilinks, in order. Code
+// blocks and code spans are skipped.
+pub fn parse(text: &str) -> Vec<Link> {
+
+    let mut links = Vec::new();
+    let mut fence: Option<&str> = None;
+    let mut offset = 0;
+
+    for (i, line) in text.split('\n').enumerate() {
+        let trimmed = line.trim_start();
+        match fence {
+            Some(marker) => {
+                if trimmed.starts_with(marker) {
+                    fence = None;
+                }
+            }
+            None if trimmed.starts_with("```") || trimmed.starts_with("~~~") => fence = Some(&trimmed[..3]),
+            None => {
+                let scan = LineScan { line, offset, number: i + 1 };
+                if !scan.definition(&mut links) {
+                    scan.scan(0, line.len(), &mut links);
+                }
+            }
+        }
+        offset += line.len() + 1;
+    }
+
+    links
+
+}
+
+// URLs with a scheme, like https: or mailto:, and protocol-relative ones
+pub fn is_external(target: &str) -> bool {
+    if target.starts_with("//") {
+        return true;
+    }
+    match target.find(':') {
+        // A single letter is a Windows drive
+        Some(i) if i > 1 => target[..i].chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)),
+        _ => false,
+    }
+}
+
+// Percent-decoding of a link destination; invalid escapes are kept as written
+pub fn decode(target: &str) -> String {
+    let bytes = target.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = target.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// Resolves . and .. without touching the filesystem, so missing targets resolve too
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                out.pop();
+            }
+            Component::CurDir => {}
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+// `to` relative to the directory `from`, with / separators as links use them
+pub fn relative_path(from: &Path, to: &Path) -> String {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut parts: Vec<String> = vec!["..".to_string(); from.len() - common];
+    parts.extend(to[common..].iter().map(|c| c.as_os_str().to_string_lossy().to_string()));
+    parts.join("/")
+}
+
+// The notes of a workspace by lowercased name, with and without extension, which
+// is how wikilinks refer to them
+pub struct Notes {
+    root: PathBuf,
+    by_name: HashMap<String, Vec<PathBuf>>,
+}
+
+impl Notes {
+
+    pub fn new(root: &Path, files: &[PathBuf]) -> Self {
+        let mut by_name: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for file in files {
+            let names = [file.file_name(), file.file_stem()];
+            for name in names.into_iter().flatten() {
+                let entry = by_name.entry(name.to_string_lossy().to_lowercase()).or_default();
+                if !entry.contains(file) {
+                    entry.push(file.clone());
+                }
+            }
+        }
+        Notes { root: root.to_path_buf(), by_name }
+    }
+
+    // Where a link in `source` points. Markdown links resolve whether or not the
+    // target exists; wikilinks by name only when a note of that name exists.
+    // External and anchor-only links resolve to None.
+    pub fn resolve(&self, source: &Path, link: &Link) -> Option<PathBuf> {
+
+        let target = link.target.trim();
+        if target.is_empty() || is_external(target) {
+            return None;
+        }
+        let dir = source.parent().unwrap_or(&self.root);
+
+        if link.kind != LinkKind::Wiki {
+            let decoded = decode(target);
+            return Some(match decoded.strip_prefix('/') {
+                Some(rooted) => normalize(&self.root.join(rooted)),
+                None => normalize(&dir.join(decoded)),
+            });
+        }
+
+        if target.contains('/') {
+            let path = normalize(&self.root.join(target));
+            if path.extension().is_some() || path.exists() {
+                return Some(path);
+            }
+            return Some(path.with_extension("md"));
+        }
+
+        // Several notes can share a name: the one next to the source wins, then the shallowest
+        let candidates = self.by_name.get(&target.to_lowercase())?;
+        candidates
+            .iter()
+            .min_by_key(|c| (c.parent() != Some(dir), c.components().count(), c.as_os_str().len()))
+            .cloned()
+
+    }
+
+}
+
+// Where `path` ends up when `from` moves to `to`, for `from` itself and anything below it
+pub fn moved(path: &Path, from: &Path, to: &Path) -> Option<PathBuf> {
+    let rest = path.strip_prefix(from).ok()?;
+    Some(if rest.as_os_str().is_empty() { to.to_path_buf() } else { to.join(rest) })
+}
+
+// The destination to write for a markdown link to `target` from a note in `dir`,
+// keeping the style of the link it replaces
+fn markdown_destination(root: &Path, dir: &Path, target: &Path, link: &Link) -> String {
+    let mut dest = if link.target.starts_with('/') {
+        format!("/{}", relative_path(root, target))
+    } else {
+        relative_path(dir, target)
+    };
+    if link.target.starts_with("./") && !dest.starts_with("../") {
+        dest = format!("./{}", dest);
+    }
+    if !link.bracketed {
+        dest = dest.replace(' ', "%20");
+    }
+    dest
+}
+
+fn wiki_destination(root: &Path, target: &Path, link: &Link) -> String {
+    let keep_extension = Path::new(&link.target).extension().is_some();
+    let named = if keep_extension { target.to_path_buf() } else { target.with_extension("") };
+    if link.target.contains('/') {
+        relative_path(root, &named)
+    } else {
+        named.file_name().map_or(String::new(), |n| n.to_string_lossy().to_string())
+    }
+}
+
+// The link rewrites that keep the workspace intact when `from` moves to `to`: links
+// pointing into the moved path, and relative links inside moved notes whose own
+// location changes. Planned against the files before the move; nothing is written.
+pub fn plan_move(root: &Path, files: &[PathBuf], from: &Path, to: &Path) -> Vec<FileReplacement> {
+
+    let notes = Notes::new(root, files);
+    let mut plans = Vec::new();
+
+    for source in files {
+
+        let text = match fs::read_to_string(source) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let new_source = moved(source, from, to).unwrap_or_else(|| source.clone());
+        let new_dir = new_source.parent().unwrap_or(root);
+
+        let mut spans: Vec<Span> = Vec::new();
+        for link in parse(&text) {
+
+            let target = match notes.resolve(source, &link) {
+                Some(target) => target,
+                None => continue,
+            };
+            let new_target = moved(&target, from, to);
+
+            let dest = match link.kind {
+                LinkKind::Wiki => match &new_target {
+                    Some(new_target) => wiki_destination(root, new_target, &link),
+                    None => continue,
+                },
+                _ if new_target.is_none() && new_source == *source => continue,
+                _ => {
+                    let dest = markdown_destination(root, new_dir, new_target.as_ref().unwrap_or(&target), &link);
+                    if decode(&dest) == decode(&link.target) {
+                        continue;
+                    }
+                    dest
+                }
+            };
+
+            if dest != link.target {
+                spans.push((link.range.0, link.range.1, dest));
+            }
+
+        }
+
+        plans.extend(FileReplacement::new(source, text, &spans));
+
+    }
+
+    plans
+
+}
+
+// Writes the rewrites planned by `plan_move` once the move has happened, finding
+// the notes that moved along at their new paths
+pub fn apply_move(journal_dir: &Path, plans: Vec<FileReplacement>, from: &Path, to: &Path) -> Result<ReplaceBatch, String> {
+    let plans: Vec<FileReplacement> = plans
+        .into_iter()
+        .map(|mut plan| {
+            if let Some(moved) = moved(Path::new(&plan.path), from, to) {
+                plan.path = moved.to_string_lossy().to_string();
+            }
+            plan
+        })
+        .collect();
+    replace::apply(journal_dir, &plans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn targets(text: &str) -> Vec<(LinkKind, String, Option<String>)> {
+        parse(text).into_iter().map(|l| (l.kind, l.target, l.anchor)).collect()
+    }
+
+    #[test]
+    fn test_parse_links() {
+        let text = "See [a](notes/a.md#intro) and ![img](<my pic.png> \"title\").\n[[Wiki Note|alias]] and [[other#part]]";
+
+        assert_eq!(
+            targets(text),
+            vec![
+                (LinkKind::Markdown, "notes/a.md".into(), Some("intro".into())),
+                (LinkKind::Image, "my pic.png".into(), None),
+                (LinkKind::Wiki, "Wiki Note".into(), None),
+                (LinkKind::Wiki, "other".into(), Some("part".into())),
+            ]
+        );
+
+        let links = parse(text);
+        assert_eq!((links[2].line, links[2].column), (2, 1));
+        assert_eq!(&text[links[0].range.0..links[0].range.1], "notes/a.md");
+        assert!(links[1].bracketed);
+    }
+
+    #[test]
+    fn test_parse_skips_code_and_finds_nested_and_definitions() {
+        let text = "`[no](x.md)` [![badge](b.svg)](page.md)\n```\n[no](y.md)\n```\n[ref]: ref.md \"Title\"";
+
+        assert_eq!(
+            targets(text),
+            vec![
+                (LinkKind::Image, "b.svg".into(), None),
+                (LinkKind::Markdown, "page.md".into(), None),
+                (LinkKind::Markdown, "ref.md".into(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_external_and_paths() {
+        assert!(is_external("https://example.com"));
+        assert!(is_external("mailto:me@example.com"));
+        assert!(!is_external("notes/a.md"));
+        assert!(!is_external("C:/notes/a.md"));
+        assert_eq!(decode("my%20note.md"), "my note.md");
+        assert_eq!(normalize(Path::new("/v/a/../b/./c.md")), PathBuf::from("/v/b/c.md"));
+        assert_eq!(relative_path(Path::new("/v/a/b"), Path::new("/v/c/d.md")), "../../c/d.md");
+        assert_eq!(relative_path(Path::new("/v"), Path::new("/v/d.md")), "d.md");
+    }
+
+    #[test]
+    fn test_resolve_wikilinks_prefers_nearby_note() {
+        let root = Path::new("/v");
+        let files = vec![PathBuf::from("/v/deep/x/Note.md"), PathBuf::from("/v/a/Note.md"), PathBuf::from("/v/deep/other.md")];
+        let notes = Notes::new(root, &files);
+        let wiki = |target: &str| Link { kind: LinkKind::Wiki, target: target.into(), anchor: None, line: 1, column: 1, range: (0, 0), bracketed: false };
+
+        assert_eq!(notes.resolve(Path::new("/v/deep/x/y.md"), &wiki("note")), Some(PathBuf::from("/v/deep/x/Note.md")));
+        assert_eq!(notes.resolve(Path::new("/v/top.md"), &wiki("Note")), Some(PathBuf::from("/v/a/Note.md")));
+        assert_eq!(notes.resolve(Path::new("/v/top.md"), &wiki("other.md")), Some(PathBuf::from("/v/deep/other.md")));
+        assert_eq!(notes.resolve(Path::new("/v/top.md"), &wiki("missing")), None);
+    }
+
+    fn workspace(files: &[(&str, &str)]) -> (TempDir, Vec<PathBuf>) {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let mut paths = Vec::new();
+        for (name, content) in files {
+            let path = temp_dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+            paths.push(path);
+        }
+        (temp_dir, paths)
+    }
+
+    fn rewritten(plans: &[FileReplacement], root: &Path, name: &str) -> Vec<String> {
+        let path = root.join(name).to_string_lossy().to_string();
+        plans
+            .iter()
+            .filter(|p| p.path == path)
+            .flat_map(|p| p.hunks.iter().flat_map(|h| h.after.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_rename_rewrites_inbound_links() {
+        let (dir, files) = workspace(&[
+            ("old.md", "target"),
+            ("index.md", "[Old](old.md#top) [[old]] [[old|Alias]] [web](https://old.md)"),
+            ("sub/deep.md", "[up](../old.md) [[sub2/x]]"),
+        ]);
+        let root = dir.path();
+
+        let plans = plan_move(root, &files, &root.join("old.md"), &root.join("new name.md"));
+
+        assert_eq!(plans.len(), 2);
+        assert_eq!(rewritten(&plans, root, "index.md"), vec!["[Old](new%20name.md#top) [[new name]] [[new name|Alias]] [web](https://old.md)"]);
+        assert_eq!(rewritten(&plans, root, "sub/deep.md"), vec!["[up](../new%20name.md) [[sub2/x]]"]);
+    }
+
+    #[test]
+    fn test_plan_move_rewrites_links_inside_moved_note() {
+        let (dir, files) = workspace(&[
+            ("note.md", "[sib](sibling.md) [self](#here) [[sibling]]"),
+            ("sibling.md", "[n](./note.md)"),
+            ("archive/keep.md", ""),
+        ]);
+        let root = dir.path();
+
+        let plans = plan_move(root, &files, &root.join("note.md"), &root.join("archive/note.md"));
+
+        assert_eq!(rewritten(&plans, root, "note.md"), vec!["[sib](../sibling.md) [self](#here) [[sibling]]"]);
+        assert_eq!(rewritten(&plans, root, "sibling.md"), vec!["[n](./archive/note.md)"]);
+    }
+
+    #[test]
+    fn test_apply_move_writes_moved_notes() {
+        let (dir, files) = workspace(&[("note.md", "[sib](sibling.md)"), ("sibling.md", "[n](note.md)")]);
+        let root = dir.path();
+        let (from, to) = (root.join("note.md"), root.join("archive/note.md"));
+        let plans = plan_move(root, &files, &from, &to);
+        fs::create_dir(root.join("archive")).unwrap();
+        fs::rename(&from, &to).unwrap();
+
+        let batch = apply_move(&root.join(".journal"), plans, &from, &to).expect("Failed to rewrite links");
+
+        assert_eq!(batch.files, 2);
+        assert_eq!(fs::read_to_string(&to).unwrap(), "[sib](../sibling.md)");
+        assert_eq!(fs::read_to_string(root.join("sibling.md")).unwrap(), "[n](archive/note.md)");
+    }
+
+    #[test]
+    fn test_apply_move_failure_leaves_notes_alone() {
+        let (dir, files) = workspace(&[("note.md", ""), ("index.md", "[n](note.md)"), ("journal", "")]);
+        let root = dir.path();
+        let (from, to) = (root.join("note.md"), root.join("renamed.md"));
+        let plans = plan_move(root, &files, &from, &to);
+        fs::rename(&from, &to).unwrap();
+
+        // The journal directory cannot be created where a file is in the way
+        let result = apply_move(&root.join("journal"), plans, &from, &to);
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(root.join("index.md")).unwrap(), "[n](note.md)");
+    }
+
+    #[test]
+    fn test_plan_folder_move() {
+        let (dir, files) = workspace(&[
+            ("a/one.md", "[two](two.md) [out](../top.md)"),
+            ("a/two.md", ""),
+            ("top.md", "[one](a/one.md) [[a/two]]"),
+        ]);
+        let root = dir.path();
+
+        let plans = plan_move(root, &files, &root.join("a"), &root.join("b/a"));
+
+        // Links between notes that move together stay as they are
+        assert_eq!(rewritten(&plans, root, "a/one.md"), vec!["[two](two.md) [out](../../top.md)"]);
+        assert_eq!(rewritten(&plans, root, "top.md"), vec!["[one](b/a/one.md) [[b/a/two]]"]);
+    }
+}
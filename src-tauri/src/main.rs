@@ -1,4 +1,28 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+
+// Release builds on Windows start without a console, so output of a subcommand
+// would go nowhere; attach to the console of the shell it was run from instead
+#[cfg(windows)]
+fn attach_console() {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // Subcommands like `aqua export` run headless and exit without opening a window
+    #[cfg(windows)]
+    if aqua_lib::cli::parse(args.get(1..).unwrap_or_default()).is_some() {
+        attach_console();
+    }
+    if let Some(code) = aqua_lib::cli::run(args) {
+        std::process::exit(code);
+    }
     aqua_lib::run();
-}
\ No newline at end of file
+}
@@ -0,0 +1,194 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::diff::{self, DiffHunk};
+use crate::save::{content_hash, write_atomic};
+
+// Left by the panic hook, and present while the app runs; either one at launch
+// means the last run did not end cleanly
+const PANIC_MARKER: &str = "panicked";
+const RUNNING_MARKER: &str = "running";
+
+// Lines of context around each change in the diff against disk
+const DIFF_CONTEXT: usize = 3;
+
+// The last unsaved content of a document
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    // Path of a document with a file, or the editor's id for an untitled one
+    pub key: String,
+    pub content: String,
+    pub saved_ms: u64,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct Recoverable {
+    pub key: String,
+    // None for untitled documents
+    pub path: Option<String>,
+    pub content: String,
+    pub saved_ms: u64,
+    // False when the file is gone, in which case the diff is against nothing
+    pub on_disk: bool,
+    pub hunks: Vec<DiffHunk>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+// Keys can be any path, so files are named by their hash
+fn snapshot_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(format!("{}.json", &content_hash(key.as_bytes())[..16]))
+}
+
+// Records the unsaved content of a document, replacing its previous snapshot
+pub fn journal(dir: &Path, key: &str, content: &str) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let snapshot = Snapshot { key: key.to_string(), content: content.to_string(), saved_ms: now_ms() };
+    let json = serde_json::to_string(&snapshot).map_err(|e| e.to_string())?;
+    write_atomic(&snapshot_path(dir, key), json.as_bytes()).map_err(|e| e.to_string())
+}
+
+// Drops the snapshot of a document once it is saved or closed
+pub fn discard(dir: &Path, key: &str) {
+    let _ = fs::remove_file(snapshot_path(dir, key));
+}
+
+// Every snapshot in the journal, oldest first. Unreadable ones are skipped.
+pub fn snapshots(dir: &Path) -> Vec<Snapshot> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut snapshots: Vec<Snapshot> = entries
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|entry| fs::read(entry.path()).ok())
+        .filter_map(|bytes| serde_json::from_slice(&bytes).ok())
+        .collect();
+    snapshots.sort_by_key(|snapshot| snapshot.saved_ms);
+    snapshots
+}
+
+pub fn clear(dir: &Path) {
+    for snapshot in snapshots(dir) {
+        discard(dir, &snapshot.key);
+    }
+}
+
+// Called from the panic hook, so it only touches the file system
+pub fn mark_panicked(dir: &Path) {
+    let _ = fs::create_dir_all(dir);
+    let _ = fs::write(dir.join(PANIC_MARKER), b"");
+}
+
+// Called once at launch: whether the last run panicked or never exited cleanly.
+// The markers are reset for this run.
+pub fn start(dir: &Path) -> bool {
+    let crashed = dir.join(PANIC_MARKER).exists() || dir.join(RUNNING_MARKER).exists();
+    let _ = fs::remove_file(dir.join(PANIC_MARKER));
+    let _ = fs::create_dir_all(dir);
+    let _ = fs::write(dir.join(RUNNING_MARKER), b"");
+    crashed
+}
+
+// Called on a clean exit
+pub fn finish(dir: &Path) {
+    let _ = fs::remove_file(dir.join(RUNNING_MARKER));
+}
+
+// The snapshots that still hold something the files on disk do not, with the
+// difference. Snapshots matching their file are dropped.
+pub fn recoverable(dir: &Path) -> Vec<Recoverable> {
+
+    let mut found = Vec::new();
+    for snapshot in snapshots(dir) {
+        let path = Path::new(&snapshot.key).is_absolute().then(|| snapshot.key.clone());
+        let disk = path.as_ref().and_then(|path| fs::read_to_string(path).ok());
+        if disk.as_deref() == Some(snapshot.content.as_str()) || (path.is_none() && snapshot.content.is_empty()) {
+            discard(dir, &snapshot.key);
+            continue;
+        }
+        found.push(Recoverable {
+            hunks: diff::hunks(disk.as_deref().unwrap_or(""), &snapshot.content, DIFF_CONTEXT),
+            on_disk: disk.is_some(),
+            key: snapshot.key,
+            path,
+            content: snapshot.content,
+            saved_ms: snapshot.saved_ms,
+        });
+    }
+
+    found
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_journal_and_discard() {
+        let journal_dir = TempDir::new().expect("Failed to create temp dir");
+        let dir = journal_dir.path();
+
+        journal(dir, "/notes/a.md", "first").unwrap();
+        journal(dir, "/notes/a.md", "second").unwrap();
+        journal(dir, "untitled-1", "draft").unwrap();
+
+        let keys: Vec<(String, String)> = snapshots(dir).into_iter().map(|s| (s.key, s.content)).collect();
+        assert_eq!(keys.len(), 2);
+        assert!(keys.contains(&("/notes/a.md".to_string(), "second".to_string())));
+
+        discard(dir, "/notes/a.md");
+        assert_eq!(snapshots(dir).len(), 1);
+        clear(dir);
+        assert!(snapshots(dir).is_empty());
+    }
+
+    #[test]
+    fn test_recoverable_diffs_against_disk() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let dir = temp_dir.path().join("recovery");
+        let path = |name: &str| temp_dir.path().join(name).to_string_lossy().to_string();
+        fs::write(path("changed.md"), "one\ntwo\n").unwrap();
+        fs::write(path("saved.md"), "same\n").unwrap();
+
+        journal(&dir, &path("changed.md"), "one\n2\n").unwrap();
+        journal(&dir, &path("saved.md"), "same\n").unwrap();
+        journal(&dir, &path("deleted.md"), "kept\n").unwrap();
+        journal(&dir, "untitled-1", "draft\n").unwrap();
+
+        let found = recoverable(&dir);
+        let summary: Vec<(&str, bool, usize)> = found.iter().map(|r| (r.key.as_str(), r.on_disk, r.hunks.len())).collect();
+        assert_eq!(found.len(), 3);
+        assert!(summary.contains(&(path("changed.md").as_str(), true, 1)));
+        assert!(summary.contains(&(path("deleted.md").as_str(), false, 1)));
+        assert!(summary.contains(&("untitled-1", false, 1)));
+        assert_eq!(found.iter().find(|r| r.key == "untitled-1").unwrap().path, None);
+
+        // The snapshot matching its file was dropped
+        assert_eq!(snapshots(&dir).len(), 3);
+    }
+
+    #[test]
+    fn test_start_reports_unclean_exit() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let dir = temp_dir.path().join("recovery");
+
+        assert!(!start(&dir));
+        finish(&dir);
+        assert!(!start(&dir));
+        // Never finished
+        assert!(start(&dir));
+        finish(&dir);
+
+        mark_panicked(&dir);
+        assert!(start(&dir));
+        finish(&dir);
+        assert!(!start(&dir));
+    }
+}
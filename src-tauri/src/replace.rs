@@ -0,0 +1,407 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::save::{content_hash, write_atomic};
+use crate::search::Query;
+
+// Undo journals kept around; older batches can no longer be undone
+const MAX_JOURNALS: usize = 20;
+
+// A run of changed lines. `line` is the 1-based first line in the original file.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct Hunk {
+    pub line: usize,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct FileReplacement {
+    pub path: String,
+    pub replacements: usize,
+    pub hunks: Vec<Hunk>,
+    #[serde(skip)]
+    original: String,
+    #[serde(skip)]
+    replaced: String,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct ReplaceBatch {
+    // Pass to `undo` to revert the whole batch
+    pub id: String,
+    pub files: usize,
+    pub replacements: usize,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct UndoResult {
+    pub restored: Vec<String>,
+    // Files edited again after the replace, left alone unless forced
+    pub skipped: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JournalEntry {
+    path: String,
+    // Name of the copy of the original inside the journal directory
+    backup: String,
+    written_hash: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Journal {
+    id: String,
+    entries: Vec<JournalEntry>,
+}
+
+// Byte range of a match and the text replacing it
+pub type Span = (usize, usize, String);
+
+// Byte offset where each line starts
+fn line_starts(text: &str) -> Vec<usize> {
+    std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect()
+}
+
+fn spans_of(query: &Query, text: &str, replacement: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    for caps in query.pattern().captures_iter(text) {
+        let m = caps.get(0).expect("Group 0 is always set");
+        let mut with = String::new();
+        if query.expands() {
+            caps.expand(replacement, &mut with);
+        } else {
+            with.push_str(replacement);
+        }
+        spans.push((m.start(), m.end(), with));
+    }
+    spans
+}
+
+// Replaces every match of `query` in `text`, see `apply_spans`
+#[cfg(test)]
+pub fn replace_text(query: &Query, text: &str, replacement: &str) -> Option<(String, usize, Vec<Hunk>)> {
+    let spans = spans_of(query, text, replacement);
+    let (replaced, hunks) = apply_spans(text, &spans)?;
+    Some((replaced, spans.len(), hunks))
+}
+
+// Applies sorted, non-overlapping spans to `text`. Spans on the same or touching
+// lines are reported as one hunk, so the preview shows each changed line once.
+pub fn apply_spans(text: &str, spans: &[Span]) -> Option<(String, Vec<Hunk>)> {
+
+    let mut replaced = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, end, with) in spans {
+        replaced.push_str(&text[last..*start]);
+        replaced.push_str(with);
+        last = *end;
+    }
+    replaced.push_str(&text[last..]);
+
+    if replaced == text {
+        return None;
+    }
+
+    let starts = line_starts(text);
+    let line_of = |offset: usize| starts.partition_point(|s| *s <= offset) - 1;
+    let line_end = |line: usize| starts.get(line + 1).map_or(text.len(), |next| next - 1);
+
+    // Group spans into runs of lines
+    let mut groups: Vec<(usize, usize, Vec<&Span>)> = Vec::new();
+    for span in spans {
+        let (first, last) = (line_of(span.0), line_of(span.1));
+        match groups.last_mut() {
+            Some(group) if first <= group.1 + 1 => {
+                group.1 = group.1.max(last);
+                group.2.push(span);
+            }
+            _ => groups.push((first, last, vec![span])),
+        }
+    }
+
+    let mut hunks = Vec::new();
+    for (first, last, group) in groups {
+        let (from, to) = (starts[first], line_end(last).max(starts[first]));
+        let mut after = String::new();
+        let mut pos = from;
+        for (start, end, with) in group {
+            after.push_str(&text[pos..*start]);
+            after.push_str(with);
+            pos = *end;
+        }
+        after.push_str(&text[pos.min(to)..to]);
+
+        let before = &text[from..to];
+        if before == after {
+            continue;
+        }
+        hunks.push(Hunk {
+            line: first + 1,
+            before: before.split('\n').map(String::from).collect(),
+            after: after.split('\n').map(String::from).collect(),
+        });
+    }
+
+    Some((replaced, hunks))
+
+}
+
+impl FileReplacement {
+
+    // The planned rewrite of `path`, None when the spans change nothing
+    pub fn new(path: &Path, original: String, spans: &[Span]) -> Option<Self> {
+        let (replaced, hunks) = apply_spans(&original, spans)?;
+        Some(FileReplacement {
+            path: path.to_string_lossy().to_string(),
+            replacements: spans.len(),
+            hunks,
+            original,
+            replaced,
+        })
+    }
+
+}
+
+// What a replace would change, file by file. Nothing is written.
+pub fn plan(root: &Path, files: &[PathBuf], query: &Query, replacement: &str) -> Vec<FileReplacement> {
+
+    let mut plans = Vec::new();
+
+    for file in files {
+        if !query.wants(file.strip_prefix(root).unwrap_or(file)) {
+            continue;
+        }
+        // Files that are not valid UTF-8 would be mangled by a rewrite
+        let original = match fs::read_to_string(file) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let spans = spans_of(query, &original, replacement);
+        plans.extend(FileReplacement::new(file, original, &spans));
+    }
+
+    plans
+
+}
+
+fn new_batch_id(journal_dir: &Path) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis());
+    let mut id = format!("{}", now);
+    let mut n = 1;
+    while journal_dir.join(&id).exists() {
+        id = format!("{}-{}", now, n);
+        n += 1;
+    }
+    id
+}
+
+// Drops the oldest journals beyond MAX_JOURNALS
+fn prune(journal_dir: &Path) {
+    let mut ids: Vec<PathBuf> = match fs::read_dir(journal_dir) {
+        Ok(entries) => entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect(),
+        Err(_) => return,
+    };
+    ids.sort();
+    let excess = ids.len().saturating_sub(MAX_JOURNALS);
+    for old in &ids[..excess] {
+        let _ = fs::remove_dir_all(old);
+    }
+}
+
+// Writes the planned replacements. The originals go to a journal first, and if any
+// write fails, or a file no longer holds what was planned from, the files already
+// written are put back, so the batch applies whole or not at all.
+pub fn apply(journal_dir: &Path, plans: &[FileReplacement]) -> Result<ReplaceBatch, String> {
+
+    let id = new_batch_id(journal_dir);
+    let dir = journal_dir.join(&id);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    let mut entries = Vec::new();
+    for (i, plan) in plans.iter().enumerate() {
+        let backup = format!("{}.orig", i);
+        fs::write(dir.join(&backup), &plan.original).map_err(|e| e.to_string())?;
+        entries.push(JournalEntry {
+            path: plan.path.clone(),
+            backup,
+            written_hash: content_hash(plan.replaced.as_bytes()),
+        });
+    }
+    let journal = serde_json::to_string_pretty(&Journal { id: id.clone(), entries }).map_err(|e| e.to_string())?;
+    write_atomic(&dir.join("journal.json"), journal.as_bytes()).map_err(|e| e.to_string())?;
+
+    for (i, plan) in plans.iter().enumerate() {
+        // A file edited since it was planned would lose that edit
+        let current = fs::read(&plan.path).map(|bytes| content_hash(&bytes)).ok();
+        let result = if current != Some(content_hash(plan.original.as_bytes())) {
+            Err("changed since the preview".to_string())
+        } else {
+            write_atomic(Path::new(&plan.path), plan.replaced.as_bytes()).map_err(|e| e.to_string())
+        };
+        if let Err(e) = result {
+            for done in &plans[..i] {
+                let _ = write_atomic(Path::new(&done.path), done.original.as_bytes());
+            }
+            let _ = fs::remove_dir_all(&dir);
+            return Err(format!("{}: {}", plan.path, e));
+        }
+    }
+
+    prune(journal_dir);
+
+    Ok(ReplaceBatch {
+        id,
+        files: plans.len(),
+        replacements: plans.iter().map(|p| p.replacements).sum(),
+    })
+
+}
+
+// Restores the files of a batch. Files changed since the replace are skipped unless
+// `force` is set. The journal is dropped once every file is back.
+pub fn undo(journal_dir: &Path, id: &str, force: bool) -> Result<UndoResult, String> {
+
+    if id.is_empty() || id.contains(['/', '\\', '.']) {
+        return Err("Unknown replace batch".into());
+    }
+    let dir = journal_dir.join(id);
+    let journal: Journal = fs::read(dir.join("journal.json"))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or("Unknown replace batch")?;
+
+    let mut result = UndoResult { restored: Vec::new(), skipped: Vec::new() };
+    for entry in journal.entries {
+        let current = fs::read(&entry.path).map(|bytes| content_hash(&bytes)).ok();
+        if !force && current.as_deref() != Some(entry.written_hash.as_str()) {
+            result.skipped.push(entry.path);
+            continue;
+        }
+        let original = fs::read(dir.join(&entry.backup)).map_err(|e| e.to_string())?;
+        write_atomic(Path::new(&entry.path), &original).map_err(|e| e.to_string())?;
+        result.restored.push(entry.path);
+    }
+
+    if result.skipped.is_empty() {
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    Ok(result)
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SearchOptions;
+    use tempfile::TempDir;
+
+    fn query(text: &str, options: SearchOptions) -> Query {
+        Query::new(text, &options).expect("Failed to build query")
+    }
+
+    #[test]
+    fn test_replace_text_hunks() {
+        let q = query("aqua", SearchOptions::default());
+
+        let (replaced, count, hunks) = replace_text(&q, "Aqua editor\nunchanged\nuse aqua, aqua\n", "Wave").unwrap();
+
+        assert_eq!(replaced, "Wave editor\nunchanged\nuse Wave, Wave\n");
+        assert_eq!(count, 3);
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(hunks[1], Hunk { line: 3, before: vec!["use aqua, aqua".into()], after: vec!["use Wave, Wave".into()] });
+    }
+
+    #[test]
+    fn test_replace_text_regex_groups_and_literal_dollar() {
+        let regex = query(r"(\w+)@old\.com", SearchOptions { regex: true, ..Default::default() });
+        let literal = query("price", SearchOptions::default());
+
+        assert_eq!(replace_text(&regex, "mail bob@old.com", "$1@new.com").unwrap().0, "mail bob@new.com");
+        assert_eq!(replace_text(&literal, "price", "$1").unwrap().0, "$1");
+        assert!(replace_text(&literal, "nothing here", "x").is_none());
+    }
+
+    #[test]
+    fn test_replace_text_across_lines() {
+        let q = query(r"one\ntwo", SearchOptions { regex: true, ..Default::default() });
+
+        let (replaced, _, hunks) = replace_text(&q, "zero\none\ntwo\nthree", "1-2").unwrap();
+
+        assert_eq!(replaced, "zero\n1-2\nthree");
+        assert_eq!(hunks, vec![Hunk { line: 2, before: vec!["one".into(), "two".into()], after: vec!["1-2".into()] }]);
+    }
+
+    #[test]
+    fn test_apply_and_undo() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let journal_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "Aqua is great").unwrap();
+        fs::write(root.join("b.md"), "about aqua").unwrap();
+        fs::write(root.join("c.md"), "unrelated").unwrap();
+        let files = vec![root.join("a.md"), root.join("b.md"), root.join("c.md")];
+
+        let plans = plan(root, &files, &query("aqua", SearchOptions::default()), "Wave");
+        assert_eq!(plans.len(), 2);
+        let batch = apply(journal_dir.path(), &plans).expect("Failed to apply");
+
+        assert_eq!((batch.files, batch.replacements), (2, 2));
+        assert_eq!(fs::read_to_string(root.join("a.md")).unwrap(), "Wave is great");
+
+        let result = undo(journal_dir.path(), &batch.id, false).expect("Failed to undo");
+
+        assert_eq!(result.restored.len(), 2);
+        assert_eq!(fs::read_to_string(root.join("a.md")).unwrap(), "Aqua is great");
+        assert_eq!(fs::read_to_string(root.join("b.md")).unwrap(), "about aqua");
+        assert!(undo(journal_dir.path(), &batch.id, false).is_err());
+    }
+
+    #[test]
+    fn test_apply_refuses_files_edited_since_the_plan() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let journal_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "old").unwrap();
+        fs::write(root.join("b.md"), "old").unwrap();
+        let files = vec![root.join("a.md"), root.join("b.md")];
+        let plans = plan(root, &files, &query("old", SearchOptions::default()), "new");
+
+        fs::write(root.join("b.md"), "old, edited").unwrap();
+        let result = apply(journal_dir.path(), &plans);
+
+        assert!(result.unwrap_err().contains("changed since the preview"));
+        assert_eq!(fs::read_to_string(root.join("a.md")).unwrap(), "old");
+        assert_eq!(fs::read_to_string(root.join("b.md")).unwrap(), "old, edited");
+        assert_eq!(fs::read_dir(journal_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_undo_skips_files_edited_since() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let journal_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "old").unwrap();
+        fs::write(root.join("b.md"), "old").unwrap();
+        let files = vec![root.join("a.md"), root.join("b.md")];
+        let batch = apply(journal_dir.path(), &plan(root, &files, &query("old", SearchOptions::default()), "new")).unwrap();
+
+        fs::write(root.join("b.md"), "new and edited").unwrap();
+        let result = undo(journal_dir.path(), &batch.id, false).unwrap();
+
+        assert_eq!(result.skipped, vec![root.join("b.md").to_string_lossy().to_string()]);
+        assert_eq!(fs::read_to_string(root.join("a.md")).unwrap(), "old");
+
+        let forced = undo(journal_dir.path(), &batch.id, true).unwrap();
+        assert_eq!(forced.restored.len(), 2);
+        assert_eq!(fs::read_to_string(root.join("b.md")).unwrap(), "old");
+    }
+
+    #[test]
+    fn test_undo_rejects_bad_ids() {
+        let journal_dir = TempDir::new().expect("Failed to create temp dir");
+        assert!(undo(journal_dir.path(), "../escape", false).is_err());
+    }
+}
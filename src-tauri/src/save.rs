@@ -0,0 +1,475 @@
+use std::fmt;
+use std::sync::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Distinguishes temp files of concurrent saves into the same directory
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// What we knew about a file when it was last opened or saved by us
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct FileFingerprint {
+    pub mtime_ms: u64,
+    pub size: u64,
+    pub hash: String,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Conflict {
+    pub path: String,
+    pub expected: FileFingerprint,
+    // None when the file was deleted on disk
+    pub actual: Option<FileFingerprint>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+pub enum SaveError {
+    Cancelled,
+    InvalidPath(String),
+    Write(String),
+    Rename(String),
+    Conflict(Conflict),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Cancelled => write!(f, "cancelled"),
+            SaveError::InvalidPath(msg) => write!(f, "invalid path: {}", msg),
+            SaveError::Write(msg) => write!(f, "write failed: {}", msg),
+            SaveError::Rename(msg) => write!(f, "rename failed: {}", msg),
+            SaveError::Conflict(c) => write!(f, "{} changed on disk since it was opened", c.path),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+pub fn mtime_ms(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+pub fn fingerprint_with_hash(meta: &fs::Metadata, hash: String) -> FileFingerprint {
+    FileFingerprint {
+        mtime_ms: mtime_ms(meta),
+        size: meta.len(),
+        hash,
+    }
+}
+
+pub fn fingerprint_file(path: &Path) -> io::Result<FileFingerprint> {
+
+    let mut file = fs::File::open(path)?;
+    let meta = file.metadata()?;
+
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 65536];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(fingerprint_with_hash(&meta, format!("{:x}", hasher.finalize())))
+
+}
+
+// A file that is gone, or not written yet, is keyed by its canonical directory
+// so it still matches the path it was recorded under
+fn key(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| {
+        match (path.parent().and_then(|dir| dir.canonicalize().ok()), path.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _ => path.to_path_buf(),
+        }
+    })
+}
+
+// Fingerprints of every file the frontend opened, keyed by canonical path
+#[derive(Default)]
+pub struct OpenFiles(Mutex<HashMap<PathBuf, FileFingerprint>>);
+
+impl OpenFiles {
+
+    pub fn record(&self, path: &Path, fingerprint: FileFingerprint) {
+        self.0.lock().unwrap().insert(key(path), fingerprint);
+    }
+
+    // Records a file right after we wrote `contents` to it
+    pub fn record_written(&self, path: &Path, contents: &[u8]) {
+        if let Ok(meta) = fs::metadata(path) {
+            self.record(path, fingerprint_with_hash(&meta, content_hash(contents)));
+        }
+    }
+
+    // Carries the entries of `from`, and of the files below it when it is a folder,
+    // over to their path after `from` was moved to `to`
+    pub fn rename(&self, from: &Path, to: &Path) {
+        let (from, to) = (key(from), key(to));
+        let mut files = self.0.lock().unwrap();
+        let moved: Vec<PathBuf> = files.keys().filter(|path| path.starts_with(&from)).cloned().collect();
+        for path in moved {
+            if let Some(fingerprint) = files.remove(&path) {
+                let rest = path.strip_prefix(&from).unwrap_or(Path::new(""));
+                files.insert(if rest.as_os_str().is_empty() { to.clone() } else { to.join(rest) }, fingerprint);
+            }
+        }
+    }
+
+    pub fn forget(&self, path: &Path) {
+        self.0.lock().unwrap().remove(&key(path));
+    }
+
+    pub fn get(&self, path: &Path) -> Option<FileFingerprint> {
+        self.0.lock().unwrap().get(&key(path)).cloned()
+    }
+
+    // Fails with a conflict when the file on disk no longer matches what was opened.
+    // Files we never opened, e.g. brand new ones, always pass.
+    pub fn check(&self, path: &Path) -> Result<(), SaveError> {
+
+        let expected = match self.get(path) {
+            Some(fp) => fp,
+            None => return Ok(()),
+        };
+
+        let conflict = |actual| SaveError::Conflict(Conflict {
+            path: path.to_string_lossy().to_string(),
+            expected: expected.clone(),
+            actual,
+        });
+
+        let meta = match fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(conflict(None)),
+            Err(e) => return Err(SaveError::InvalidPath(e.to_string())),
+        };
+
+        // Cheap path: nothing touched the file
+        if mtime_ms(&meta) == expected.mtime_ms && meta.len() == expected.size {
+            return Ok(());
+        }
+
+        let actual = fingerprint_file(path).map_err(|e| SaveError::InvalidPath(e.to_string()))?;
+
+        // Touched but identical content (e.g. a sync tool rewrote it) is not a conflict
+        if actual.hash == expected.hash {
+            self.record(path, actual);
+            return Ok(());
+        }
+
+        Err(conflict(Some(actual)))
+
+    }
+
+}
+
+// Follows a symlink so the save replaces its target instead of the link itself
+fn resolve_target(path: &Path) -> Result<PathBuf, SaveError> {
+
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return Ok(path.to_path_buf()),
+    };
+
+    if !meta.file_type().is_symlink() {
+        return Ok(path.to_path_buf());
+    }
+
+    if let Ok(canonical) = path.canonicalize() {
+        return Ok(canonical);
+    }
+
+    // Dangling link: resolve it by hand relative to the link's directory
+    let link = fs::read_link(path).map_err(|e| SaveError::InvalidPath(e.to_string()))?;
+    Ok(match path.parent() {
+        Some(parent) if link.is_relative() => parent.join(link),
+        _ => link,
+    })
+
+}
+
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        fs::File::open(dir)?.sync_all()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = dir;
+        Ok(())
+    }
+}
+
+// Writes to a sibling temp file, fsyncs it and renames it over the target, so
+// a crash or full disk leaves either the old or the new content, never half of it
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), SaveError> {
+
+    let target = resolve_target(path)?;
+
+    let file_name = target
+        .file_name()
+        .ok_or_else(|| SaveError::InvalidPath(format!("{} has no file name", target.display())))?
+        .to_string_lossy()
+        .to_string();
+
+    let parent = match target.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    parent
+        .canonicalize()
+        .map_err(|_| SaveError::InvalidPath("Invalid destination directory".into()))?;
+
+    let existing_permissions = fs::metadata(&target).ok().map(|m| m.permissions());
+
+    let tmp_path = parent.join(format!(
+        ".{}.aqua-{}-{}.tmp",
+        file_name,
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let write_tmp = || -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&tmp_path)?;
+        file.write_all(contents)?;
+        if let Some(perms) = &existing_permissions {
+            file.set_permissions(perms.clone())?;
+        }
+        file.sync_all()
+    };
+
+    if let Err(e) = write_tmp() {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(SaveError::Write(e.to_string()));
+    }
+
+    if let Err(e) = fs::rename(&tmp_path, &target) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(SaveError::Rename(e.to_string()));
+    }
+
+    // Persist the directory entry too, otherwise the rename itself can be lost
+    let _ = sync_dir(&parent);
+
+    Ok(())
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_atomic_creates_file() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("new.md");
+
+        write_atomic(&file_path, b"# Hello").expect("Atomic write failed");
+
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "# Hello");
+    }
+
+    #[test]
+    fn test_write_atomic_overwrites_and_leaves_no_temp_files() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        fs::write(&file_path, "old content that is longer").unwrap();
+
+        write_atomic(&file_path, b"new").expect("Atomic write failed");
+
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "new");
+        let entries: Vec<_> = fs::read_dir(temp_dir.path()).unwrap().flatten().collect();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn test_write_atomic_missing_directory() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("missing/note.md");
+
+        let result = write_atomic(&file_path, b"content");
+
+        assert!(matches!(result, Err(SaveError::InvalidPath(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_preserves_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("script.md");
+        fs::write(&file_path, "old").unwrap();
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        write_atomic(&file_path, b"new").expect("Atomic write failed");
+
+        let mode = fs::metadata(&file_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_keeps_symlink() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let real_path = temp_dir.path().join("real.md");
+        let link_path = temp_dir.path().join("link.md");
+        fs::write(&real_path, "old").unwrap();
+        std::os::unix::fs::symlink(&real_path, &link_path).unwrap();
+
+        write_atomic(&link_path, b"new").expect("Atomic write failed");
+
+        assert!(fs::symlink_metadata(&link_path).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&real_path).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_save_error_serialization() {
+        let json = serde_json::to_string(&SaveError::Rename("disk full".into())).unwrap();
+        assert_eq!(json, "{\"kind\":\"rename\",\"detail\":\"disk full\"}");
+    }
+
+    #[test]
+    fn test_check_passes_for_untracked_file() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        fs::write(&file_path, "content").unwrap();
+
+        let open_files = OpenFiles::default();
+
+        assert!(open_files.check(&file_path).is_ok());
+    }
+
+    #[test]
+    fn test_check_passes_when_unchanged() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        fs::write(&file_path, "content").unwrap();
+
+        let open_files = OpenFiles::default();
+        open_files.record(&file_path, fingerprint_file(&file_path).unwrap());
+
+        assert!(open_files.check(&file_path).is_ok());
+    }
+
+    #[test]
+    fn test_check_detects_external_change() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        fs::write(&file_path, "original").unwrap();
+
+        let open_files = OpenFiles::default();
+        open_files.record(&file_path, fingerprint_file(&file_path).unwrap());
+
+        fs::write(&file_path, "changed by git pull").unwrap();
+
+        match open_files.check(&file_path) {
+            Err(SaveError::Conflict(c)) => {
+                let actual = c.actual.expect("File still exists");
+                assert_eq!(actual.hash, content_hash(b"changed by git pull"));
+                assert_ne!(actual.hash, c.expected.hash);
+            }
+            other => panic!("Expected conflict, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_check_ignores_touch_with_same_content() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        fs::write(&file_path, "same").unwrap();
+
+        let open_files = OpenFiles::default();
+        let mut stale = fingerprint_file(&file_path).unwrap();
+        stale.mtime_ms = 0;
+        open_files.record(&file_path, stale);
+
+        assert!(open_files.check(&file_path).is_ok());
+    }
+
+    #[test]
+    fn test_check_detects_deleted_file() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        fs::write(&file_path, "content").unwrap();
+
+        let open_files = OpenFiles::default();
+        open_files.record(&file_path, fingerprint_file(&file_path).unwrap());
+        fs::remove_file(&file_path).unwrap();
+
+        assert!(matches!(
+            open_files.check(&file_path),
+            Err(SaveError::Conflict(Conflict { actual: None, .. }))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_detects_deleted_file_through_symlinked_directory() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let real_dir = temp_dir.path().join("real");
+        let link_dir = temp_dir.path().join("link");
+        fs::create_dir(&real_dir).unwrap();
+        std::os::unix::fs::symlink(&real_dir, &link_dir).unwrap();
+        fs::write(real_dir.join("note.md"), "content").unwrap();
+
+        let open_files = OpenFiles::default();
+        open_files.record(&link_dir.join("note.md"), fingerprint_file(&real_dir.join("note.md")).unwrap());
+        fs::remove_file(real_dir.join("note.md")).unwrap();
+
+        assert!(matches!(
+            open_files.check(&real_dir.join("note.md")),
+            Err(SaveError::Conflict(Conflict { actual: None, .. }))
+        ));
+    }
+
+    #[test]
+    fn test_record_written_matches_disk() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+
+        let open_files = OpenFiles::default();
+        write_atomic(&file_path, b"saved").unwrap();
+        open_files.record_written(&file_path, b"saved");
+
+        assert_eq!(open_files.get(&file_path), Some(fingerprint_file(&file_path).unwrap()));
+    }
+
+    #[test]
+    fn test_rename_follows_moved_files() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let (from, to) = (temp_dir.path().join("notes"), temp_dir.path().join("archive"));
+        fs::create_dir(&from).unwrap();
+        fs::write(from.join("note.md"), "content").unwrap();
+        fs::write(temp_dir.path().join("other.md"), "other").unwrap();
+
+        let open_files = OpenFiles::default();
+        open_files.record(&from.join("note.md"), fingerprint_file(&from.join("note.md")).unwrap());
+        open_files.record(&temp_dir.path().join("other.md"), fingerprint_file(&temp_dir.path().join("other.md")).unwrap());
+        fs::rename(&from, &to).unwrap();
+        open_files.rename(&from, &to);
+
+        assert!(open_files.get(&from.join("note.md")).is_none());
+        assert!(open_files.check(&to.join("note.md")).is_ok());
+        assert_eq!(open_files.get(&to.join("note.md")), Some(fingerprint_file(&to.join("note.md")).unwrap()));
+        assert!(open_files.get(&temp_dir.path().join("other.md")).is_some());
+    }
+}
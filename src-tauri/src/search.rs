@@ -0,0 +1,323 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::{Regex, RegexBuilder};
+
+
+#[derive(serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SearchOptions {
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    // Treat the query as a regular expression instead of literal text
+    pub regex: bool,
+    // Globs relative to the workspace root; an empty include list searches everything
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // Lines of context around each match
+    pub context: usize,
+    pub max_results: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            case_sensitive: false,
+            whole_word: false,
+            regex: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            context: 1,
+            max_results: 1000,
+        }
+    }
+}
+
+// Line and column are 1-based, the column and length counted in characters
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct SearchMatch {
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    pub text: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct SearchSummary {
+    pub matches: usize,
+    pub files: usize,
+    pub cancelled: bool,
+    // True when max_results cut the search short
+    pub truncated: bool,
+}
+
+pub struct Query {
+    pattern: Regex,
+    // Literal queries take their replacement literally too, without $1 expansion
+    expand: bool,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    context: usize,
+    max_results: usize,
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).map_err(|e| e.to_string())?);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+impl Query {
+
+    pub fn new(query: &str, options: &SearchOptions) -> Result<Self, String> {
+
+        if query.is_empty() {
+            return Err("Empty search query".into());
+        }
+
+        let source = if options.regex { query.to_string() } else { regex::escape(query) };
+        let source = if options.whole_word { format!(r"\b(?:{})\b", source) } else { source };
+        let pattern = RegexBuilder::new(&source)
+            .case_insensitive(!options.case_sensitive)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        Ok(Query {
+            pattern,
+            expand: options.regex,
+            include: if options.include.is_empty() { None } else { Some(glob_set(&options.include)?) },
+            exclude: glob_set(&options.exclude)?,
+            context: options.context,
+            max_results: options.max_results,
+        })
+
+    }
+
+    pub fn max_results(&self) -> usize {
+        self.max_results
+    }
+
+    pub fn pattern(&self) -> &Regex {
+        &self.pattern
+    }
+
+    pub fn expands(&self) -> bool {
+        self.expand
+    }
+
+    // Whether a path relative to the workspace root passes the include and exclude globs
+    pub fn wants(&self, rel: &Path) -> bool {
+        let included = match &self.include {
+            Some(set) => set.is_match(rel),
+            None => true,
+        };
+        included && !self.exclude.is_match(rel)
+    }
+
+    // Every match in `text`, zero-width matches skipped
+    pub fn find_in(&self, path: &str, text: &str) -> Vec<SearchMatch> {
+
+        let lines: Vec<&str> = text.lines().collect();
+        let mut matches = Vec::new();
+
+        for (i, line) in lines.iter().enumerate() {
+            for m in self.pattern.find_iter(line).filter(|m| !m.is_empty()) {
+                matches.push(SearchMatch {
+                    path: path.to_string(),
+                    line: i + 1,
+                    column: line[..m.start()].chars().count() + 1,
+                    length: m.as_str().chars().count(),
+                    text: line.to_string(),
+                    before: lines[i.saturating_sub(self.context)..i].iter().map(|l| l.to_string()).collect(),
+                    after: lines[i + 1..(i + 1 + self.context).min(lines.len())].iter().map(|l| l.to_string()).collect(),
+                });
+            }
+        }
+
+        matches
+
+    }
+
+}
+
+// The content of a text file. Files that are not valid UTF-8 or hold a NUL byte,
+// like the PDFs and images a workspace may show, are None.
+pub fn read_text(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().filter(|text| !text.contains('\0'))
+}
+
+// Searches `files` one by one, handing each file's matches to `on_matches` as soon
+// as it is read. `cancel` is checked between files.
+pub fn search_files<F>(root: &Path, files: &[PathBuf], query: &Query, cancel: &AtomicBool, on_matches: F) -> SearchSummary
+where
+    F: FnMut(Vec<SearchMatch>),
+{
+    search_within(root, files, query, query.max_results, cancel, on_matches)
+}
+
+// `search_files` with room for only `budget` more matches, for searching several
+// roots under one limit. With no room left it stops at the first match and reports
+// the search as truncated.
+pub fn search_within<F>(root: &Path, files: &[PathBuf], query: &Query, budget: usize, cancel: &AtomicBool, mut on_matches: F) -> SearchSummary
+where
+    F: FnMut(Vec<SearchMatch>),
+{
+
+    let mut summary = SearchSummary { matches: 0, files: 0, cancelled: false, truncated: false };
+
+    for file in files {
+
+        if cancel.load(Ordering::Relaxed) {
+            summary.cancelled = true;
+            break;
+        }
+        if !query.wants(file.strip_prefix(root).unwrap_or(file)) {
+            continue;
+        }
+
+        let text = match read_text(file) {
+            Some(text) => text,
+            None => continue,
+        };
+
+        let mut matches = query.find_in(&file.to_string_lossy(), &text);
+        if matches.is_empty() {
+            continue;
+        }
+
+        let room = budget - summary.matches;
+        if matches.len() > room {
+            summary.truncated = true;
+            matches.truncate(room);
+            if matches.is_empty() {
+                break;
+            }
+        }
+
+        summary.matches += matches.len();
+        summary.files += 1;
+        on_matches(matches);
+
+        if summary.matches >= budget {
+            break;
+        }
+
+    }
+
+    summary
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{list_files, TreeOptions};
+    use tempfile::TempDir;
+
+    fn query(text: &str, options: SearchOptions) -> Query {
+        Query::new(text, &options).expect("Failed to build query")
+    }
+
+    #[test]
+    fn test_find_literal_case_insensitive() {
+        let q = query("rust", SearchOptions::default());
+
+        let matches = q.find_in("/a.md", "intro\nRust and rust\noutro");
+
+        assert_eq!(matches.len(), 2);
+        assert_eq!((matches[0].line, matches[0].column, matches[0].length), (2, 1, 4));
+        assert_eq!(matches[1].column, 10);
+        assert_eq!(matches[0].before, vec!["intro".to_string()]);
+        assert_eq!(matches[0].after, vec!["outro".to_string()]);
+    }
+
+    #[test]
+    fn test_find_case_sensitive_whole_word() {
+        let q = query("Note", SearchOptions { case_sensitive: true, whole_word: true, ..Default::default() });
+
+        let matches = q.find_in("/a.md", "Notes, note and Note.");
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].column, 17);
+    }
+
+    #[test]
+    fn test_find_regex_and_literal_escaping() {
+        let regex = query(r"\d{4}-\d{2}", SearchOptions { regex: true, ..Default::default() });
+        let literal = query("a.b", SearchOptions::default());
+
+        assert_eq!(regex.find_in("/a.md", "due 2024-05-01").len(), 1);
+        assert_eq!(literal.find_in("/a.md", "axb a.b").len(), 1);
+        assert!(Query::new("(", &SearchOptions { regex: true, ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_columns_count_characters() {
+        let q = query("x", SearchOptions::default());
+
+        let matches = q.find_in("/a.md", "ééx");
+
+        assert_eq!(matches[0].column, 3);
+    }
+
+    #[test]
+    fn test_search_files_with_globs() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("journal")).unwrap();
+        fs::write(root.join("journal/day.md"), "todo: write").unwrap();
+        fs::write(root.join("notes.md"), "todo: read").unwrap();
+        let files = list_files(root, &TreeOptions::default_for(root));
+        let q = query("todo", SearchOptions { include: vec!["journal/**".into()], ..Default::default() });
+
+        let mut found = Vec::new();
+        let summary = search_files(root, &files, &q, &AtomicBool::new(false), |m| found.extend(m));
+
+        assert_eq!(summary, SearchSummary { matches: 1, files: 1, cancelled: false, truncated: false });
+        assert!(found[0].path.ends_with("day.md"));
+    }
+
+    #[test]
+    fn test_search_files_cancel_and_limit() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "x x x").unwrap();
+        fs::write(root.join("b.md"), "x").unwrap();
+        let files = list_files(root, &TreeOptions::default_for(root));
+
+        let cancelled = search_files(root, &files, &query("x", SearchOptions::default()), &AtomicBool::new(true), |_| {});
+        assert!(cancelled.cancelled);
+        assert_eq!(cancelled.matches, 0);
+
+        let limited = search_files(root, &files, &query("x", SearchOptions { max_results: 2, ..Default::default() }), &AtomicBool::new(false), |_| {});
+        assert_eq!(limited.matches, 2);
+        assert!(limited.truncated);
+
+        // A later root searched with the limit used up only tells whether it had more
+        let spent = search_within(root, &files, &query("x", SearchOptions::default()), 0, &AtomicBool::new(false), |_| panic!("No room for matches"));
+        assert_eq!(spent.matches, 0);
+        assert!(spent.truncated);
+    }
+
+    #[test]
+    fn test_search_files_skips_binary_files() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("note.md"), "Pages of notes").unwrap();
+        fs::write(root.join("doc.pdf"), b"%PDF-1.4\n/Type /Pages\n\0\xff\xfe").unwrap();
+        fs::write(root.join("latin1.md"), b"Pages \xe9t\xe9").unwrap();
+        let files = vec![root.join("note.md"), root.join("doc.pdf"), root.join("latin1.md")];
+
+        let mut found = Vec::new();
+        search_files(root, &files, &query("Pages", SearchOptions::default()), &AtomicBool::new(false), |m| found.extend(m));
+
+        assert_eq!(found.len(), 1);
+        assert!(found[0].path.ends_with("note.md"));
+    }
+}
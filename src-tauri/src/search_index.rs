@@ -0,0 +1,632 @@
+use std::fs;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::save::{mtime_ms, write_atomic};
+use crate::search::read_text;
+use crate::settings::workspace_key;
+use crate::tree::{list_files, TreeOptions};
+use crate::watch::{DeltaKind, PathDelta};
+
+// Bumped whenever the stored layout changes, so old files are rebuilt instead of misread
+const FORMAT: u32 = 2;
+
+// BM25 parameters
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Doc {
+    path: String,
+    mtime_ms: u64,
+    size: u64,
+    // Number of tokens, for length normalisation
+    len: u32,
+    // Distinct terms of the document, so removing it only touches their postings
+    terms: Vec<String>,
+}
+
+// Inverted index of the notes of one workspace. Postings map each term to the
+// documents containing it and the token positions there, which phrase queries need.
+// Terms are kept sorted so prefix queries are a range scan.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    root: PathBuf,
+    docs: HashMap<u32, Doc>,
+    ids: HashMap<String, u32>,
+    postings: BTreeMap<String, HashMap<u32, Vec<u32>>>,
+    next_id: u32,
+    total_len: u64,
+}
+
+// The stored form of an index. Each term is written once and every document as
+// its token stream, as indexes into that vocabulary; postings are rebuilt on load.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredIndex {
+    format: u32,
+    root: PathBuf,
+    next_id: u32,
+    terms: Vec<String>,
+    docs: Vec<StoredDoc>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredDoc {
+    id: u32,
+    path: String,
+    mtime_ms: u64,
+    size: u64,
+    tokens: Vec<u32>,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct IndexHit {
+    pub path: String,
+    pub score: f64,
+}
+
+#[derive(Debug, PartialEq)]
+enum Clause {
+    Term(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+}
+
+// Lowercased alphanumeric runs; a token's position is its index
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+// Words must all match; "quoted words" form a phrase and a trailing * makes a prefix
+fn parse_query(query: &str) -> Vec<Clause> {
+
+    let mut clauses = Vec::new();
+
+    for (i, part) in query.split('"').enumerate() {
+        if i % 2 == 1 {
+            let words = tokenize(part);
+            match words.len() {
+                0 => {}
+                1 => clauses.push(Clause::Term(words[0].clone())),
+                _ => clauses.push(Clause::Phrase(words)),
+            }
+            continue;
+        }
+        for word in part.split_whitespace() {
+            let prefix = word.ends_with('*');
+            let mut tokens = tokenize(word);
+            let last = match tokens.pop() {
+                Some(last) => last,
+                None => continue,
+            };
+            clauses.extend(tokens.into_iter().map(Clause::Term));
+            clauses.push(if prefix { Clause::Prefix(last) } else { Clause::Term(last) });
+        }
+    }
+
+    clauses
+
+}
+
+fn index_path(data_dir: &Path, root: &Path) -> PathBuf {
+    data_dir.join("index").join(format!("{}.json", workspace_key(root)))
+}
+
+// Writes the bytes of `SearchIndex::to_bytes` where `load` finds them
+pub fn write_stored(data_dir: &Path, root: &Path, bytes: &[u8]) -> Result<(), String> {
+
+    let path = index_path(data_dir, root);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    write_atomic(&path, bytes).map_err(|e| e.to_string())
+
+}
+
+impl SearchIndex {
+
+    pub fn new(root: &Path) -> Self {
+        SearchIndex {
+            root: root.to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // The stored index of a workspace, or an empty one when there is none yet or it is unreadable
+    pub fn load(data_dir: &Path, root: &Path) -> Self {
+        fs::read(index_path(data_dir, root))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<StoredIndex>(&bytes).ok())
+            .filter(|stored| stored.format == FORMAT && stored.root == root)
+            .and_then(Self::from_stored)
+            .unwrap_or_else(|| Self::new(root))
+    }
+
+    fn from_stored(stored: StoredIndex) -> Option<Self> {
+
+        let mut index = SearchIndex::new(&stored.root);
+        index.next_id = stored.next_id;
+
+        for doc in stored.docs {
+            let mut terms = Vec::new();
+            for (position, token) in doc.tokens.iter().enumerate() {
+                let term = stored.terms.get(*token as usize)?;
+                let docs = index.postings.entry(term.clone()).or_default();
+                if !docs.contains_key(&doc.id) {
+                    terms.push(term.clone());
+                }
+                docs.entry(doc.id).or_default().push(position as u32);
+            }
+            index.total_len += doc.tokens.len() as u64;
+            index.ids.insert(doc.path.clone(), doc.id);
+            index.docs.insert(doc.id, Doc { path: doc.path, mtime_ms: doc.mtime_ms, size: doc.size, len: doc.tokens.len() as u32, terms });
+        }
+
+        Some(index)
+
+    }
+
+    // Serializes the index in its stored form. This is the only part of persisting
+    // that needs the index, so callers can write the bytes after letting go of it.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+
+        let vocabulary: HashMap<&str, u32> = self.postings.keys().enumerate().map(|(i, term)| (term.as_str(), i as u32)).collect();
+
+        let mut docs: Vec<StoredDoc> = self
+            .docs
+            .iter()
+            .map(|(id, doc)| {
+                let mut tokens = vec![0; doc.len as usize];
+                for term in &doc.terms {
+                    for position in self.postings.get(term).and_then(|docs| docs.get(id)).into_iter().flatten() {
+                        tokens[*position as usize] = vocabulary[term.as_str()];
+                    }
+                }
+                StoredDoc { id: *id, path: doc.path.clone(), mtime_ms: doc.mtime_ms, size: doc.size, tokens }
+            })
+            .collect();
+        docs.sort_by_key(|doc| doc.id);
+
+        let stored = StoredIndex {
+            format: FORMAT,
+            root: self.root.clone(),
+            next_id: self.next_id,
+            terms: self.postings.keys().cloned().collect(),
+            docs,
+        };
+        serde_json::to_vec(&stored).map_err(|e| e.to_string())
+
+    }
+
+    pub fn store(&self, data_dir: &Path) -> Result<(), String> {
+        write_stored(data_dir, &self.root, &self.to_bytes()?)
+    }
+
+    fn remove(&mut self, path: &str) -> bool {
+
+        let id = match self.ids.remove(path) {
+            Some(id) => id,
+            None => return false,
+        };
+        let doc = match self.docs.remove(&id) {
+            Some(doc) => doc,
+            None => return true,
+        };
+        self.total_len -= doc.len as u64;
+
+        for term in &doc.terms {
+            if let Some(docs) = self.postings.get_mut(term) {
+                docs.remove(&id);
+                if docs.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+
+        true
+
+    }
+
+    // Drops a path and, for a directory, everything below it
+    fn remove_under(&mut self, path: &Path) -> bool {
+        let paths: Vec<String> = self
+            .ids
+            .keys()
+            .filter(|p| Path::new(p).starts_with(path))
+            .cloned()
+            .collect();
+        let mut changed = false;
+        for p in paths {
+            changed |= self.remove(&p);
+        }
+        changed
+    }
+
+    // (Re)indexes a file when it is new or changed on disk since it was indexed
+    fn update(&mut self, path: &Path) -> bool {
+
+        let meta = match fs::metadata(path) {
+            Ok(meta) if meta.is_file() => meta,
+            _ => return self.remove(&path.to_string_lossy()),
+        };
+        let key = path.to_string_lossy().to_string();
+        if let Some(doc) = self.ids.get(&key).and_then(|id| self.docs.get(id)) {
+            if doc.mtime_ms == mtime_ms(&meta) && doc.size == meta.len() {
+                return false;
+            }
+        }
+
+        // Binary and unreadable files, e.g. PDFs a workspace shows, stay in as empty
+        // documents so they are not read again until they change
+        let text = read_text(path).unwrap_or_default();
+        self.remove(&key);
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let tokens = tokenize(&text);
+        let mut terms = Vec::new();
+        for (position, token) in tokens.iter().enumerate() {
+            let docs = self.postings.entry(token.clone()).or_default();
+            if !docs.contains_key(&id) {
+                terms.push(token.clone());
+            }
+            docs.entry(id).or_default().push(position as u32);
+        }
+
+        self.total_len += tokens.len() as u64;
+        self.docs.insert(id, Doc { path: key.clone(), mtime_ms: mtime_ms(&meta), size: meta.len(), len: tokens.len() as u32, terms });
+        self.ids.insert(key, id);
+        true
+
+    }
+
+    // Brings the index in line with the files the workspace shows, reading only
+    // the ones that changed since the index was stored. Returns whether anything changed.
+    pub fn sync(&mut self, files: &[PathBuf]) -> bool {
+
+        let wanted: HashSet<String> = files.iter().map(|f| f.to_string_lossy().to_string()).collect();
+        let stale: Vec<String> = self.ids.keys().filter(|p| !wanted.contains(*p)).cloned().collect();
+
+        let mut changed = false;
+        for path in stale {
+            changed |= self.remove(&path);
+        }
+        for file in files {
+            changed |= self.update(file);
+        }
+
+        changed
+
+    }
+
+    // Applies a batch of watcher deltas, walking created or renamed directories
+    pub fn apply(&mut self, deltas: &[PathDelta], options: &TreeOptions) -> bool {
+
+        let mut changed = false;
+
+        for delta in deltas {
+            let path = Path::new(&delta.path);
+            if delta.kind == DeltaKind::Removed || !path.exists() {
+                changed |= self.remove_under(path);
+            }
+            else if path.is_dir() {
+                for file in list_files(path, options) {
+                    changed |= self.update(&file);
+                }
+            }
+            else if options.is_relevant(path, delta.kind) {
+                changed |= self.update(path);
+            }
+        }
+
+        changed
+
+    }
+
+    // BM25 contribution of every document containing `term`
+    fn term_scores(&self, term: &str, collection: &Collection) -> HashMap<u32, f64> {
+
+        let postings = match self.postings.get(term) {
+            Some(postings) => postings,
+            None => return HashMap::new(),
+        };
+
+        let idf = collection.idf(term);
+        let avg_len = collection.avg_len();
+
+        postings
+            .iter()
+            .map(|(id, positions)| {
+                let tf = positions.len() as f64;
+                let len = self.docs.get(id).map_or(0.0, |doc| doc.len as f64);
+                let norm = K1 * (1.0 - B + B * len / avg_len.max(1.0));
+                (*id, idf * tf * (K1 + 1.0) / (tf + norm))
+            })
+            .collect()
+
+    }
+
+    fn clause_scores(&self, clause: &Clause, collection: &Collection) -> HashMap<u32, f64> {
+        match clause {
+            Clause::Term(term) => self.term_scores(term, collection),
+            Clause::Prefix(prefix) => {
+                let mut scores: HashMap<u32, f64> = HashMap::new();
+                for term in self.postings.range(prefix.clone()..).map(|(t, _)| t).take_while(|t| t.starts_with(prefix.as_str())) {
+                    for (id, score) in self.term_scores(term, collection) {
+                        let best = scores.entry(id).or_default();
+                        *best = best.max(score);
+                    }
+                }
+                scores
+            }
+            Clause::Phrase(words) => {
+                let mut scores: HashMap<u32, f64> = HashMap::new();
+                for word in words {
+                    for (id, score) in self.term_scores(word, collection) {
+                        *scores.entry(id).or_default() += score;
+                    }
+                }
+                scores.retain(|id, _| self.has_phrase(*id, words));
+                scores
+            }
+        }
+    }
+
+    fn has_phrase(&self, id: u32, words: &[String]) -> bool {
+        let positions: Vec<&Vec<u32>> = match words
+            .iter()
+            .map(|w| self.postings.get(w).and_then(|docs| docs.get(&id)))
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(positions) => positions,
+            None => return false,
+        };
+        positions[0].iter().any(|start| {
+            positions[1..]
+                .iter()
+                .enumerate()
+                .all(|(i, later)| later.binary_search(&(start + i as u32 + 1)).is_ok())
+        })
+    }
+
+    // Documents matching every clause, with BM25 statistics taken from the whole collection
+    fn query_in(&self, clauses: &[Clause], collection: &Collection, limit: usize) -> Vec<IndexHit> {
+
+        let mut total: Option<HashMap<u32, f64>> = None;
+
+        for clause in clauses {
+            let scores = self.clause_scores(clause, collection);
+            total = Some(match total {
+                None => scores,
+                Some(mut total) => {
+                    total.retain(|id, _| scores.contains_key(id));
+                    for (id, score) in total.iter_mut() {
+                        *score += scores[id];
+                    }
+                    total
+                }
+            });
+        }
+
+        let mut hits: Vec<IndexHit> = total
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(id, score)| self.docs.get(&id).map(|doc| IndexHit { path: doc.path.clone(), score }))
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+        hits.truncate(limit);
+        hits
+
+    }
+
+}
+
+// The indexes a query runs against. BM25 takes the document count, average length
+// and document frequencies from all of them, so scores from several root folders
+// compare and can be merged as they are.
+struct Collection<'a> {
+    indexes: &'a [&'a SearchIndex],
+}
+
+impl Collection<'_> {
+
+    fn docs(&self) -> usize {
+        self.indexes.iter().map(|index| index.docs.len()).sum()
+    }
+
+    fn avg_len(&self) -> f64 {
+        let total_len: u64 = self.indexes.iter().map(|index| index.total_len).sum();
+        total_len as f64 / self.docs().max(1) as f64
+    }
+
+    fn idf(&self, term: &str) -> f64 {
+        let n = self.docs() as f64;
+        let df = self.indexes.iter().filter_map(|index| index.postings.get(term)).map(HashMap::len).sum::<usize>() as f64;
+        (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+    }
+
+}
+
+// Documents of several root folders matching every clause of `query`, best first
+pub fn query_all(indexes: &[&SearchIndex], query: &str, limit: usize) -> Vec<IndexHit> {
+
+    let clauses = parse_query(query);
+    let collection = Collection { indexes };
+
+    let mut hits: Vec<IndexHit> = indexes
+        .iter()
+        .flat_map(|index| index.query_in(&clauses, &collection, limit))
+        .collect();
+
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+    hits.truncate(limit);
+    hits
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn indexed(root: &Path) -> SearchIndex {
+        let mut index = SearchIndex::new(root);
+        index.sync(&list_files(root, &TreeOptions::default_for(root)));
+        index
+    }
+
+    fn names(hits: &[IndexHit]) -> Vec<String> {
+        hits.iter().map(|h| Path::new(&h.path).file_name().unwrap().to_string_lossy().to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            parse_query("Rust \"borrow checker\" life*"),
+            vec![
+                Clause::Term("rust".into()),
+                Clause::Phrase(vec!["borrow".into(), "checker".into()]),
+                Clause::Prefix("life".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_ranks_by_bm25() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("often.md"), "tauri tauri tauri notes").unwrap();
+        fs::write(root.join("once.md"), "tauri and a lot of other words in this note").unwrap();
+        fs::write(root.join("never.md"), "nothing relevant").unwrap();
+
+        let hits = query_all(&[&indexed(root)], "tauri", 10);
+
+        assert_eq!(names(&hits), vec!["often.md", "once.md"]);
+        assert!(hits[0].score > hits[1].score);
+    }
+
+    #[test]
+    fn test_query_all_shares_statistics_across_roots() {
+        let (vault, small) = (TempDir::new().expect("Failed to create temp dir"), TempDir::new().expect("Failed to create temp dir"));
+        fs::write(vault.path().join("strong.md"), "tauri tauri tauri").unwrap();
+        for i in 0..8 {
+            fs::write(vault.path().join(format!("other{}.md", i)), "unrelated notes about something else").unwrap();
+        }
+        // The only match of its root, but a weak one in a long note
+        fs::write(small.path().join("weak.md"), "tauri is mentioned once among a great many other words in this rather long note").unwrap();
+        let (vault, small) = (indexed(vault.path()), indexed(small.path()));
+
+        let hits = query_all(&[&small, &vault], "tauri", 10);
+
+        assert_eq!(names(&hits), vec!["strong.md", "weak.md"]);
+        assert!(hits[0].score > hits[1].score * 1.5);
+    }
+
+    #[test]
+    fn test_binary_files_are_not_indexed() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("note.md"), "the catalog of notes").unwrap();
+        fs::write(root.join("doc.pdf"), b"%PDF-1.4\n/Type /Catalog\n\0\xff\xfe").unwrap();
+        let mut index = SearchIndex::new(root);
+        index.sync(&[root.join("note.md"), root.join("doc.pdf")]);
+
+        assert_eq!(names(&query_all(&[&index], "catalog", 10)), vec!["note.md"]);
+        // Unchanged, so not read again
+        assert!(!index.sync(&[root.join("note.md"), root.join("doc.pdf")]));
+    }
+
+    #[test]
+    fn test_query_prefix_and_phrase() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "The borrow checker helps").unwrap();
+        fs::write(root.join("b.md"), "checker of the borrow").unwrap();
+        let index = indexed(root);
+
+        assert_eq!(names(&query_all(&[&index], "\"borrow checker\"", 10)), vec!["a.md"]);
+        assert_eq!(query_all(&[&index], "check*", 10).len(), 2);
+        assert_eq!(names(&query_all(&[&index], "borrow help*", 10)), vec!["a.md"]);
+        assert!(query_all(&[&index], "missing", 10).is_empty());
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let data_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "persistent words").unwrap();
+
+        indexed(root).store(data_dir.path()).expect("Failed to store index");
+        let mut loaded = SearchIndex::load(data_dir.path(), root);
+
+        assert_eq!(loaded.docs.len(), 1);
+        assert_eq!(names(&query_all(&[&loaded], "persistent", 10)), vec!["a.md"]);
+        assert_eq!(names(&query_all(&[&loaded], "\"persistent words\"", 10)), vec!["a.md"]);
+        // Nothing changed on disk, so syncing reads nothing
+        assert!(!loaded.sync(&[root.join("a.md")]));
+    }
+
+    #[test]
+    fn test_apply_watcher_deltas() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "old text").unwrap();
+        let mut index = indexed(root);
+        let options = TreeOptions::default_for(root);
+
+        fs::write(root.join("a.md"), "new text, longer than before").unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/b.md"), "new note").unwrap();
+        let changed = index.apply(
+            &[
+                PathDelta { path: root.join("a.md").to_string_lossy().to_string(), kind: DeltaKind::Modified, renamed_from: None },
+                PathDelta { path: root.join("sub").to_string_lossy().to_string(), kind: DeltaKind::Created, renamed_from: None },
+            ],
+            &options,
+        );
+
+        assert!(changed);
+        assert!(query_all(&[&index], "old", 10).is_empty());
+        assert_eq!(query_all(&[&index], "new", 10).len(), 2);
+
+        fs::remove_dir_all(root.join("sub")).unwrap();
+        index.apply(&[PathDelta { path: root.join("sub").to_string_lossy().to_string(), kind: DeltaKind::Removed, renamed_from: None }], &options);
+
+        assert_eq!(names(&query_all(&[&index], "new", 10)), vec!["a.md"]);
+        assert!(!index.postings.contains_key("note"));
+    }
+
+    #[test]
+    fn test_remove_keeps_other_postings() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let data_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "shared alpha alpha").unwrap();
+        fs::write(root.join("b.md"), "shared beta").unwrap();
+        let mut index = indexed(root);
+
+        assert!(index.remove(&root.join("a.md").to_string_lossy()));
+
+        assert!(!index.postings.contains_key("alpha"));
+        assert_eq!(names(&query_all(&[&index], "shared", 10)), vec!["b.md"]);
+        assert_eq!(index.total_len, 2);
+
+        // A document added after a removal round-trips through the stored form
+        fs::write(root.join("c.md"), "beta gamma beta").unwrap();
+        index.sync(&[root.join("b.md"), root.join("c.md")]);
+        index.store(data_dir.path()).expect("Failed to store index");
+        let loaded = SearchIndex::load(data_dir.path(), root);
+        assert_eq!(loaded.postings, index.postings);
+        assert_eq!(loaded.docs, index.docs);
+        assert_eq!(loaded.next_id, index.next_id);
+    }
+}
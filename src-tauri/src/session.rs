@@ -0,0 +1,180 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::save::write_atomic;
+
+// Where the editor stood in an open file
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct FileState {
+    pub path: String,
+    // Character offset of the cursor
+    pub cursor: usize,
+    pub scroll_top: f64,
+}
+
+// Outer position and inner size in physical pixels
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Geometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct WindowSession {
+    pub label: String,
+    // Root folders in sidebar order
+    pub roots: Vec<String>,
+    pub files: Vec<FileState>,
+    pub active: Option<String>,
+    // Directories expanded in the sidebar
+    pub expanded: Vec<String>,
+    pub geometry: Option<Geometry>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Session {
+    pub windows: Vec<WindowSession>,
+}
+
+impl WindowSession {
+
+    // Forgets paths that no longer exist and returns them
+    pub fn prune(&mut self) -> Vec<String> {
+
+        let mut missing = Vec::new();
+        let mut keep = |path: &String, is_dir: bool| {
+            let p = Path::new(path);
+            let found = if is_dir { p.is_dir() } else { p.is_file() };
+            if !found {
+                missing.push(path.clone());
+            }
+            found
+        };
+
+        self.roots.retain(|root| keep(root, true));
+        self.files.retain(|file| keep(&file.path, false));
+        let roots = &self.roots;
+        self.expanded.retain(|dir| Path::new(dir).is_dir() && roots.iter().any(|root| Path::new(dir).starts_with(root)));
+        if self.active.as_ref().is_some_and(|active| !self.files.iter().any(|file| file.path == *active)) {
+            self.active = None;
+        }
+
+        missing
+
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty() && self.files.is_empty()
+    }
+
+}
+
+impl Session {
+
+    pub fn window(&self, label: &str) -> Option<&WindowSession> {
+        self.windows.iter().find(|window| window.label == label)
+    }
+
+    // Replaces the saved state of a window, adding it when new
+    pub fn update(&mut self, window: WindowSession) {
+        match self.windows.iter_mut().find(|w| w.label == window.label) {
+            Some(current) => *current = window,
+            None => self.windows.push(window),
+        }
+    }
+
+    pub fn remove(&mut self, label: &str) {
+        self.windows.retain(|window| window.label != label);
+    }
+
+}
+
+fn session_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("session.json")
+}
+
+// A missing or unreadable session starts empty
+pub fn load(config_dir: &Path) -> Session {
+    fs::read_to_string(session_path(config_dir))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn store(config_dir: &Path, session: &Session) -> Result<(), String> {
+    fs::create_dir_all(config_dir).map_err(|e| e.to_string())?;
+    let json = serde_json::to_string_pretty(session).map_err(|e| e.to_string())?;
+    write_atomic(&session_path(config_dir), json.as_bytes()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_store_and_load() {
+        let config_dir = TempDir::new().expect("Failed to create temp dir");
+        assert_eq!(load(config_dir.path()), Session::default());
+
+        let mut session = Session::default();
+        session.update(WindowSession {
+            label: "main".into(),
+            roots: vec!["/vault".into()],
+            files: vec![FileState { path: "/vault/a.md".into(), cursor: 42, scroll_top: 120.5 }],
+            active: Some("/vault/a.md".into()),
+            expanded: vec!["/vault/sub".into()],
+            geometry: Some(Geometry { x: 10, y: 20, width: 1200, height: 1000, maximized: false }),
+        });
+        session.update(WindowSession { label: "main".into(), roots: vec!["/other".into()], ..WindowSession::default() });
+        store(config_dir.path(), &session).expect("Failed to store session");
+
+        let loaded = load(config_dir.path());
+        assert_eq!(loaded, session);
+        assert_eq!(loaded.windows.len(), 1);
+        assert_eq!(loaded.window("main").unwrap().roots, vec!["/other".to_string()]);
+    }
+
+    #[test]
+    fn test_load_ignores_corrupt_file() {
+        let config_dir = TempDir::new().expect("Failed to create temp dir");
+        fs::write(config_dir.path().join("session.json"), "{\"windows\": [").unwrap();
+        assert_eq!(load(config_dir.path()), Session::default());
+    }
+
+    #[test]
+    fn test_prune_forgets_missing_paths() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("kept.md"), "").unwrap();
+        let path = |name: &str| root.join(name).to_string_lossy().to_string();
+        let root_path = root.to_string_lossy().to_string();
+
+        let mut window = WindowSession {
+            label: "main".into(),
+            roots: vec![root_path.clone(), path("gone")],
+            files: vec![
+                FileState { path: path("kept.md"), ..FileState::default() },
+                FileState { path: path("deleted.md"), ..FileState::default() },
+            ],
+            active: Some(path("deleted.md")),
+            expanded: vec![path("sub"), path("gone/sub")],
+            geometry: None,
+        };
+
+        let missing = window.prune();
+        assert_eq!(missing, vec![path("gone"), path("deleted.md")]);
+        assert_eq!(window.roots, vec![root_path]);
+        assert_eq!(window.files.len(), 1);
+        assert_eq!(window.active, None);
+        assert_eq!(window.expanded, vec![path("sub")]);
+    }
+}
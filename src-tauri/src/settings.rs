@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::save::{content_hash, write_atomic};
+
+// Which files the sidebar shows. Extensions are matched case-insensitively
+// without the dot, exclude globs are relative to the workspace root.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct FileFilter {
+    pub extensions: Vec<String>,
+    pub exclude: Vec<String>,
+    // Honor .gitignore, .ignore and .aquaignore files
+    pub respect_ignore_files: bool,
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        FileFilter {
+            extensions: vec!["md".into()],
+            exclude: Vec::new(),
+            respect_ignore_files: true,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct WorkspaceSettings {
+    pub filter: FileFilter,
+    // Walk into symlinked directories; loops are never entered either way
+    pub follow_symlinks: bool,
+}
+
+impl Default for WorkspaceSettings {
+    fn default() -> Self {
+        WorkspaceSettings {
+            filter: FileFilter::default(),
+            follow_symlinks: true,
+        }
+    }
+}
+
+// Short stable name for a workspace, used for its files under the app dirs
+pub fn workspace_key(root: &Path) -> String {
+    content_hash(root.to_string_lossy().as_bytes())[..16].to_string()
+}
+
+// One settings file per workspace under the app config dir, named after the root path
+fn settings_path(config_dir: &Path, root: &Path) -> PathBuf {
+    config_dir.join("workspaces").join(format!("{}.json", workspace_key(root)))
+}
+
+// Missing or unreadable settings fall back to the defaults
+pub fn load(config_dir: &Path, root: &Path) -> WorkspaceSettings {
+    fs::read_to_string(settings_path(config_dir, root))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn store(config_dir: &Path, root: &Path, settings: &WorkspaceSettings) -> Result<(), String> {
+
+    let path = settings_path(config_dir, root);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    let json = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+    write_atomic(&path, json.as_bytes()).map_err(|e| e.to_string())
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_load_defaults_when_missing() {
+        let config_dir = TempDir::new().expect("Failed to create temp dir");
+
+        let settings = load(config_dir.path(), Path::new("/some/vault"));
+
+        assert_eq!(settings, WorkspaceSettings::default());
+        assert_eq!(settings.filter.extensions, vec!["md".to_string()]);
+    }
+
+    #[test]
+    fn test_store_and_load_per_workspace() {
+        let config_dir = TempDir::new().expect("Failed to create temp dir");
+        let settings = WorkspaceSettings {
+            filter: FileFilter {
+                extensions: vec!["md".into(), "mdx".into(), "markdown".into()],
+                exclude: vec!["node_modules/**".into()],
+                respect_ignore_files: false,
+            },
+            follow_symlinks: false,
+        };
+
+        store(config_dir.path(), Path::new("/vault/a"), &settings).expect("Failed to store settings");
+
+        assert_eq!(load(config_dir.path(), Path::new("/vault/a")), settings);
+        assert_eq!(load(config_dir.path(), Path::new("/vault/b")), WorkspaceSettings::default());
+    }
+
+    #[test]
+    fn test_load_fills_missing_fields() {
+        let settings: WorkspaceSettings = serde_json::from_str("{\"filter\":{\"exclude\":[\"drafts/**\"]}}").unwrap();
+
+        assert_eq!(settings.filter.extensions, vec!["md".to_string()]);
+        assert_eq!(settings.filter.exclude, vec!["drafts/**".to_string()]);
+        assert!(settings.filter.respect_ignore_files);
+    }
+}
@@ -0,0 +1,203 @@
+use std::fs;
+use std::cmp::Ordering;
+use std::path::Path;
+
+use crate::save::write_atomic;
+use crate::tree::FileNode;
+
+// Per-folder sidecar listing the manual order, one name per line
+pub const ORDER_FILE: &str = ".aquaorder";
+
+pub fn is_order_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == ORDER_FILE)
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Name,
+    // Runs of digits compare by value, so "2-foo" comes before "10-foo"
+    Natural,
+    Modified,
+    Created,
+    Size,
+    // The order saved in the folder's ORDER_FILE, unlisted entries after it by name
+    Manual,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+fn by_name(a: &FileNode, b: &FileNode) -> Ordering {
+    a.name.to_lowercase().cmp(&b.name.to_lowercase()).then_with(|| a.name.cmp(&b.name))
+}
+
+// Case-insensitive, with digit runs compared as numbers of any length
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+
+    let (a, b) = (a.to_lowercase(), b.to_lowercase());
+    let (mut a, mut b) = (a.as_str(), b.as_str());
+
+    loop {
+        let (x, y) = match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => (x, y),
+        };
+
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let a_len = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let b_len = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let a_num = a[..a_len].trim_start_matches('0');
+            let b_num = b[..b_len].trim_start_matches('0');
+            let ord = a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            a = &a[a_len..];
+            b = &b[b_len..];
+        }
+        else {
+            if x != y {
+                return x.cmp(&y);
+            }
+            a = &a[x.len_utf8()..];
+            b = &b[y.len_utf8()..];
+        }
+    }
+
+}
+
+impl SortOrder {
+
+    // Directories stay on top for every key but the manual one, where the user
+    // placed them. Ties fall back to the name so the order is stable.
+    pub fn compare(&self, a: &FileNode, b: &FileNode, manual: &[String]) -> Ordering {
+
+        if self.key != SortKey::Manual && a.is_dir != b.is_dir {
+            return b.is_dir.cmp(&a.is_dir);
+        }
+
+        let meta_key = |node: &FileNode| match (self.key, &node.meta) {
+            (SortKey::Modified, Some(meta)) => Some(meta.modified_ms),
+            (SortKey::Created, Some(meta)) => meta.created_ms,
+            (SortKey::Size, Some(meta)) => Some(meta.size),
+            _ => None,
+        };
+        let position = |node: &FileNode| manual.iter().position(|name| *name == node.name).unwrap_or(usize::MAX);
+
+        let ord = match self.key {
+            SortKey::Name => by_name(a, b),
+            SortKey::Natural => natural_cmp(&a.name, &b.name).then_with(|| by_name(a, b)),
+            SortKey::Modified | SortKey::Created | SortKey::Size => meta_key(a).cmp(&meta_key(b)).then_with(|| by_name(a, b)),
+            SortKey::Manual => position(a).cmp(&position(b)).then_with(|| by_name(a, b)),
+        };
+
+        if self.descending { ord.reverse() } else { ord }
+
+    }
+
+    // Sorts the children of `dir`, reading its manual order when needed
+    pub fn sort(&self, dir: &Path, nodes: &mut [FileNode]) {
+        let manual = self.manual_order(dir);
+        nodes.sort_by(|a, b| self.compare(a, b, &manual));
+    }
+
+    pub fn manual_order(&self, dir: &Path) -> Vec<String> {
+        if self.key == SortKey::Manual { read_order(dir) } else { Vec::new() }
+    }
+
+}
+
+pub fn read_order(dir: &Path) -> Vec<String> {
+    fs::read_to_string(dir.join(ORDER_FILE))
+        .map(|text| text.lines().filter(|l| !l.is_empty()).map(String::from).collect())
+        .unwrap_or_default()
+}
+
+pub fn write_order(dir: &Path, names: &[String]) -> Result<(), String> {
+    let mut text = names.join("\n");
+    text.push('\n');
+    write_atomic(&dir.join(ORDER_FILE), text.as_bytes()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::FileMeta;
+    use tempfile::TempDir;
+
+    fn node(name: &str, is_dir: bool, size: u64, modified_ms: u64) -> FileNode {
+        FileNode {
+            name: name.to_string(),
+            path: format!("/vault/{}", name),
+            is_dir,
+            children: None,
+            has_children: false,
+            is_symlink: false,
+            link_target: None,
+            meta: (!is_dir).then_some(FileMeta { size, modified_ms, created_ms: None }),
+            git_status: None,
+        }
+    }
+
+    fn names(nodes: &[FileNode]) -> Vec<&str> {
+        nodes.iter().map(|n| n.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        assert_eq!(natural_cmp("2-foo", "10-foo"), Ordering::Less);
+        assert_eq!(natural_cmp("file010", "file9"), Ordering::Greater);
+        assert_eq!(natural_cmp("File2", "file2"), Ordering::Equal);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+        assert_eq!(natural_cmp("2024-01-02", "2024-01-10"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_sort_by_name_and_natural() {
+        let mut nodes = vec![node("10-foo.md", false, 0, 0), node("2-foo.md", false, 0, 0), node("zdir", true, 0, 0)];
+
+        SortOrder::default().sort(Path::new("/vault"), &mut nodes);
+        assert_eq!(names(&nodes), vec!["zdir", "10-foo.md", "2-foo.md"]);
+
+        SortOrder { key: SortKey::Natural, descending: false }.sort(Path::new("/vault"), &mut nodes);
+        assert_eq!(names(&nodes), vec!["zdir", "2-foo.md", "10-foo.md"]);
+    }
+
+    #[test]
+    fn test_sort_by_metadata_descending() {
+        let mut nodes = vec![node("old.md", false, 30, 100), node("new.md", false, 10, 300), node("mid.md", false, 20, 200)];
+
+        SortOrder { key: SortKey::Modified, descending: true }.sort(Path::new("/vault"), &mut nodes);
+        assert_eq!(names(&nodes), vec!["new.md", "mid.md", "old.md"]);
+
+        SortOrder { key: SortKey::Size, descending: false }.sort(Path::new("/vault"), &mut nodes);
+        assert_eq!(names(&nodes), vec!["new.md", "mid.md", "old.md"]);
+    }
+
+    #[test]
+    fn test_sort_manual_from_sidecar() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let dir = temp_dir.path();
+        write_order(dir, &["b.md".into(), "sub".into(), "a.md".into()]).unwrap();
+        let mut nodes = vec![node("a.md", false, 0, 0), node("c.md", false, 0, 0), node("sub", true, 0, 0), node("b.md", false, 0, 0)];
+
+        SortOrder { key: SortKey::Manual, descending: false }.sort(dir, &mut nodes);
+
+        assert_eq!(names(&nodes), vec!["b.md", "sub", "a.md", "c.md"]);
+        assert_eq!(read_order(dir), vec!["b.md", "sub", "a.md"]);
+    }
+
+    #[test]
+    fn test_sort_order_deserialization() {
+        let order: SortOrder = serde_json::from_str("{\"key\":\"natural\"}").unwrap();
+        assert_eq!(order, SortOrder { key: SortKey::Natural, descending: false });
+    }
+}
@@ -0,0 +1,1142 @@
+use std::fs;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::git::GitStatus;
+use crate::ignore_rules::IgnoreRules;
+use crate::save::mtime_ms;
+use crate::settings::WorkspaceSettings;
+use crate::sort::SortOrder;
+use crate::watch::{DeltaKind, PathDelta};
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct FileNode {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    // None for directories below the requested depth, see `has_children`
+    pub children: Option<Vec<FileNode>>,
+    pub has_children: bool,
+    pub is_symlink: bool,
+    // Where a symlink points, as stored in the link
+    pub link_target: Option<String>,
+    // Files only
+    pub meta: Option<FileMeta>,
+    // Set when the folder is in a git repository
+    pub git_status: Option<GitStatus>,
+}
+
+// Larger files are not read just to count their words
+const MAX_COUNTED_SIZE: u64 = 1024 * 1024;
+
+// What a stat tells about a file; walking a tree never reads file contents
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct FileMeta {
+    pub size: u64,
+    pub modified_ms: u64,
+    // Not every filesystem records a creation time
+    pub created_ms: Option<u64>,
+}
+
+impl FileMeta {
+
+    pub fn read(p: &Path) -> Option<Self> {
+
+        let meta = fs::metadata(p).ok()?;
+        let created_ms = meta
+            .created()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64);
+
+        Some(FileMeta {
+            size: meta.len(),
+            modified_ms: mtime_ms(&meta),
+            created_ms,
+        })
+
+    }
+
+}
+
+// Line and word counts of a text file, asked for separately from the tree
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct TextCounts {
+    pub lines: usize,
+    pub words: usize,
+}
+
+// None for files above MAX_COUNTED_SIZE and for binary files
+pub fn count_text(p: &Path) -> Option<TextCounts> {
+    if fs::metadata(p).ok()?.len() > MAX_COUNTED_SIZE {
+        return None;
+    }
+    let bytes = fs::read(p).ok()?;
+    if bytes.contains(&0) {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    Some(TextCounts { lines: text.lines().count(), words: text.split_whitespace().count() })
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TreePatch {
+    // `index` is the sorted position of the node among the children of `parent`
+    Insert { parent: String, index: usize, node: FileNode },
+    Remove { path: String },
+    Rename { from: String, parent: String, index: usize, node: FileNode },
+    // A file changed in place; with fresh metadata it may sort to a new `index`
+    Update { parent: String, index: usize, node: FileNode },
+    // Sent when the rules for the whole tree changed, e.g. an edited .gitignore
+    Reset { tree: Vec<FileNode> },
+}
+
+// Compiled form of a workspace's settings, deciding what the tree and the watcher show
+#[derive(Clone, Debug)]
+pub struct TreeOptions {
+    pub root: PathBuf,
+    extensions: HashSet<String>,
+    exclude: GlobSet,
+    ignore: Option<Arc<IgnoreRules>>,
+    follow_symlinks: bool,
+    pub sort: SortOrder,
+}
+
+impl TreeOptions {
+
+    pub fn new(root: &Path, settings: &WorkspaceSettings) -> Result<Self, String> {
+
+        let extensions = settings
+            .filter
+            .extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .collect();
+
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &settings.filter.exclude {
+            for glob in expand_exclude(pattern) {
+                builder.add(Glob::new(&glob).map_err(|e| e.to_string())?);
+            }
+        }
+
+        Ok(TreeOptions {
+            root: root.to_path_buf(),
+            extensions,
+            exclude: builder.build().map_err(|e| e.to_string())?,
+            ignore: settings
+                .filter
+                .respect_ignore_files
+                .then(|| Arc::new(IgnoreRules::new(root))),
+            follow_symlinks: settings.follow_symlinks,
+            sort: SortOrder::default(),
+        })
+
+    }
+
+    pub fn default_for(root: &Path) -> Self {
+        Self::new(root, &WorkspaceSettings::default()).expect("Default settings are valid")
+    }
+
+    fn shows_extension(&self, p: &Path) -> bool {
+        p.extension()
+            .is_some_and(|ext| self.extensions.contains(&ext.to_string_lossy().to_lowercase()))
+    }
+
+    fn is_excluded(&self, p: &Path, is_dir: bool) -> bool {
+        p.strip_prefix(&self.root).is_ok_and(|rel| self.exclude.is_match(rel))
+            || self.ignore.as_ref().is_some_and(|rules| rules.is_ignored(p, is_dir))
+    }
+
+    // Canonical paths of `dir` and its parents inside the workspace. A symlinked
+    // directory resolving to one of them would lead back into itself.
+    fn ancestry(&self, dir: &Path) -> Vec<PathBuf> {
+        let mut chain: Vec<PathBuf> = dir
+            .ancestors()
+            .take_while(|d| d.starts_with(&self.root))
+            .filter_map(|d| d.canonicalize().ok())
+            .collect();
+        if chain.is_empty() {
+            chain.extend(dir.canonicalize().ok());
+        }
+        chain
+    }
+
+    pub fn reload_ignore_rules(&self) {
+        if let Some(rules) = &self.ignore {
+            rules.clear();
+        }
+    }
+
+    fn is_listed(&self, p: &Path) -> bool {
+        let name = match p.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return false,
+        };
+        let is_dir = p.is_dir();
+        !name.starts_with('.') && !self.is_excluded(p, is_dir) && (is_dir || self.shows_extension(p))
+    }
+
+    // Whether a watcher delta can affect the tree. Hidden entries never do, even
+    // deep inside the root, which also covers our own temp files during saves.
+    pub fn is_relevant(&self, path: &Path, kind: DeltaKind) -> bool {
+
+        let rel = match path.strip_prefix(&self.root) {
+            Ok(rel) => rel,
+            Err(_) => return false,
+        };
+
+        let hidden = rel.components().any(|c| match c {
+            Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+            _ => false,
+        });
+        // A removed path no longer says whether it was a directory, so guess from the name;
+        // `TreeIndex::is_relevant` asks the tree first
+        let is_dir = match kind {
+            DeltaKind::Removed => path.extension().is_none(),
+            _ => path.is_dir(),
+        };
+        if hidden || self.is_excluded(path, is_dir) {
+            return false;
+        }
+
+        let shown = self.shows_extension(path);
+
+        match kind {
+            // Gone from disk, so a path without extension may have been a directory
+            DeltaKind::Removed => shown || path.extension().is_none(),
+            DeltaKind::Created => shown || path.is_dir(),
+            DeltaKind::Modified => shown && !path.is_dir(),
+        }
+
+    }
+
+}
+
+// Exclude globs behave like gitignore lines: a pattern without a slash matches at
+// any depth, and `dir/**` also hides `dir` itself
+fn expand_exclude(pattern: &str) -> Vec<String> {
+
+    let pattern = pattern.trim_start_matches('/');
+    let base = pattern.strip_suffix("/**").unwrap_or(pattern);
+
+    let mut globs = vec![pattern.to_string()];
+    if base != pattern {
+        globs.push(base.to_string());
+    }
+    if !base.contains('/') && !base.starts_with("**") {
+        globs = globs.into_iter().map(|g| format!("**/{}", g)).collect();
+    }
+
+    globs
+
+}
+
+// Stops at the first visible entry, so it stays cheap on huge directories
+fn dir_has_entries(p: &Path, options: &TreeOptions) -> bool {
+    fs::read_dir(p).is_ok_and(|entries| entries.flatten().any(|entry| options.is_listed(&entry.path())))
+}
+
+// Builds the node for a single path. Directories are walked `depth` more levels
+// deep, or all the way down when `depth` is None. `ancestors` holds the canonical
+// paths of the directories above, so symlink loops are listed but not entered.
+fn node_for(p: &Path, depth: Option<usize>, options: &TreeOptions, ancestors: &mut Vec<PathBuf>) -> Option<FileNode> {
+
+    if !options.is_listed(p) {
+        return None;
+    }
+
+    let name = p.file_name()?.to_string_lossy().to_string();
+    let is_symlink = fs::symlink_metadata(p).is_ok_and(|m| m.file_type().is_symlink());
+    let link_target = if is_symlink {
+        fs::read_link(p).ok().map(|target| target.to_string_lossy().to_string())
+    } else {
+        None
+    };
+
+    if p.is_dir() {
+        let canonical = p.canonicalize().ok().filter(|c| !ancestors.contains(c));
+        let enter = !is_symlink || options.follow_symlinks;
+        let children = match (canonical, depth) {
+            (Some(canonical), depth) if enter && depth != Some(0) => {
+                ancestors.push(canonical);
+                let children = read_dir_to_depth(p, depth.map(|d| d - 1), options, ancestors);
+                ancestors.pop();
+                Some(children)
+            }
+            _ => None,
+        };
+        let has_children = match &children {
+            Some(children) => !children.is_empty(),
+            None => enter && depth == Some(0) && dir_has_entries(p, options),
+        };
+        Some(FileNode {
+            name,
+            path: p.to_string_lossy().to_string(),
+            is_dir: true,
+            children,
+            has_children,
+            is_symlink,
+            link_target,
+            meta: None,
+            git_status: None,
+        })
+    }
+    else {
+        Some(FileNode {
+            name,
+            path: p.to_string_lossy().to_string(),
+            is_dir: false,
+            children: None,
+            has_children: false,
+            is_symlink,
+            link_target,
+            meta: FileMeta::read(p),
+            git_status: None,
+        })
+    }
+
+}
+
+fn read_dir_to_depth(path: &Path, depth: Option<usize>, options: &TreeOptions, ancestors: &mut Vec<PathBuf>) -> Vec<FileNode> {
+    let mut nodes = Vec::new();
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            if let Some(node) = node_for(&entry.path(), depth, options, ancestors) {
+                nodes.push(node);
+            }
+        }
+    }
+
+    options.sort.sort(path, &mut nodes);
+
+    nodes
+
+}
+
+fn sort_all(nodes: &mut [FileNode], dir: &Path, order: &SortOrder) {
+    order.sort(dir, nodes);
+    for node in nodes.iter_mut() {
+        if let Some(children) = node.children.as_mut() {
+            sort_all(children, Path::new(&node.path), order);
+        }
+    }
+}
+
+// Walks the whole tree with the default markdown-only settings
+#[cfg(test)]
+pub fn read_dir_recursive(path: &Path) -> Vec<FileNode> {
+    read_dir_with(path, &TreeOptions::default_for(path))
+}
+
+pub fn read_dir_with(path: &Path, options: &TreeOptions) -> Vec<FileNode> {
+    read_dir_to_depth(path, None, options, &mut options.ancestry(path))
+}
+
+// The files `read_dir_with` shows below `path`, without building nodes or reading
+// metadata, for callers that only need the paths
+pub fn list_files(path: &Path, options: &TreeOptions) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    collect_files(path, options, &mut options.ancestry(path), &mut files);
+    files
+}
+
+fn collect_files(dir: &Path, options: &TreeOptions, ancestors: &mut Vec<PathBuf>, files: &mut Vec<PathBuf>) {
+
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.flatten().map(|entry| entry.path()).filter(|p| options.is_listed(p)).collect(),
+        Err(_) => return,
+    };
+    entries.sort();
+
+    for p in entries {
+        if !p.is_dir() {
+            files.push(p);
+            continue;
+        }
+        let is_symlink = fs::symlink_metadata(&p).is_ok_and(|m| m.file_type().is_symlink());
+        if is_symlink && !options.follow_symlinks {
+            continue;
+        }
+        if let Some(canonical) = p.canonicalize().ok().filter(|c| !ancestors.contains(c)) {
+            ancestors.push(canonical);
+            collect_files(&p, options, ancestors, files);
+            ancestors.pop();
+        }
+    }
+
+}
+
+// Lists `depth` levels of `path`; directories on the last level come back without children
+pub fn list_directory(path: &Path, depth: usize, options: &TreeOptions) -> Vec<FileNode> {
+    read_dir_to_depth(path, Some(depth.max(1) - 1), options, &mut options.ancestry(path))
+}
+
+// In-memory copy of the opened folder's tree, kept current from watcher deltas
+// so changes can be sent as patches instead of full rescans
+pub struct TreeIndex {
+    pub root: PathBuf,
+    pub nodes: Vec<FileNode>,
+    pub options: TreeOptions,
+    pub depth: Option<usize>,
+}
+
+impl TreeIndex {
+
+    // With a depth limit only the first levels are indexed; the rest is grafted
+    // in as the sidebar expands directories
+    pub fn build(options: TreeOptions, depth: Option<usize>) -> Self {
+        let root = options.root.clone();
+        TreeIndex {
+            nodes: match depth {
+                Some(depth) => list_directory(&root, depth, &options),
+                None => read_dir_with(&root, &options),
+            },
+            root,
+            options,
+            depth,
+        }
+    }
+
+    // Children of a directory in the index, the root included
+    fn children_mut(&mut self, dir: &Path) -> Option<&mut Vec<FileNode>> {
+
+        let rel = dir.strip_prefix(&self.root).ok()?;
+        let mut children = &mut self.nodes;
+
+        for component in rel.iter() {
+            let node = children.iter_mut().find(|n| n.is_dir && n.name.as_str() == component)?;
+            children = node.children.as_mut()?;
+        }
+
+        Some(children)
+
+    }
+
+    // Re-sorts every indexed directory, after the order or a manual order file changed
+    pub fn set_sort(&mut self, order: SortOrder) -> Vec<TreePatch> {
+        self.options.sort = order;
+        sort_all(&mut self.nodes, &self.root, &order);
+        vec![TreePatch::Reset { tree: self.nodes.clone() }]
+    }
+
+    // Walks the folder again from scratch, after the rules for what is shown changed
+    pub fn rebuild(&mut self) -> Vec<TreePatch> {
+        self.options.reload_ignore_rules();
+        self.nodes = match self.depth {
+            Some(depth) => list_directory(&self.root, depth, &self.options),
+            None => read_dir_with(&self.root, &self.options),
+        };
+        vec![TreePatch::Reset { tree: self.nodes.clone() }]
+    }
+
+    fn node_mut(&mut self, path: &Path) -> Option<&mut FileNode> {
+        let name = path.file_name()?;
+        self.children_mut(path.parent()?)?.iter_mut().find(|n| n.name.as_str() == name)
+    }
+
+    fn refresh_has_children(&mut self, dir: &Path) {
+        if let Some(node) = self.node_mut(dir) {
+            node.has_children = node.children.as_ref().is_some_and(|c| !c.is_empty());
+        }
+    }
+
+    // Stores a lazily listed directory so later watcher events inside it are patched too
+    pub fn graft(&mut self, dir: &Path, children: Vec<FileNode>) {
+        if dir == self.root {
+            self.nodes = children;
+        }
+        else if let Some(node) = self.node_mut(dir) {
+            node.has_children = !children.is_empty();
+            node.children = Some(children);
+        }
+    }
+
+    pub fn contains(&mut self, path: &Path) -> bool {
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => self
+                .children_mut(parent)
+                .is_some_and(|children| children.iter().any(|n| n.name.as_str() == name)),
+            _ => false,
+        }
+    }
+
+    // Like `TreeOptions::is_relevant`, but a removed path the tree shows is relevant
+    // whatever its name, so a removed `v1.2` directory is not taken for a file
+    pub fn is_relevant(&mut self, path: &Path, kind: DeltaKind) -> bool {
+        (kind == DeltaKind::Removed && self.contains(path)) || self.options.is_relevant(path, kind)
+    }
+
+    fn take(&mut self, path: &Path) -> Option<FileNode> {
+        let name = path.file_name()?;
+        let children = self.children_mut(path.parent()?)?;
+        let pos = children.iter().position(|n| n.name.as_str() == name)?;
+        let node = children.remove(pos);
+        self.refresh_has_children(path.parent()?);
+        Some(node)
+    }
+
+    // Inserts at the sorted position, returning the parent path and index
+    fn insert(&mut self, parent: &Path, node: FileNode) -> Option<(String, usize)> {
+        let order = self.options.sort;
+        let manual = order.manual_order(parent);
+        let children = self.children_mut(parent)?;
+        let index = children
+            .binary_search_by(|probe| order.compare(probe, &node, &manual))
+            .unwrap_or_else(|i| i);
+        children.insert(index, node);
+        self.refresh_has_children(parent);
+        Some((parent.to_string_lossy().to_string(), index))
+    }
+
+    // For a path whose parent directories are not indexed yet, the topmost missing
+    // directory is what has to be inserted
+    fn insertion_point(&mut self, path: &Path) -> Option<PathBuf> {
+        let mut candidate = path.to_path_buf();
+        loop {
+            let parent = candidate.parent()?.to_path_buf();
+            if !parent.starts_with(&self.root) {
+                return None;
+            }
+            if self.children_mut(&parent).is_some() {
+                return Some(candidate);
+            }
+            candidate = parent;
+        }
+    }
+
+    // Applies a batch of watcher deltas and returns the patches that bring a copy
+    // of the previous tree up to date. A creation is reported as a rename only when
+    // the watcher saw it renamed from a path removed in the same batch.
+    pub fn apply(&mut self, deltas: &[PathDelta]) -> Vec<TreePatch> {
+
+        let mut removed: Vec<FileNode> = Vec::new();
+        for delta in deltas.iter().filter(|d| d.kind == DeltaKind::Removed) {
+            if let Some(node) = self.take(Path::new(&delta.path)) {
+                removed.push(node);
+            }
+        }
+
+        let mut patches = Vec::new();
+        for delta in deltas.iter().filter(|d| d.kind == DeltaKind::Created) {
+
+            let path = PathBuf::from(&delta.path);
+            if self.contains(&path) {
+                continue;
+            }
+
+            let target = match self.insertion_point(&path) {
+                Some(target) => target,
+                None => continue,
+            };
+            if self.contains(&target) {
+                continue;
+            }
+            // Walked only as deep as `build` would have, counting from the root
+            let level = target.strip_prefix(&self.root).map_or(0, |rel| rel.components().count());
+            let depth = self.depth.map(|depth| depth.saturating_sub(level));
+            let mut ancestors = self.options.ancestry(target.parent().unwrap_or(&self.root));
+            let node = match node_for(&target, depth, &self.options, &mut ancestors) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            let parent = target.parent().unwrap_or(&self.root).to_path_buf();
+            let renamed_from = delta
+                .renamed_from
+                .as_ref()
+                .filter(|_| target == path)
+                .and_then(|from| removed.iter().position(|old| Path::new(&old.path) == Path::new(from) && old.is_dir == node.is_dir));
+
+            if let Some((parent, index)) = self.insert(&parent, node.clone()) {
+                patches.push(match renamed_from {
+                    Some(pos) => TreePatch::Rename { from: removed.remove(pos).path, parent, index, node },
+                    None => TreePatch::Insert { parent, index, node },
+                });
+            }
+
+        }
+
+        for delta in deltas.iter().filter(|d| d.kind == DeltaKind::Modified) {
+
+            let path = Path::new(&delta.path);
+            let meta = FileMeta::read(path);
+            let stale = self.node_mut(path).is_some_and(|n| !n.is_dir && meta.is_some() && n.meta != meta);
+            if !stale {
+                continue;
+            }
+
+            // Taken out and inserted again, since the new metadata can move it
+            if let (Some(mut node), Some(parent)) = (self.take(path), path.parent()) {
+                node.meta = meta;
+                if let Some((parent, index)) = self.insert(parent, node.clone()) {
+                    patches.push(TreePatch::Update { parent, index, node });
+                }
+            }
+
+        }
+
+        let mut result: Vec<TreePatch> = removed
+            .into_iter()
+            .map(|node| TreePatch::Remove { path: node.path })
+            .collect();
+        result.extend(patches);
+        result
+
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sort::SortKey;
+    use tempfile::TempDir;
+
+    fn delta(path: &Path, kind: DeltaKind) -> PathDelta {
+        PathDelta { path: path.to_string_lossy().to_string(), kind, renamed_from: None }
+    }
+
+    fn renamed(from: &Path, to: &Path) -> PathDelta {
+        PathDelta { renamed_from: Some(from.to_string_lossy().to_string()), ..delta(to, DeltaKind::Created) }
+    }
+
+    #[test]
+    fn test_apply_insert_file() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("b.md"), "content").unwrap();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), None);
+
+        fs::write(root.join("a.md"), "content").unwrap();
+        let patches = index.apply(&[delta(&root.join("a.md"), DeltaKind::Created)]);
+
+        assert_eq!(patches.len(), 1);
+        match &patches[0] {
+            TreePatch::Insert { parent, index: pos, node } => {
+                assert_eq!(parent, &root.to_string_lossy().to_string());
+                assert_eq!(*pos, 0);
+                assert_eq!(node.name, "a.md");
+            }
+            other => panic!("Expected insert, got {:?}", other),
+        }
+        assert_eq!(index.nodes, read_dir_recursive(root));
+    }
+
+    #[test]
+    fn test_apply_ignores_existing_file() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("note.md"), "content").unwrap();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), None);
+
+        let patches = index.apply(&[
+            delta(&root.join("note.md"), DeltaKind::Created),
+            delta(&root.join("note.md"), DeltaKind::Modified),
+        ]);
+
+        assert!(patches.is_empty());
+    }
+
+    #[test]
+    fn test_apply_remove_directory() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let subdir = root.join("sub");
+        fs::create_dir(&subdir).unwrap();
+        fs::write(subdir.join("note.md"), "content").unwrap();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), None);
+
+        fs::remove_dir_all(&subdir).unwrap();
+        let patches = index.apply(&[
+            delta(&subdir.join("note.md"), DeltaKind::Removed),
+            delta(&subdir, DeltaKind::Removed),
+        ]);
+
+        assert_eq!(patches, vec![TreePatch::Remove { path: subdir.join("note.md").to_string_lossy().to_string() }, TreePatch::Remove { path: subdir.to_string_lossy().to_string() }]);
+        assert!(index.nodes.is_empty());
+    }
+
+    #[test]
+    fn test_apply_rename() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("old.md"), "content").unwrap();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), None);
+
+        fs::rename(root.join("old.md"), root.join("new.md")).unwrap();
+        let patches = index.apply(&[
+            delta(&root.join("old.md"), DeltaKind::Removed),
+            renamed(&root.join("old.md"), &root.join("new.md")),
+        ]);
+
+        assert_eq!(patches.len(), 1);
+        match &patches[0] {
+            TreePatch::Rename { from, node, .. } => {
+                assert_eq!(from, &root.join("old.md").to_string_lossy().to_string());
+                assert_eq!(node.name, "new.md");
+            }
+            other => panic!("Expected rename, got {:?}", other),
+        }
+        assert_eq!(index.nodes, read_dir_recursive(root));
+    }
+
+    #[test]
+    fn test_apply_remove_and_unrelated_create() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "content").unwrap();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), None);
+
+        fs::remove_file(root.join("a.md")).unwrap();
+        fs::write(root.join("b.md"), "other").unwrap();
+        let patches = index.apply(&[
+            delta(&root.join("a.md"), DeltaKind::Removed),
+            delta(&root.join("b.md"), DeltaKind::Created),
+        ]);
+
+        assert_eq!(patches.len(), 2);
+        assert_eq!(patches[0], TreePatch::Remove { path: root.join("a.md").to_string_lossy().to_string() });
+        match &patches[1] {
+            TreePatch::Insert { node, .. } => assert_eq!(node.name, "b.md"),
+            other => panic!("Expected insert, got {:?}", other),
+        }
+        assert_eq!(index.nodes, read_dir_recursive(root));
+    }
+
+    #[test]
+    fn test_apply_move_into_new_directory() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), None);
+
+        let nested = root.join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(nested.join("deep.md"), "content").unwrap();
+        let patches = index.apply(&[delta(&nested.join("deep.md"), DeltaKind::Created)]);
+
+        assert_eq!(patches.len(), 1);
+        match &patches[0] {
+            TreePatch::Insert { node, .. } => assert_eq!(node.name, "a"),
+            other => panic!("Expected insert, got {:?}", other),
+        }
+        assert_eq!(index.nodes, read_dir_recursive(root));
+    }
+
+    #[test]
+    fn test_list_directory_single_level() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("full/inner")).unwrap();
+        fs::write(root.join("full/inner/deep.md"), "content").unwrap();
+        fs::create_dir(root.join("empty")).unwrap();
+        fs::write(root.join("empty/ignored.txt"), "content").unwrap();
+        fs::write(root.join("note.md"), "content").unwrap();
+
+        let result = list_directory(root, 1, &TreeOptions::default_for(root));
+
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0].name, "empty");
+        assert!(result[0].children.is_none());
+        assert!(!result[0].has_children);
+        assert_eq!(result[1].name, "full");
+        assert!(result[1].children.is_none());
+        assert!(result[1].has_children);
+        assert!(!result[2].has_children);
+    }
+
+    #[test]
+    fn test_list_directory_two_levels() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::write(root.join("a/b/c/deep.md"), "content").unwrap();
+
+        let result = list_directory(root, 2, &TreeOptions::default_for(root));
+
+        let a_children = result[0].children.as_ref().expect("First level is listed");
+        assert_eq!(a_children[0].name, "b");
+        assert!(a_children[0].children.is_none());
+        assert!(a_children[0].has_children);
+    }
+
+    #[test]
+    fn test_graft_and_patch_lazy_directory() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let subdir = root.join("sub");
+        fs::create_dir(&subdir).unwrap();
+        fs::write(subdir.join("a.md"), "content").unwrap();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), Some(1));
+
+        // Not listed yet, so nothing to patch
+        fs::write(subdir.join("b.md"), "content").unwrap();
+        assert!(index.apply(&[delta(&subdir.join("b.md"), DeltaKind::Created)]).is_empty());
+
+        index.graft(&subdir, list_directory(&subdir, 1, &index.options));
+        fs::write(subdir.join("c.md"), "content").unwrap();
+        let patches = index.apply(&[delta(&subdir.join("c.md"), DeltaKind::Created)]);
+
+        assert_eq!(patches.len(), 1);
+        assert_eq!(index.nodes, read_dir_recursive(root));
+    }
+
+    #[test]
+    fn test_apply_respects_depth_limit() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), Some(1));
+
+        fs::create_dir_all(root.join("new/inner")).unwrap();
+        fs::write(root.join("new/inner/a.md"), "content").unwrap();
+        let patches = index.apply(&[delta(&root.join("new"), DeltaKind::Created)]);
+
+        assert_eq!(patches.len(), 1);
+        assert_eq!(index.nodes[0].name, "new");
+        assert!(index.nodes[0].children.is_none());
+        assert!(index.nodes[0].has_children);
+    }
+
+    #[test]
+    fn test_apply_updates_has_children() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let subdir = root.join("sub");
+        fs::create_dir(&subdir).unwrap();
+        fs::write(subdir.join("only.md"), "content").unwrap();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), None);
+
+        fs::remove_file(subdir.join("only.md")).unwrap();
+        index.apply(&[delta(&subdir.join("only.md"), DeltaKind::Removed)]);
+
+        assert!(!index.nodes[0].has_children);
+    }
+
+    #[test]
+    fn test_is_relevant_directories() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let subdir = root.join("journal");
+        fs::create_dir(&subdir).unwrap();
+        let options = TreeOptions::default_for(root);
+
+        assert!(options.is_relevant(&subdir, DeltaKind::Created));
+        assert!(!options.is_relevant(&subdir, DeltaKind::Modified));
+        assert!(options.is_relevant(&root.join("gone"), DeltaKind::Removed));
+        assert!(!options.is_relevant(&root.join(".git/HEAD"), DeltaKind::Modified));
+        assert!(!options.is_relevant(Path::new("/elsewhere/note.md"), DeltaKind::Created));
+    }
+
+    #[test]
+    fn test_removed_directory_with_dot_is_relevant() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let subdir = root.join("v1.2");
+        fs::create_dir(&subdir).unwrap();
+        fs::write(subdir.join("notes.md"), "content").unwrap();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), None);
+
+        fs::remove_dir_all(&subdir).unwrap();
+
+        assert!(!index.options.is_relevant(&subdir, DeltaKind::Removed));
+        assert!(index.is_relevant(&subdir, DeltaKind::Removed));
+        assert!(!index.is_relevant(&root.join("v1.3"), DeltaKind::Removed));
+    }
+
+    fn options_with(root: &Path, extensions: &[&str], exclude: &[&str]) -> TreeOptions {
+        let mut settings = WorkspaceSettings::default();
+        settings.filter.extensions = extensions.iter().map(|s| s.to_string()).collect();
+        settings.filter.exclude = exclude.iter().map(|s| s.to_string()).collect();
+        TreeOptions::new(root, &settings).expect("Valid settings")
+    }
+
+    #[test]
+    fn test_filter_extensions() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        for name in ["a.md", "b.markdown", "c.MDX", "d.png", "e.rs"] {
+            fs::write(root.join(name), "content").unwrap();
+        }
+        let options = options_with(root, &["md", ".markdown", "mdx", "png"], &[]);
+
+        let names: Vec<_> = read_dir_with(root, &options).into_iter().map(|n| n.name).collect();
+
+        assert_eq!(names, vec!["a.md", "b.markdown", "c.MDX", "d.png"]);
+        assert!(options.is_relevant(&root.join("b.markdown"), DeltaKind::Modified));
+        assert!(!options.is_relevant(&root.join("e.rs"), DeltaKind::Modified));
+    }
+
+    #[test]
+    fn test_filter_exclude_globs() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("node_modules/pkg")).unwrap();
+        fs::write(root.join("node_modules/pkg/README.md"), "content").unwrap();
+        fs::create_dir_all(root.join("docs/node_modules")).unwrap();
+        fs::write(root.join("docs/node_modules/x.md"), "content").unwrap();
+        fs::write(root.join("docs/draft.md"), "content").unwrap();
+        fs::write(root.join("docs/final.md"), "content").unwrap();
+        let options = options_with(root, &["md"], &["node_modules/**", "docs/draft.md"]);
+
+        let tree = read_dir_with(root, &options);
+
+        assert_eq!(tree.len(), 1);
+        let docs: Vec<_> = tree[0].children.as_ref().unwrap().iter().map(|n| n.name.clone()).collect();
+        assert_eq!(docs, vec!["final.md"]);
+        assert!(!options.is_relevant(&root.join("node_modules/pkg/README.md"), DeltaKind::Modified));
+    }
+
+    #[test]
+    fn test_tree_respects_ignore_files() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("target/doc")).unwrap();
+        fs::write(root.join("target/doc/api.md"), "content").unwrap();
+        fs::create_dir(root.join("notes")).unwrap();
+        fs::write(root.join("notes/a.md"), "content").unwrap();
+        fs::write(root.join("notes/b.md"), "content").unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        fs::write(root.join("notes/.aquaignore"), "b.md\n").unwrap();
+
+        let tree = read_dir_recursive(root);
+
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].name, "notes");
+        assert_eq!(tree[0].children.as_ref().unwrap().len(), 1);
+
+        let options = TreeOptions::default_for(root);
+        assert!(!options.is_relevant(&root.join("target/doc/api.md"), DeltaKind::Modified));
+        assert!(!options.is_relevant(&root.join("target"), DeltaKind::Removed));
+    }
+
+    #[test]
+    fn test_ignore_files_can_be_disabled() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("ignored.md"), "content").unwrap();
+        fs::write(root.join(".gitignore"), "ignored.md\n").unwrap();
+        let mut settings = WorkspaceSettings::default();
+        settings.filter.respect_ignore_files = false;
+
+        let tree = read_dir_with(root, &TreeOptions::new(root, &settings).unwrap());
+
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn test_rebuild_after_ignore_change() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "content").unwrap();
+        fs::write(root.join("b.md"), "content").unwrap();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), None);
+        assert_eq!(index.nodes.len(), 2);
+
+        fs::write(root.join(".gitignore"), "b.md\n").unwrap();
+        let patches = index.rebuild();
+
+        assert_eq!(index.nodes.len(), 1);
+        assert!(matches!(&patches[0], TreePatch::Reset { tree } if tree.len() == 1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_are_marked() {
+        use std::os::unix::fs::symlink;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("shared")).unwrap();
+        fs::write(root.join("shared/snippet.md"), "content").unwrap();
+        fs::create_dir(root.join("a")).unwrap();
+        symlink(root.join("shared"), root.join("a/shared")).unwrap();
+        symlink("../shared/snippet.md", root.join("a/link.md")).unwrap();
+
+        let tree = read_dir_recursive(root);
+        let a = &tree[0].children.as_ref().unwrap();
+
+        let linked_dir = a.iter().find(|n| n.name == "shared").unwrap();
+        assert!(linked_dir.is_symlink);
+        assert_eq!(linked_dir.link_target, Some(root.join("shared").to_string_lossy().to_string()));
+        assert_eq!(linked_dir.children.as_ref().unwrap().len(), 1);
+
+        let linked_file = a.iter().find(|n| n.name == "link.md").unwrap();
+        assert!(linked_file.is_symlink);
+        assert_eq!(linked_file.link_target.as_deref(), Some("../shared/snippet.md"));
+
+        // The shared folder itself is listed too, linking it elsewhere does not hide it
+        assert!(!tree[1].is_symlink);
+        assert_eq!(tree[1].children.as_ref().unwrap().len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_cycle_is_not_entered() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("a")).unwrap();
+        fs::write(root.join("a/note.md"), "content").unwrap();
+        std::os::unix::fs::symlink(root, root.join("a/loop")).unwrap();
+
+        let tree = read_dir_recursive(root);
+        let a_children = tree[0].children.as_ref().unwrap();
+        let loop_node = a_children.iter().find(|n| n.name == "loop").unwrap();
+
+        assert!(loop_node.is_symlink);
+        assert!(loop_node.children.is_none());
+        assert!(!loop_node.has_children);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks_can_be_disabled() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("real")).unwrap();
+        fs::write(root.join("real/note.md"), "content").unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("linked")).unwrap();
+        let settings = WorkspaceSettings {
+            follow_symlinks: false,
+            ..WorkspaceSettings::default()
+        };
+
+        let tree = read_dir_with(root, &TreeOptions::new(root, &settings).unwrap());
+
+        assert_eq!(tree[0].name, "linked");
+        assert!(tree[0].is_dir);
+        assert!(tree[0].children.is_none());
+        assert!(tree[1].children.is_some());
+    }
+
+    #[test]
+    fn test_filter_rejects_invalid_glob() {
+        let mut settings = WorkspaceSettings::default();
+        settings.filter.exclude = vec!["[unclosed".into()];
+
+        assert!(TreeOptions::new(Path::new("/root"), &settings).is_err());
+    }
+
+    #[test]
+    fn test_file_meta_counts() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("note.md"), "# Title\n\nSome words here.\n").unwrap();
+
+        let tree = read_dir_recursive(root);
+        let meta = tree[1].meta.as_ref().unwrap();
+
+        assert!(tree[0].meta.is_none());
+        assert_eq!(meta.size, 26);
+        assert!(meta.modified_ms > 0);
+        assert_eq!(count_text(&root.join("note.md")), Some(TextCounts { lines: 3, words: 5 }));
+    }
+
+    #[test]
+    fn test_count_text_skips_large_and_binary_files() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let big = temp_dir.path().join("big.md");
+        let image = temp_dir.path().join("image.png");
+        fs::write(&big, vec![b'a'; MAX_COUNTED_SIZE as usize + 1]).unwrap();
+        fs::write(&image, [0x89, b'P', b'N', b'G', 0, 0]).unwrap();
+
+        assert_eq!(FileMeta::read(&big).unwrap().size, MAX_COUNTED_SIZE + 1);
+        assert_eq!(count_text(&big), None);
+        assert_eq!(count_text(&image), None);
+    }
+
+    #[test]
+    fn test_list_files_matches_tree() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        fs::create_dir(root.join(".hidden")).unwrap();
+        for file in ["a.md", "b.txt", "sub/c.md", "sub/deeper/d.md", ".hidden/e.md"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        fn files_of(nodes: &[FileNode]) -> Vec<PathBuf> {
+            nodes
+                .iter()
+                .flat_map(|node| match &node.children {
+                    Some(children) => files_of(children),
+                    None if !node.is_dir => vec![PathBuf::from(&node.path)],
+                    None => Vec::new(),
+                })
+                .collect()
+        }
+
+        let options = TreeOptions::default_for(root);
+        let mut from_tree = files_of(&read_dir_with(root, &options));
+        from_tree.sort();
+        assert_eq!(list_files(root, &options), from_tree);
+        assert_eq!(from_tree.len(), 3);
+    }
+
+    #[test]
+    fn test_apply_updates_modified_file() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("note.md"), "one").unwrap();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), None);
+
+        fs::write(root.join("note.md"), "one two three").unwrap();
+        let patches = index.apply(&[delta(&root.join("note.md"), DeltaKind::Modified)]);
+
+        assert_eq!(patches.len(), 1);
+        match &patches[0] {
+            TreePatch::Update { node, index, .. } => {
+                assert_eq!(*index, 0);
+                assert_eq!(node.meta.as_ref().unwrap().size, 13);
+            }
+            other => panic!("unexpected patch {:?}", other),
+        }
+        assert_eq!(index.nodes[0].meta.as_ref().unwrap().size, 13);
+    }
+
+    #[test]
+    fn test_apply_moves_modified_file_when_sorted_by_size() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::write(root.join("a.md"), "a").unwrap();
+        fs::write(root.join("b.md"), "bb").unwrap();
+        let mut options = TreeOptions::default_for(root);
+        options.sort = SortOrder { key: SortKey::Size, descending: false };
+        let mut index = TreeIndex::build(options, None);
+        assert_eq!(index.nodes[0].name, "a.md");
+
+        fs::write(root.join("a.md"), "aaa").unwrap();
+        let patches = index.apply(&[delta(&root.join("a.md"), DeltaKind::Modified)]);
+
+        assert!(matches!(&patches[0], TreePatch::Update { index: 1, .. }));
+        assert_eq!(index.nodes[1].name, "a.md");
+    }
+
+    #[test]
+    fn test_set_sort_resorts_nested_directories() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir(root.join("journal")).unwrap();
+        fs::write(root.join("journal/2-day.md"), "").unwrap();
+        fs::write(root.join("journal/10-day.md"), "").unwrap();
+        let mut index = TreeIndex::build(TreeOptions::default_for(root), None);
+        assert_eq!(index.nodes[0].children.as_ref().unwrap()[0].name, "10-day.md");
+
+        index.set_sort(SortOrder { key: SortKey::Natural, descending: false });
+
+        assert_eq!(index.nodes[0].children.as_ref().unwrap()[0].name, "2-day.md");
+        fs::write(root.join("journal/3-day.md"), "").unwrap();
+        let patches = index.apply(&[delta(&root.join("journal/3-day.md"), DeltaKind::Created)]);
+        assert!(matches!(&patches[0], TreePatch::Insert { index: 1, .. }));
+    }
+
+    #[test]
+    fn test_tree_patch_serialization() {
+        let json = serde_json::to_string(&TreePatch::Remove { path: "/a.md".into() }).unwrap();
+        assert_eq!(json, "{\"op\":\"remove\",\"path\":\"/a.md\"}");
+    }
+}
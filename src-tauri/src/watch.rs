@@ -0,0 +1,545 @@
+use std::thread;
+use std::sync::mpsc;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::save::fingerprint_file;
+
+// Editors that save by rename (vim, VS Code) briefly leave the file missing,
+// so wait for the directory to go quiet before looking at it
+const SETTLE: Duration = Duration::from_millis(150);
+// A directory that never goes quiet is looked at after this long anyway
+const MAX_SETTLE: Duration = Duration::from_secs(1);
+
+pub const DEFAULT_FOLDER_DEBOUNCE: Duration = Duration::from_millis(300);
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Modified,
+    Deleted,
+    Renamed,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct ExternalChange {
+    pub path: String,
+    pub kind: ChangeKind,
+    // Hash of the new content, None when the file is gone
+    pub hash: Option<String>,
+    pub new_path: Option<String>,
+}
+
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeltaKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct PathDelta {
+    pub path: String,
+    pub kind: DeltaKind,
+    // For a creation the watcher reported as the far end of a rename, where it came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renamed_from: Option<String>,
+}
+
+fn is_rename_pair(event: &Event) -> bool {
+    matches!(event.kind, EventKind::Modify(ModifyKind::Name(RenameMode::Both))) && event.paths.len() == 2
+}
+
+// Turns a settled batch of directory events into at most one change for `path`.
+// `known_hash` is the content the editor has, `last_hash` the last change we reported.
+pub fn classify(path: &Path, events: &[Event], known_hash: Option<&str>, last_hash: Option<&str>) -> Option<ExternalChange> {
+
+    if !events.iter().any(|e| e.paths.iter().any(|p| p == path)) {
+        return None;
+    }
+
+    let path_str = path.to_string_lossy().to_string();
+
+    // Still there (possibly replaced by a rename-save): only the content matters
+    if let Ok(fingerprint) = fingerprint_file(path) {
+        if Some(fingerprint.hash.as_str()) == known_hash || Some(fingerprint.hash.as_str()) == last_hash {
+            return None;
+        }
+        return Some(ExternalChange {
+            path: path_str,
+            kind: ChangeKind::Modified,
+            hash: Some(fingerprint.hash),
+            new_path: None,
+        });
+    }
+
+    let renamed_to = events
+        .iter()
+        .rev()
+        .filter(|e| is_rename_pair(e) && e.paths[0] == path)
+        .map(|e| e.paths[1].clone())
+        .find(|to| to.exists());
+
+    Some(match renamed_to {
+        Some(to) => ExternalChange {
+            path: path_str,
+            kind: ChangeKind::Renamed,
+            hash: fingerprint_file(&to).ok().map(|fp| fp.hash),
+            new_path: Some(to.to_string_lossy().to_string()),
+        },
+        None => ExternalChange {
+            path: path_str,
+            kind: ChangeKind::Deleted,
+            hash: None,
+            new_path: None,
+        },
+    })
+
+}
+
+// Flattens a raw notify event into per-path deltas; renames become a removal plus a creation
+fn deltas_of(event: &Event) -> Vec<(PathBuf, DeltaKind)> {
+    match &event.kind {
+        EventKind::Create(_) => event.paths.iter().map(|p| (p.clone(), DeltaKind::Created)).collect(),
+        EventKind::Remove(_) => event.paths.iter().map(|p| (p.clone(), DeltaKind::Removed)).collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => vec![
+            (event.paths[0].clone(), DeltaKind::Removed),
+            (event.paths[1].clone(), DeltaKind::Created),
+        ],
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            event.paths.iter().map(|p| (p.clone(), DeltaKind::Removed)).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            event.paths.iter().map(|p| (p.clone(), DeltaKind::Created)).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(_)) => event
+            .paths
+            .iter()
+            .map(|p| (p.clone(), if p.exists() { DeltaKind::Created } else { DeltaKind::Removed }))
+            .collect(),
+        EventKind::Modify(_) => event.paths.iter().map(|p| (p.clone(), DeltaKind::Modified)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Net effect of two consecutive deltas on the same path; None means they cancel out
+fn merge(prev: Option<DeltaKind>, next: DeltaKind) -> Option<DeltaKind> {
+    use DeltaKind::*;
+    match (prev, next) {
+        (None, next) => Some(next),
+        (Some(Created), Modified) => Some(Created),
+        (Some(Created), Removed) => None,
+        (Some(Removed), Created) | (Some(Removed), Modified) => Some(Modified),
+        (Some(_), next) => Some(next),
+    }
+}
+
+// Collapses a window of events into one delta per path, in first-seen order,
+// dropping paths the sidebar does not show. A creation keeps the source of the
+// rename that made it while that source ends up removed.
+pub fn coalesce(events: &[Event], is_relevant: &dyn Fn(&Path, DeltaKind) -> bool) -> Vec<PathDelta> {
+
+    let mut order: Vec<PathBuf> = Vec::new();
+    let mut net: HashMap<PathBuf, Option<DeltaKind>> = HashMap::new();
+    let mut sources: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+    for event in events {
+        if is_rename_pair(event) {
+            sources.insert(event.paths[1].clone(), event.paths[0].clone());
+        }
+    }
+
+    for (path, kind) in events.iter().flat_map(deltas_of) {
+        if !is_relevant(&path, kind) {
+            continue;
+        }
+        let prev = match net.get(&path) {
+            Some(prev) => *prev,
+            None => {
+                order.push(path.clone());
+                None
+            }
+        };
+        net.insert(path, merge(prev, kind));
+    }
+
+    order
+        .into_iter()
+        .filter_map(|path| {
+            let renamed_from = sources
+                .get(&path)
+                .filter(|from| net.get(*from) == Some(&Some(DeltaKind::Removed)))
+                .map(|from| from.to_string_lossy().to_string());
+            net[&path].map(|kind| PathDelta {
+                path: path.to_string_lossy().to_string(),
+                renamed_from: renamed_from.filter(|_| kind == DeltaKind::Created),
+                kind,
+            })
+        })
+        .collect()
+
+}
+
+// Watches a folder recursively and reports changes in batches, at most one per `window`,
+// so bulk operations like a git checkout cause a single refresh
+pub fn watch_folder<R, F>(root: &Path, window: Duration, is_relevant: R, on_batch: F) -> notify::Result<RecommendedWatcher>
+where
+    R: Fn(&Path, DeltaKind) -> bool + Send + 'static,
+    F: Fn(Vec<PathDelta>) + Send + 'static,
+{
+    watch_batches(root, RecursiveMode::Recursive, window, is_relevant, on_batch)
+}
+
+// Like `watch_folder` for the entries directly in `dir` only
+pub fn watch_entries<R, F>(dir: &Path, window: Duration, is_relevant: R, on_batch: F) -> notify::Result<RecommendedWatcher>
+where
+    R: Fn(&Path, DeltaKind) -> bool + Send + 'static,
+    F: Fn(Vec<PathDelta>) + Send + 'static,
+{
+    watch_batches(dir, RecursiveMode::NonRecursive, window, is_relevant, on_batch)
+}
+
+fn watch_batches<R, F>(root: &Path, mode: RecursiveMode, window: Duration, is_relevant: R, on_batch: F) -> notify::Result<RecommendedWatcher>
+where
+    R: Fn(&Path, DeltaKind) -> bool + Send + 'static,
+    F: Fn(Vec<PathDelta>) + Send + 'static,
+{
+
+    let root = root.to_path_buf();
+    let (tx, rx) = mpsc::channel::<Event>();
+
+    let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
+        match res {
+            Ok(event) => { let _ = tx.send(event); },
+            Err(e) => println!("watch error: {:?}", e),
+        }
+    })?;
+
+    watcher.watch(&root, mode)?;
+
+    thread::spawn(move || {
+
+        while let Ok(first) = rx.recv() {
+
+            // The window starts at the first event, so a steady stream still flushes
+            let deadline = Instant::now() + window;
+            let mut events = vec![first];
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match rx.recv_timeout(remaining) {
+                    Ok(event) => events.push(event),
+                    Err(_) => break,
+                }
+            }
+
+            let batch = coalesce(&events, &is_relevant);
+            if !batch.is_empty() {
+                on_batch(batch);
+            }
+
+        }
+
+    });
+
+    Ok(watcher)
+
+}
+
+// Collects the events following `first` until none came for `quiet`, or `max` passed
+fn settle(rx: &mpsc::Receiver<Event>, first: Event, quiet: Duration, max: Duration) -> Vec<Event> {
+    let deadline = Instant::now() + max;
+    let mut events = vec![first];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now()).min(quiet);
+        if remaining.is_zero() {
+            break;
+        }
+        match rx.recv_timeout(remaining) {
+            Ok(event) => events.push(event),
+            Err(_) => break,
+        }
+    }
+    events
+}
+
+// Watches a single file through its parent directory, which keeps working when the
+// file is replaced by a rename. Dropping the returned watcher stops the background thread.
+pub fn watch_file<H, F>(path: &Path, known_hash: H, on_change: F) -> notify::Result<RecommendedWatcher>
+where
+    H: Fn(&Path) -> Option<String> + Send + 'static,
+    F: Fn(ExternalChange) + Send + 'static,
+{
+
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let parent = path
+        .parent()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| notify::Error::generic("file has no parent directory"))?;
+
+    let (tx, rx) = mpsc::channel::<Event>();
+
+    let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
+        match res {
+            Ok(event) => { let _ = tx.send(event); },
+            Err(e) => println!("watch error: {:?}", e),
+        }
+    })?;
+
+    watcher.watch(&parent, RecursiveMode::NonRecursive)?;
+
+    thread::spawn(move || {
+
+        let mut current: PathBuf = path;
+        let mut last_hash: Option<String> = None;
+
+        // Ends once the watcher, and with it the sender, is dropped
+        while let Ok(first) = rx.recv() {
+
+            let events = settle(&rx, first, SETTLE, MAX_SETTLE);
+
+            let known = known_hash(&current);
+            if let Some(change) = classify(&current, &events, known.as_deref(), last_hash.as_deref()) {
+                last_hash = change.hash.clone();
+                if let Some(new_path) = &change.new_path {
+                    current = PathBuf::from(new_path);
+                }
+                on_change(change);
+            }
+
+        }
+
+    });
+
+    Ok(watcher)
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+    use crate::save::content_hash;
+    use crate::tree::TreeOptions;
+    use notify::event::{CreateKind, RemoveKind};
+
+    fn modify(path: &Path) -> Event {
+        Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path.to_path_buf())
+    }
+
+    fn relevant_in(root: &Path) -> impl Fn(&Path, DeltaKind) -> bool {
+        let options = TreeOptions::default_for(root);
+        move |path, kind| options.is_relevant(path, kind)
+    }
+
+    fn event(kind: EventKind, paths: &[&Path]) -> Event {
+        paths.iter().fold(Event::new(kind), |e, p| e.add_path(p.to_path_buf()))
+    }
+
+    #[test]
+    fn test_coalesce_merges_repeated_events() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let file_path = root.join("note.md");
+        fs::write(&file_path, "content").unwrap();
+
+        let events = vec![
+            event(EventKind::Create(CreateKind::File), &[&file_path]),
+            modify(&file_path),
+            modify(&file_path),
+        ];
+        let batch = coalesce(&events, &relevant_in(root));
+
+        assert_eq!(batch, vec![PathDelta { path: file_path.to_string_lossy().to_string(), kind: DeltaKind::Created, renamed_from: None }]);
+    }
+
+    #[test]
+    fn test_coalesce_drops_create_then_remove() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let file_path = root.join("scratch.md");
+
+        let events = vec![
+            event(EventKind::Create(CreateKind::File), &[&file_path]),
+            event(EventKind::Remove(RemoveKind::File), &[&file_path]),
+        ];
+
+        assert!(coalesce(&events, &relevant_in(root)).is_empty());
+    }
+
+    #[test]
+    fn test_coalesce_splits_renames() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let old_path = root.join("old.md");
+        let new_path = root.join("new.md");
+        fs::write(&new_path, "content").unwrap();
+
+        let events = vec![event(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &[&old_path, &new_path])];
+        let batch = coalesce(&events, &relevant_in(root));
+
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0].kind, DeltaKind::Removed);
+        assert_eq!(batch[1].kind, DeltaKind::Created);
+        assert_eq!(batch[1].renamed_from, Some(old_path.to_string_lossy().to_string()));
+    }
+
+    #[test]
+    fn test_coalesce_keeps_unrelated_remove_and_create_apart() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let (a, b) = (root.join("a.md"), root.join("b.md"));
+        fs::write(&b, "content").unwrap();
+
+        let events = vec![
+            event(EventKind::Remove(RemoveKind::File), &[&a]),
+            event(EventKind::Create(CreateKind::File), &[&b]),
+        ];
+        let batch = coalesce(&events, &relevant_in(root));
+
+        assert_eq!(batch.len(), 2);
+        assert!(batch.iter().all(|delta| delta.renamed_from.is_none()));
+    }
+
+    #[test]
+    fn test_coalesce_filters_hidden_and_non_markdown() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        let git_dir = root.join(".git");
+        fs::create_dir(&git_dir).unwrap();
+        let git_file = git_dir.join("notes.md");
+        let text_file = root.join("notes.txt");
+        let temp_save = root.join(".note.md.aqua-1-0.tmp");
+        fs::write(&git_file, "content").unwrap();
+        fs::write(&text_file, "content").unwrap();
+
+        let events = vec![modify(&git_file), modify(&text_file), modify(&temp_save)];
+
+        assert!(coalesce(&events, &relevant_in(root)).is_empty());
+    }
+
+    #[test]
+    fn test_settle_stops_at_deadline() {
+        let (tx, rx) = mpsc::channel();
+        let path = PathBuf::from("/notes/a.md");
+        let sender = path.clone();
+        thread::spawn(move || {
+            // Keeps the channel busy for longer than the deadline
+            for _ in 0..100 {
+                if tx.send(modify(&sender)).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        let start = Instant::now();
+        let events = settle(&rx, modify(&path), Duration::from_millis(100), Duration::from_millis(200));
+
+        assert!(start.elapsed() < Duration::from_millis(600));
+        assert!(events.len() > 1);
+    }
+
+    #[test]
+    fn test_classify_ignores_other_files() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        let other_path = temp_dir.path().join("other.md");
+        fs::write(&file_path, "content").unwrap();
+
+        let result = classify(&file_path, &[modify(&other_path)], None, None);
+
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_classify_reports_modification() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        fs::write(&file_path, "changed").unwrap();
+
+        let change = classify(&file_path, &[modify(&file_path)], Some(&content_hash(b"original")), None)
+            .expect("Expected a change");
+
+        assert_eq!(change.kind, ChangeKind::Modified);
+        assert_eq!(change.hash, Some(content_hash(b"changed")));
+    }
+
+    #[test]
+    fn test_classify_ignores_own_save() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        fs::write(&file_path, "saved by aqua").unwrap();
+
+        let result = classify(&file_path, &[modify(&file_path)], Some(&content_hash(b"saved by aqua")), None);
+
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_classify_does_not_repeat_last_change() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        fs::write(&file_path, "changed").unwrap();
+        let hash = content_hash(b"changed");
+
+        let result = classify(&file_path, &[modify(&file_path)], Some(&content_hash(b"original")), Some(&hash));
+
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_classify_rename_save_is_modification() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        let swap_path = temp_dir.path().join(".note.md.swp");
+        fs::write(&file_path, "new content").unwrap();
+
+        let events = vec![
+            Event::new(EventKind::Remove(RemoveKind::File)).add_path(file_path.clone()),
+            Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                .add_path(swap_path)
+                .add_path(file_path.clone()),
+        ];
+
+        let change = classify(&file_path, &events, Some(&content_hash(b"old")), None).expect("Expected a change");
+
+        assert_eq!(change.kind, ChangeKind::Modified);
+    }
+
+    #[test]
+    fn test_classify_reports_deletion() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+
+        let events = vec![Event::new(EventKind::Remove(RemoveKind::File)).add_path(file_path.clone())];
+        let change = classify(&file_path, &events, None, None).expect("Expected a change");
+
+        assert_eq!(change.kind, ChangeKind::Deleted);
+        assert!(change.hash.is_none());
+    }
+
+    #[test]
+    fn test_classify_reports_rename() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file_path = temp_dir.path().join("note.md");
+        let new_path = temp_dir.path().join("renamed.md");
+        fs::write(&new_path, "content").unwrap();
+
+        let events = vec![
+            Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                .add_path(file_path.clone())
+                .add_path(new_path.clone()),
+            Event::new(EventKind::Create(CreateKind::File)).add_path(new_path.clone()),
+        ];
+        let change = classify(&file_path, &events, None, None).expect("Expected a change");
+
+        assert_eq!(change.kind, ChangeKind::Renamed);
+        assert_eq!(change.new_path, Some(new_path.to_string_lossy().to_string()));
+        assert_eq!(change.hash, Some(content_hash(b"content")));
+    }
+}
@@ -1,12 +1,12 @@
 // solid-js
-import { Component, createSignal, createEffect, onMount, onCleanup, createMemo, Show } from "solid-js";
+import { Component, createSignal, createEffect, onMount, onCleanup, createMemo, Show, For, on } from "solid-js";
 // Resizable import
 import Resizable from '@corvu/resizable';
 
 // Tauri import
 import { getCurrentWindow } from '@tauri-apps/api/window';
 import { invoke } from "@tauri-apps/api/core";
-import { listen } from "@tauri-apps/api/event";
+import { getCurrentWebviewWindow } from "@tauri-apps/api/webviewWindow";
 
 // components
 import Editor from "./components/Editor.tsx";
@@ -17,6 +17,8 @@ import Sidebar from "./components/Sidebar.tsx";
 import { loadFileChunked } from "./utils/fileLoader.ts";
 // file state store
 import { fileState } from './store/fileState';
+// session of this window
+import { sessionState, WindowSession } from './store/sessionState';
 // styles
 import "./styles/main.css";
 // utils
@@ -25,12 +27,32 @@ import { exportToHtml, printToPdf } from './utils/export.ts';
 import { themeState } from './store/themeState.ts';
 // status bar
 import StatusBar from "./components/StatusBar.tsx";
+// documents recovered after a crash
+import RecoveryPanel from "./components/RecoveryPanel.tsx";
+import { recovery, Recoverable } from './store/recovery';
+// saves refused because the file changed on disk
+import SaveProblemPanel from "./components/SaveProblemPanel.tsx";
 import { create } from "@tauri-apps/plugin-fs";
 
+// A file or folder to open, from the command line or the OS
+interface OpenRequest {
+  path: string;
+  is_dir: boolean;
+  line: number | null;
+  column: number | null;
+}
+
+// The window's last session and the paths of it that no longer exist
+interface RestoredSession {
+  session: WindowSession;
+  missing: string[];
+}
+
 const App: Component = () => {
   
   const [md, setMd] = createSignal("# Hello Aqua\nStart typing…");
   const [showSidebar, setShowSidebar] = createSignal(true);
+  const [missing, setMissing] = createSignal<string[]>([]);
 
   // Update window title on file path or modified changeb
   createEffect(() => {
@@ -38,32 +60,43 @@ const App: Component = () => {
     try { getCurrentWindow().setTitle(`${name} - Aqua`); } catch (error) {console.error("Failed to set window title:", error);}
   });
 
+  // Record the open file in the session whenever it changes
+  createEffect(on(fileState.path, () => sessionState.fileChanged(), { defer: true }));
+
   onMount(() => {
 
     let unlisteners: Array<() => void> = [];
 
     const setupListeners = async () => {
-      
-      const u1 = await listen("menu-export-html", () => {
-        const previewEl = document.querySelector('.preview');
-        if (previewEl) {
-          exportToHtml(previewEl.innerHTML, "document");
-        }
-        else {
-          console.error("Preview element not found for export.");
-        }
+
+      // Events are routed to one window; only listen for the ones sent to this window
+      const win = getCurrentWebviewWindow();
+
+      const u1 = await win.listen("menu-export-html", () => {
+        exportToHtml(md(), "document");
       });
 
-      const u2 = await listen("menu-print-pdf", () => {
+      const u2 = await win.listen("menu-print-pdf", () => {
         const content = md();
         printToPdf(content);
       });
 
-      const u3 = await listen("menu-toggle-sidebar", () => {
+      const u3 = await win.listen("menu-toggle-sidebar", () => {
         setShowSidebar(!showSidebar());
       });
 
-      unlisteners.push(u1, u2, u3);
+      // Paths handed to the running app, then the ones it was launched with
+      const u4 = await win.listen<OpenRequest[]>("open-paths", (event) => {
+        openRequests(event.payload);
+      });
+
+      unlisteners.push(u1, u2, u3, u4);
+
+      await restoreSession();
+      await recovery.load();
+
+      const launched = await invoke<OpenRequest[]>("take_launch_requests").catch(() => []);
+      openRequests(launched);
 
     };
 
@@ -104,9 +137,70 @@ const App: Component = () => {
     }
 
   }
+
+  // Reopen the file the window had open last time, where the editor stood in it
+  const restoreSession = async () => {
+
+    const restored = await invoke<RestoredSession | null>("get_window_session").catch(() => null);
+
+    if (restored) {
+      setMissing(restored.missing);
+      sessionState.setExpanded(new Set(restored.session.expanded));
+
+      const active = restored.session.active;
+      const file = restored.session.files.find(f => f.path === active);
+      if (active && !restored.missing.includes(active)) {
+        await handleFileSelect(active);
+        if (file) sessionState.setRestore({ cursor: file.cursor, scrollTop: file.scroll_top });
+      }
+    }
+
+    sessionState.ready();
+
+  }
+
+  // Reopen a recovered document with its unsaved content
+  const restoreRecovered = (item: Recoverable) => {
+    recovery.resolve(item, true);
+    setMd(item.content);
+    fileState.setPath(item.path);
+    fileState.setModified(true);
+  }
+
+  // Folders go to the sidebar; the last file is opened in the editor
+  const openRequests = async (requests: OpenRequest[]) => {
+
+    for (const request of requests.filter(r => r.is_dir)) {
+      setShowSidebar(true);
+      window.dispatchEvent(new CustomEvent('open-folder', { detail: request.path }));
+    }
+
+    const file = requests.filter(r => !r.is_dir).pop();
+    if (!file) return;
+
+    await handleFileSelect(file.path);
+    if (file.line !== null) {
+      window.dispatchEvent(new CustomEvent('editor-goto', { detail: { line: file.line, column: file.column ?? 1 } }));
+    }
+
+  }
   
   return (
     <div class="app-container">
+    <Show when={missing().length > 0}>
+      <div class="session-notice">
+        <span>Some files and folders of the last session could not be found:</span>
+        <ul>
+          <For each={missing()}>{(path) => <li>{path}</li>}</For>
+        </ul>
+        <button onClick={() => setMissing([])}>Dismiss</button>
+      </div>
+    </Show>
+    <RecoveryPanel onRestore={restoreRecovered} />
+    <SaveProblemPanel
+      onOverwrite={() => window.dispatchEvent(new CustomEvent('editor-overwrite'))}
+      onReload={() => window.dispatchEvent(new CustomEvent('editor-reload'))}
+    />
     <div class="app">
       <Show when={showSidebar()}>
         <Sidebar onFileSelect={handleFileSelect} />
@@ -1,4 +1,4 @@
-import { onMount, createSignal, createEffect } from 'solid-js';
+import { onMount, createSignal, createEffect, on } from 'solid-js';
 
 // CodeMirror imports
 import { syntaxHighlighting, defaultHighlightStyle } from '@codemirror/language';
@@ -9,10 +9,13 @@ import { EditorState } from '@codemirror/state';
 
 // Tauri imports
 import { invoke } from '@tauri-apps/api/core';
-import { listen } from '@tauri-apps/api/event';
+import { getCurrentWebviewWindow } from '@tauri-apps/api/webviewWindow';
 
 // Local imports for storage and state management
 import { fileState } from '../store/fileState.ts';
+import { sessionState } from '../store/sessionState.ts';
+import { recovery } from '../store/recovery.ts';
+import { saveProblem } from '../store/saveProblem.ts';
 import { initDB, saveDoc, loadDoc } from '../store/store.ts';
 
 // Import for throttle function for scroll syncing
@@ -47,6 +50,24 @@ const Editor = (props: EditorProps) => {
     }
   });
 
+  // Put the cursor and scroll position of a restored session back
+  createEffect(() => {
+
+    const v = view();
+    const restore = sessionState.restore();
+    if (!v || !restore) return;
+
+    v.dispatch({ selection: { anchor: Math.min(restore.cursor, v.state.doc.length) } });
+    v.scrollDOM.scrollTop = restore.scrollTop;
+    sessionState.setRestore(null);
+  });
+
+  // The document that was open is closed, so its snapshot is no longer needed
+  createEffect(on(fileState.path, (_, previous) => recovery.discard(previous ?? null), { defer: true }));
+
+  // A refused save belongs to the document it was for
+  createEffect(on(fileState.path, () => saveProblem.clear(), { defer: true }));
+
   let lastExternalScroll = 0;
 
   onMount(async () => {
@@ -82,6 +103,9 @@ const Editor = (props: EditorProps) => {
             });
 
           }
+          if (up.docChanged || up.selectionSet) {
+            sessionState.setView(up.state.selection.main.head, up.view.scrollDOM.scrollTop);
+          }
         }),
 
         EditorView.domEventHandlers({
@@ -90,6 +114,9 @@ const Editor = (props: EditorProps) => {
             const el = event.target as HTMLElement;
             if(!el || !parentEl) return;
 
+            const v = view();
+            if (v) sessionState.setView(v.state.selection.main.head, el.scrollTop);
+
             if(Date.now() - lastExternalScroll < 100) return;
 
             const pct = el.scrollTop / (el.scrollHeight - el.clientHeight);
@@ -107,8 +134,12 @@ const Editor = (props: EditorProps) => {
     // Store unlisten functions for cleanup
     const unlisteners: Array<() => void> = [];
 
+    // Menu events are sent to the window they were picked in
+    const win = getCurrentWebviewWindow();
+
     // New file menu listener
-    const unlistenNew = await listen('menu-new', () => {
+    const unlistenNew = await win.listen('menu-new', () => {
+      recovery.discard(fileState.path());
       v.dispatch({ changes: { from: 0, to: v.state.doc.length, insert: '' } });
       fileState.reset();
       props.onChange?.('');
@@ -116,7 +147,7 @@ const Editor = (props: EditorProps) => {
     unlisteners.push(unlistenNew);
 
     // Open file menu listener
-    const unlistenOpen = await listen('menu-open', async () => {
+    const unlistenOpen = await win.listen('menu-open', async () => {
 
       const file = await invoke<{path: string, content: string}>('pick_file').catch(() => null);
       if (!file) return;
@@ -138,8 +169,23 @@ const Editor = (props: EditorProps) => {
     });
     unlisteners.push(unlistenOpen);
 
+    // Writes the document to its file. A refused save, e.g. because the file changed
+    // on disk, is reported rather than dropped; `force` overwrites it anyway.
+    const save = async (path: string, force = false) => {
+      const text = v.state.doc.toString();
+      try {
+        await invoke('save_file', { path, content: text, force });
+        fileState.setModified(false);
+        recovery.discard(path);
+        saveProblem.clear();
+      } catch (err) {
+        console.error('Failed to save:', err);
+        saveProblem.report(path, text, err);
+      }
+    };
+
     // Save file menu listener
-    const unlistenSave = await listen('menu-save', async () => {
+    const unlistenSave = await win.listen('menu-save', async () => {
 
       const text = v.state.doc.toString();
       const path = fileState.path();
@@ -148,8 +194,7 @@ const Editor = (props: EditorProps) => {
 
       // overwrite existing file
       if (path) {
-        await invoke('save_file', { path, content: text });
-        fileState.setModified(false);
+        await save(path);
       }
       // save as new file
       else {
@@ -164,26 +209,60 @@ const Editor = (props: EditorProps) => {
     });
     unlisteners.push(unlistenSave);
 
-    // Auto-save every 30 seconds
+    // Auto-save every 30 seconds, unless a refused save waits for the user
     const autoSaveInterval = setInterval(() => {
-      if (!fileState.modified() || !fileState.path()) return;
-      const text = v.state.doc.toString();
-      invoke('save_file', { path: fileState.path(), content: text })
-        .then(() => fileState.setModified(false))
-        .catch(() => {/* silent fail */});
+      const path = fileState.path();
+      if (!fileState.modified() || !path || saveProblem.problem()) return;
+      save(path);
     }, 30_000);
 
+    // The user decided on a save that conflicted with the file on disk
+    const handleOverwrite = () => {
+      const path = fileState.path();
+      if (path) save(path, true);
+    };
+    window.addEventListener('editor-overwrite', handleOverwrite);
+
+    const handleReload = async () => {
+      const path = fileState.path();
+      if (!path) return;
+      try {
+        const content = await loadFileChunked(path);
+        v.dispatch({ changes: { from: 0, to: v.state.doc.length, insert: content } });
+        fileState.setModified(false);
+        props.onChange?.(content);
+        recovery.discard(path);
+        saveProblem.clear();
+      } catch (error) {
+        console.error("Error reloading file:", error);
+      }
+    };
+    window.addEventListener('editor-reload', handleReload);
+
+    // Snapshot unsaved changes every 5 seconds, so a crash loses little
+    let journaled = '';
+    const journalInterval = setInterval(() => {
+      if (!fileState.modified()) {
+        journaled = '';
+        return;
+      }
+      const text = v.state.doc.toString();
+      if (text === journaled) return;
+      journaled = text;
+      recovery.journal(fileState.path(), text);
+    }, 5_000);
+
     // Undo, Redo, Select All, Copy, Cut, Paste listeners
-    const unlistenUndo = await listen('undo', () => undo(v));
+    const unlistenUndo = await win.listen('undo', () => undo(v));
     unlisteners.push(unlistenUndo);
     
-    const unlistenRedo = await listen('redo', () => redo(v));
+    const unlistenRedo = await win.listen('redo', () => redo(v));
     unlisteners.push(unlistenRedo);
     
-    const unlistenSelectAll = await listen('select-all', () => v.dispatch({ selection: { anchor: 0, head: v.state.doc.length } }));
+    const unlistenSelectAll = await win.listen('select-all', () => v.dispatch({ selection: { anchor: 0, head: v.state.doc.length } }));
     unlisteners.push(unlistenSelectAll);
 
-    const unlistenCopy = await listen('copy', async () => {
+    const unlistenCopy = await win.listen('copy', async () => {
       const sel = v.state.selection.main;
       if (!sel.empty) {
         try {
@@ -196,7 +275,7 @@ const Editor = (props: EditorProps) => {
     });
     unlisteners.push(unlistenCopy);
 
-    const unlistenCut = await listen('cut', async () => {
+    const unlistenCut = await win.listen('cut', async () => {
       const sel = v.state.selection.main;
       if (!sel.empty) {
         try {
@@ -210,7 +289,7 @@ const Editor = (props: EditorProps) => {
     });
     unlisteners.push(unlistenCut);
 
-    const unlistenPaste = await listen('paste', async () => {
+    const unlistenPaste = await win.listen('paste', async () => {
       try {
         const text = (window as any).__TAURI__
           ? await invoke<string>('clipboard_read')
@@ -232,10 +311,24 @@ const Editor = (props: EditorProps) => {
     };
     window.addEventListener('preview-scroll', handlePreviewScroll);
 
+    // Jump to a 1-based line and column, e.g. for a file opened as `aqua notes.md +12:4`
+    const handleGoto = (e: any) => {
+      const doc = v.state.doc;
+      const line = doc.line(Math.min(Math.max(e.detail.line, 1), doc.lines));
+      const anchor = line.from + Math.min(Math.max(e.detail.column - 1, 0), line.length);
+      v.dispatch({ selection: { anchor }, scrollIntoView: true });
+      v.focus();
+    };
+    window.addEventListener('editor-goto', handleGoto);
+
     // Cleanup function
     return () => {
       clearInterval(autoSaveInterval);
+      clearInterval(journalInterval);
       window.removeEventListener('preview-scroll', handlePreviewScroll);
+      window.removeEventListener('editor-goto', handleGoto);
+      window.removeEventListener('editor-overwrite', handleOverwrite);
+      window.removeEventListener('editor-reload', handleReload);
       unlisteners.forEach(unlisten => unlisten());
       v.destroy();
     };
@@ -0,0 +1,55 @@
+import { Component, For, Show } from "solid-js";
+import { recovery, Recoverable } from "../store/recovery";
+import '../styles/components/recovery.css'
+
+// Documents a crashed run left unsaved, each with how it differs from the file on disk
+const RecoveryPanel: Component<{ onRestore: (item: Recoverable) => void }> = (props) => {
+
+    const name = (item: Recoverable) => item.path?.split(/[/\\]/).pop() ?? 'Untitled';
+
+    const prefix = { equal: ' ', insert: '+', delete: '-' };
+
+    return (
+        <Show when={recovery.recoverable().length > 0}>
+            <div class="recovery-panel">
+
+                <span class="recovery-title">Unsaved changes were recovered from the last session</span>
+
+                <For each={recovery.recoverable()}>
+                    {(item) => (
+                        <div class="recovery-item">
+
+                            <div class="recovery-header">
+                                <span class="recovery-name" title={item.path ?? undefined}>{name(item)}</span>
+                                <span class="recovery-time">{new Date(item.saved_ms).toLocaleString()}</span>
+                                <Show when={item.path && !item.on_disk}>
+                                    <span class="recovery-gone">deleted from disk</span>
+                                </Show>
+                                <button onClick={() => props.onRestore(item)}>Restore</button>
+                                <button onClick={() => recovery.resolve(item, false)}>Discard</button>
+                            </div>
+
+                            <pre class="recovery-diff">
+                                <For each={item.hunks}>
+                                    {(hunk) => (
+                                        <>
+                                            <div class="diff-hunk">{`@@ -${hunk.old_start},${hunk.old_len} +${hunk.new_start},${hunk.new_len} @@`}</div>
+                                            <For each={hunk.lines}>
+                                                {(line) => <div class={`diff-${line.op}`}>{prefix[line.op] + line.text}</div>}
+                                            </For>
+                                        </>
+                                    )}
+                                </For>
+                            </pre>
+
+                        </div>
+                    )}
+                </For>
+
+            </div>
+        </Show>
+    );
+
+};
+
+export default RecoveryPanel;
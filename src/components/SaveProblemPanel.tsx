@@ -0,0 +1,53 @@
+import { Component, For, Show } from "solid-js";
+import { saveProblem } from "../store/saveProblem";
+import '../styles/components/recovery.css'
+
+// A save that was refused, with a way out: overwrite the file, reload it, or look at
+// how the editor differs from it first
+const SaveProblemPanel: Component<{ onOverwrite: () => void; onReload: () => void }> = (props) => {
+
+    const prefix = { equal: ' ', insert: '+', delete: '-' };
+
+    return (
+        <Show when={saveProblem.problem()}>
+            {(problem) => (
+                <div class="recovery-panel">
+
+                    <div class="recovery-header">
+                        <span class="recovery-name" title={problem().path}>{problem().path.split(/[/\\]/).pop()}</span>
+                        <span>{problem().deleted ? 'was deleted from disk since it was opened' : problem().message}</span>
+                        <Show when={problem().conflict}>
+                            <button onClick={() => props.onOverwrite()}>Overwrite</button>
+                            <Show when={!problem().deleted}>
+                                <button onClick={() => props.onReload()}>Reload</button>
+                            </Show>
+                            <button onClick={() => saveProblem.showDiff()}>Diff</button>
+                        </Show>
+                        <button onClick={() => saveProblem.clear()}>Dismiss</button>
+                    </div>
+
+                    <Show when={problem().hunks}>
+                        {(hunks) => (
+                            <pre class="recovery-diff">
+                                <For each={hunks()}>
+                                    {(hunk) => (
+                                        <>
+                                            <div class="diff-hunk">{`@@ -${hunk.old_start},${hunk.old_len} +${hunk.new_start},${hunk.new_len} @@`}</div>
+                                            <For each={hunk.lines}>
+                                                {(line) => <div class={`diff-${line.op}`}>{prefix[line.op] + line.text}</div>}
+                                            </For>
+                                        </>
+                                    )}
+                                </For>
+                            </pre>
+                        )}
+                    </Show>
+
+                </div>
+            )}
+        </Show>
+    );
+
+};
+
+export default SaveProblemPanel;
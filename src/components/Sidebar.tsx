@@ -2,11 +2,13 @@
 import { createSignal, onCleanup, onMount, Show, For, createMemo } from 'solid-js';
 // Tauri imports
 import { invoke } from '@tauri-apps/api/core';
-import { listen } from '@tauri-apps/api/event';
+import { getCurrentWebviewWindow } from '@tauri-apps/api/webviewWindow';
 // Folder Tree Nodes
 import {FileNode, FlatNode, flattenTree} from '../store/fileTreeTypes';
 // Theme Toggle Component
 import { ThemeToggle } from './ThemeToggle';
+// Session of this window, which keeps the expanded folders
+import { sessionState } from '../store/sessionState';
 
 import { createVirtualizer } from '@tanstack/solid-virtual';
 
@@ -15,6 +17,12 @@ interface SidebarProps {
   onFileSelect: (path: string) => void;
 }
 
+// A root folder open in the sidebar
+interface FolderResult {
+  path: string;
+  tree: FileNode[];
+}
+
 const ITEM_HEIGHT = 30; // Height of each file tree item in pixels
 
 const VirtualizedFileTreeItem = (props: {
@@ -22,6 +30,7 @@ const VirtualizedFileTreeItem = (props: {
   onSelect: (p: string) => void;
   isExpanded: boolean;
   onToggle: (path: string) => void;
+  onClose?: (path: string) => void;
 }) => {
   const toggle = (e: MouseEvent) => {
     e.stopPropagation();
@@ -42,16 +51,45 @@ const VirtualizedFileTreeItem = (props: {
         {props.flatNode.isDir ? (props.isExpanded ? '📂' : '📁') : '📄'}
       </span>
       <span class="name">{props.flatNode.name}</span>
+      <Show when={props.onClose}>
+        <button
+          class="btn-close-root"
+          title="Remove folder from workspace"
+          onClick={(e) => { e.stopPropagation(); props.onClose?.(props.flatNode.id); }}
+        >
+          ×
+        </button>
+      </Show>
     </div>
   );
 };
 
 // Component
 const Sidebar = (props: SidebarProps) => {
-  const [fileTree, setFileTree] = createSignal<FileNode[]>([]);
-  const [currentRoot, setCurrentRoot] = createSignal<string | null>(null);
-  const [expandedKeys, setExpandedKeys] = createSignal<Set<string>>(new Set());
+  const [roots, setRoots] = createSignal<FolderResult[]>([]);
+  const expandedKeys = sessionState.expanded;
   const [containerRef, setContainerRef] = createSignal<HTMLDivElement | undefined>(undefined);
+
+  // A single root shows its contents; several show one top-level folder each
+  const fileTree = createMemo<FileNode[]>(() => {
+    const open = roots();
+    if (open.length === 1) return open[0].tree;
+    return open.map((root) => ({
+      name: root.path.split(/[/\\]/).filter(Boolean).pop() || root.path,
+      path: root.path,
+      is_dir: true,
+      children: root.tree,
+    }));
+  });
+
+  // Add a root, or replace the tree of one already open
+  const upsertRoot = (result: FolderResult) => {
+    setRoots((prev) => {
+      const index = prev.findIndex((root) => root.path === result.path);
+      if (index === -1) return [...prev, result];
+      return prev.map((root, i) => (i === index ? result : root));
+    });
+  };
   
   // Create a memoized flattened tree that updates whenever fileTree or expandedKeys change
   const flattenedTree = createMemo(() => {
@@ -80,63 +118,111 @@ const Sidebar = (props: SidebarProps) => {
 
   // Toggle folder expansion
   const toggleExpanded = (path: string) => {
-    setExpandedKeys((prev) => {
-      const newSet = new Set(prev);
-      if (newSet.has(path)) {
-        newSet.delete(path);
-      } else {
-        newSet.add(path);
-      }
-      return newSet;
-    });
+    const newSet = new Set(expandedKeys());
+    if (newSet.has(path)) {
+      newSet.delete(path);
+    } else {
+      newSet.add(path);
+    }
+    sessionState.setExpanded(newSet);
   };
   
   // Pick folder and generate tree
   const pickFolder = async () => {
     try {
-      const result = await invoke<{path: string, tree: FileNode[]}>('open_folder_and_list_files');
+      const result = await invoke<FolderResult>('open_folder_and_list_files');
       if (result) {
-        setFileTree(result.tree);
-        setCurrentRoot(result.path);
-        setExpandedKeys(new Set<string>()); // Reset expanded keys when opening new folder
+        setRoots([result]);
+        sessionState.setExpanded(new Set<string>()); // Reset expanded keys when opening new folder
       }
     } catch (err) {
       if (err !== "cancelled") console.error("Error:", err);
     }
   };
 
-  // Refresh tree data from Rust
-  const refreshTree = async () => {
+  // Add a folder as one more root, next to the open ones
+  const addFolder = async () => {
+    try {
+      const result = await invoke<FolderResult>('open_folder_and_list_files', { add: true });
+      if (result) upsertRoot(result);
+    } catch (err) {
+      if (err !== "cancelled") console.error("Error:", err);
+    }
+  };
 
-    const path = currentRoot();
-    
-    if (!path) return;
+  // Take a root folder out of the workspace
+  const closeRoot = async (path: string) => {
+    try {
+      await invoke('close_folder', { path });
+      setRoots((prev) => prev.filter((root) => root.path !== path));
+    } catch (err) {
+      console.error("Error:", err);
+    }
+  };
 
+  // Open a folder the app was asked to open, in place of the current one
+  const openFolderAt = async (path: string) => {
     try {
-      const updatedTree = await invoke<FileNode[]>('get_directory_tree', {path});
-      setFileTree(updatedTree);
-    } catch (err){
-      console.error("Failed to refresh tree:", err);
+      const result = await invoke<FolderResult>('open_folder', { path });
+      setRoots([result]);
+      sessionState.setExpanded(new Set<string>());
+    } catch (err) {
+      console.error("Error:", err);
+    }
+  };
+
+  // Refresh tree data from Rust
+  const refreshTree = async () => {
+
+    for (const { path } of roots()) {
+      try {
+        const tree = await invoke<FileNode[]>('get_directory_tree', {path});
+        upsertRoot({ path, tree });
+      } catch (err){
+        console.error("Failed to refresh tree:", err);
+      }
     }
 
   };
 
   // Listen for refresh events from Rust watcher
   onMount(async () => {
-    
+
+    // The backend sends tree events to the window the folders are open in
+    const win = getCurrentWebviewWindow();
+
     // Refresh file watcher
-    const unListenRefresh = await listen('refresh-files', async () => {
+    const unListenRefresh = await win.listen('refresh-files', async () => {
         console.log("File system change detected!");
         refreshTree();
     });
 
-    const unListenMenuFolder = await listen('menu-open-folder', async () => {
+    const unListenMenuFolder = await win.listen('menu-open-folder', async () => {
       await pickFolder();
     })
 
+    const unListenAddFolder = await win.listen('menu-add-folder', async () => {
+      await addFolder();
+    });
+
+    // Folders of the last session, opened by the backend after launch
+    const unListenRestored = await win.listen<FolderResult>('folder-opened', (event) => {
+      upsertRoot(event.payload);
+    });
+
+    // and the ones it opened before this listener was in place
+    const opened = await invoke<FolderResult[]>('list_open_folders').catch(() => []);
+    opened.forEach(upsertRoot);
+
+    const handleOpenFolder = (e: any) => openFolderAt(e.detail);
+    window.addEventListener('open-folder', handleOpenFolder);
+
     onCleanup(() => {
       unListenRefresh();
       unListenMenuFolder();
+      unListenAddFolder();
+      unListenRestored();
+      window.removeEventListener('open-folder', handleOpenFolder);
     });
   });
 
@@ -146,6 +232,9 @@ const Sidebar = (props: SidebarProps) => {
       <div class="sidebar-header">
         <h2>Workspace</h2>
         <button onClick={pickFolder} class="btn-open">Open Project</button>
+        <Show when={roots().length === 1}>
+          <button onClick={() => closeRoot(roots()[0].path)} class="btn-close-folder">Close Folder</button>
+        </Show>
       </div>
       
       <div class="file-tree" ref={setContainerRef}>
@@ -166,6 +255,7 @@ const Sidebar = (props: SidebarProps) => {
                     onSelect={props.onFileSelect}
                     isExpanded={expandedKeys().has(flatNode.id)}
                     onToggle={toggleExpanded}
+                    onClose={flatNode.depth === 0 && roots().length > 1 ? closeRoot : undefined}
                   />
                 </div>
               );
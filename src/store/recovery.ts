@@ -0,0 +1,67 @@
+import { createSignal } from 'solid-js';
+import { invoke } from '@tauri-apps/api/core';
+
+export interface DiffLine {
+  op: 'equal' | 'insert' | 'delete';
+  text: string;
+  old_line: number | null;
+  new_line: number | null;
+}
+
+export interface DiffHunk {
+  old_start: number;
+  old_len: number;
+  new_start: number;
+  new_len: number;
+  lines: DiffLine[];
+}
+
+// A document left unsaved by a run that crashed
+export interface Recoverable {
+  key: string;
+  path: string | null;
+  content: string;
+  saved_ms: number;
+  on_disk: boolean;
+  hunks: DiffHunk[];
+}
+
+// The untitled document has no path to key its snapshot by, so it gets an id of its
+// own. Every window has one, and a new one is made once it is saved or closed.
+const newUntitledKey = () => `untitled:${crypto.randomUUID()}`;
+let untitledKey = newUntitledKey();
+
+const [recoverable, setRecoverable] = createSignal<Recoverable[]>([]);
+
+const keyOf = (path: string | null) => path ?? untitledKey;
+
+export const recovery = {
+  recoverable,
+
+  // Fetched once on startup; empty unless the last run crashed
+  async load () {
+    setRecoverable(await invoke<Recoverable[]>('take_recoverable_buffers').catch(() => []));
+  },
+
+  journal (path: string | null, content: string) {
+    return invoke('journal_buffer', { pathOrId: keyOf(path), content })
+      .catch((err) => console.error('Failed to journal buffer:', err));
+  },
+
+  // The document was saved or closed. Snapshots still offered for recovery are kept
+  // until the user decides on them.
+  discard (path: string | null) {
+    const key = keyOf(path);
+    if (path === null) untitledKey = newUntitledKey();
+    if (recoverable().some(r => r.key === key)) return;
+    invoke('discard_buffer', { pathOrId: key }).catch(() => {});
+  },
+
+  // Taken out of the list, either reopened or dropped for good. A reopened untitled
+  // document keeps its id, so its snapshot is the one journaled from now on.
+  resolve (item: Recoverable, keep: boolean) {
+    setRecoverable(recoverable().filter(r => r.key !== item.key));
+    if (keep && item.path === null) untitledKey = item.key;
+    if (!keep) invoke('discard_buffer', { pathOrId: item.key }).catch(() => {});
+  },
+};
@@ -0,0 +1,49 @@
+import { createSignal } from 'solid-js';
+import { invoke } from '@tauri-apps/api/core';
+import { DiffHunk } from './recovery';
+
+// A save the backend refused. A conflict means the file changed on disk since it
+// was opened; `content` is what the editor tried to write.
+export interface SaveProblem {
+  path: string;
+  content: string;
+  conflict: boolean;
+  deleted: boolean;
+  message: string;
+  hunks: DiffHunk[] | null;
+}
+
+const [problem, setProblem] = createSignal<SaveProblem | null>(null);
+
+export const saveProblem = {
+  problem,
+
+  // `err` is the rejection of `save_file`, `{ kind, detail }` for a save error
+  report (path: string, content: string, err: any) {
+    const conflict = err?.kind === 'conflict';
+    setProblem({
+      path,
+      content,
+      conflict,
+      deleted: conflict && err.detail?.actual == null,
+      message: conflict ? 'changed on disk since it was opened' : `could not be saved: ${err?.detail ?? err}`,
+      hunks: null,
+    });
+  },
+
+  // How the refused content differs from the file on disk
+  async showDiff () {
+    const current = problem();
+    if (!current) return;
+    const hunks = await invoke<DiffHunk[]>('diff_with_disk', { path: current.path, content: current.content })
+      .catch((err) => {
+        console.error('Failed to diff with disk:', err);
+        return [];
+      });
+    if (problem() === current) setProblem({ ...current, hunks });
+  },
+
+  clear () {
+    setProblem(null);
+  },
+};
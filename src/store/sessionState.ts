@@ -0,0 +1,73 @@
+import { createSignal } from 'solid-js';
+import { invoke } from '@tauri-apps/api/core';
+import { fileState } from './fileState';
+
+export interface FileSession {
+  path: string;
+  cursor: number;
+  scroll_top: number;
+}
+
+export interface WindowSession {
+  files: FileSession[];
+  active: string | null;
+  expanded: string[];
+}
+
+// Where to put the cursor and scroll position once the editor holds the restored file
+export interface ViewRestore {
+  cursor: number;
+  scrollTop: number;
+}
+
+const SAVE_DELAY = 1000;
+
+const [expanded, setExpanded] = createSignal<Set<string>>(new Set());
+const [restore, setRestore] = createSignal<ViewRestore | null>(null);
+
+let cursor = 0;
+let scrollTop = 0;
+// Nothing is saved until the last session had its chance to be restored
+let ready = false;
+let timer: ReturnType<typeof setTimeout> | undefined;
+
+const save = () => {
+  const path = fileState.path();
+  const session: WindowSession = {
+    files: path ? [{ path, cursor, scroll_top: scrollTop }] : [],
+    active: path,
+    expanded: [...expanded()],
+  };
+  invoke('save_window_session', { session }).catch((err) => console.error('Failed to save session:', err));
+};
+
+const schedule = () => {
+  if (!ready) return;
+  clearTimeout(timer);
+  timer = setTimeout(save, SAVE_DELAY);
+};
+
+export const sessionState = {
+  expanded,
+  restore,
+  setExpanded (keys: Set<string>) {
+    setExpanded(keys);
+    schedule();
+  },
+  setView (nextCursor: number, nextScrollTop: number) {
+    cursor = nextCursor;
+    scrollTop = nextScrollTop;
+    schedule();
+  },
+  setRestore,
+  // The file moved, so its cursor and scroll start over
+  fileChanged () {
+    cursor = 0;
+    scrollTop = 0;
+    schedule();
+  },
+  ready () {
+    ready = true;
+  },
+  schedule,
+};
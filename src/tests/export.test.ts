@@ -5,6 +5,11 @@ import DOMPurify from 'dompurify';
 // Mock Tauri APIs
 const mockSave = vi.fn();
 const mockWriteTextFile = vi.fn();
+const mockInvoke = vi.fn();
+
+vi.mock('@tauri-apps/api/core', () => ({
+  invoke: mockInvoke,
+}));
 
 vi.mock('@tauri-apps/plugin-dialog', () => ({
   save: mockSave,
@@ -38,13 +43,17 @@ describe('Export Utilities', () => {
   });
 
   describe('exportToHtml', () => {
+    beforeEach(() => {
+      mockInvoke.mockResolvedValue('<!DOCTYPE html>\n<div class="preview">\n<p>Test</p>\n</div>');
+    });
+
     it('should create HTML with content', async () => {
       const { exportToHtml } = await import('../utils/export');
       
       mockSave.mockResolvedValue('/path/to/file.html');
       mockWriteTextFile.mockResolvedValue(undefined);
 
-      const content = '<p>Test content</p>';
+      const content = 'Test content';
       await exportToHtml(content, 'test-doc');
 
       expect(mockSave).toHaveBeenCalledWith({
@@ -59,7 +68,7 @@ describe('Export Utilities', () => {
       mockSave.mockResolvedValue('/path/to/file.html');
       mockWriteTextFile.mockResolvedValue(undefined);
 
-      await exportToHtml('<p>Content</p>');
+      await exportToHtml('Content');
 
       expect(mockSave).toHaveBeenCalledWith({
         filters: [{ name: 'HTML', extensions: ['html'] }],
@@ -73,44 +82,31 @@ describe('Export Utilities', () => {
       mockSave.mockResolvedValue(null);
       mockWriteTextFile.mockResolvedValue(undefined);
 
-      await exportToHtml('<p>Content</p>', 'doc');
+      await exportToHtml('Content', 'doc');
 
       expect(mockWriteTextFile).not.toHaveBeenCalled();
     });
 
-    it('should include stylesheets in HTML', async () => {
+    it('should write the page rendered by the backend', async () => {
       const { exportToHtml } = await import('../utils/export');
-      
-      // Add a style element to document
-      const style = document.createElement('style');
-      style.textContent = 'body { color: blue; }';
-      document.head.appendChild(style);
 
       mockSave.mockResolvedValue('/path/to/file.html');
       mockWriteTextFile.mockResolvedValue(undefined);
 
-      await exportToHtml('<p>Content</p>', 'doc');
-
-      const writtenHtml = mockWriteTextFile.mock.calls[0][1] as string;
-      expect(writtenHtml).toContain('<style>');
-      expect(writtenHtml).toContain('color: blue');
+      await exportToHtml('Test', 'doc');
 
-      document.head.removeChild(style);
+      expect(mockInvoke).toHaveBeenCalledWith('render_html', { markdown: 'Test', title: 'doc' });
+      expect(mockWriteTextFile).toHaveBeenCalledWith('/path/to/file.html', expect.stringContaining('<div class="preview">'));
     });
 
-    it('should include DOCTYPE and proper HTML structure', async () => {
+    it('should not render when the save dialog is cancelled', async () => {
       const { exportToHtml } = await import('../utils/export');
-      
-      mockSave.mockResolvedValue('/path/to/file.html');
-      mockWriteTextFile.mockResolvedValue(undefined);
 
-      await exportToHtml('<p>Test</p>', 'doc');
+      mockSave.mockResolvedValue(null);
+
+      await exportToHtml('Test', 'doc');
 
-      const writtenHtml = mockWriteTextFile.mock.calls[0][1] as string;
-      expect(writtenHtml).toContain('<!DOCTYPE html>');
-      expect(writtenHtml).toContain('<html lang="en">');
-      expect(writtenHtml).toContain('<meta charset="UTF-8">');
-      expect(writtenHtml).toContain('<div class="preview">');
+      expect(mockInvoke).not.toHaveBeenCalled();
     });
 
     it('should handle export error gracefully', async () => {
@@ -120,7 +116,7 @@ describe('Export Utilities', () => {
 
       const consoleErrorSpy = vi.spyOn(console, 'error').mockImplementation(() => {});
       
-      await exportToHtml('<p>Content</p>', 'doc');
+      await exportToHtml('Content', 'doc');
 
       expect(consoleErrorSpy).toHaveBeenCalledWith(
         expect.stringContaining('Error exporting to HTML'),
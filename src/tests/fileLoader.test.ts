@@ -8,8 +8,8 @@ vi.mock('@tauri-apps/api/core', () => ({
   invoke: mockInvoke,
 }));
 
-vi.mock('@tauri-apps/api/event', () => ({
-  listen: mockListen,
+vi.mock('@tauri-apps/api/webviewWindow', () => ({
+  getCurrentWebviewWindow: () => ({ listen: mockListen }),
 }));
 
 describe('FileLoader - Chunked File Loading', () => {
@@ -0,0 +1,50 @@
+import { describe, it, expect, beforeEach, vi } from 'vitest';
+
+const mockInvoke = vi.fn();
+
+vi.mock('@tauri-apps/api/core', () => ({
+  invoke: mockInvoke,
+}));
+
+const journaledKey = (call: number) => mockInvoke.mock.calls[call][1].pathOrId;
+
+describe('Recovery - Untitled Documents', () => {
+  beforeEach(() => {
+    vi.clearAllMocks();
+    mockInvoke.mockResolvedValue(undefined);
+  });
+
+  it('should journal an untitled document under an id of its own', async () => {
+    const { recovery } = await import('../store/recovery');
+    await recovery.journal(null, 'draft');
+    await recovery.journal(null, 'draft, longer');
+
+    expect(journaledKey(0)).toMatch(/^untitled:/);
+    expect(journaledKey(1)).toBe(journaledKey(0));
+  });
+
+  it('should give the next untitled document a new id once one is closed', async () => {
+    const { recovery } = await import('../store/recovery');
+    await recovery.journal(null, 'first');
+    recovery.discard(null);
+    await recovery.journal(null, 'second');
+
+    expect(mockInvoke).toHaveBeenCalledWith('discard_buffer', { pathOrId: journaledKey(0) });
+    expect(journaledKey(2)).not.toBe(journaledKey(0));
+  });
+
+  it('should keep the id of a reopened untitled document', async () => {
+    const { recovery } = await import('../store/recovery');
+    recovery.resolve({ key: 'untitled:old', path: null, content: 'x', saved_ms: 1, on_disk: false, hunks: [] }, true);
+    await recovery.journal(null, 'x, edited');
+
+    expect(journaledKey(0)).toBe('untitled:old');
+  });
+
+  it('should journal documents with a file under their path', async () => {
+    const { recovery } = await import('../store/recovery');
+    await recovery.journal('/notes/a.md', 'text');
+
+    expect(journaledKey(0)).toBe('/notes/a.md');
+  });
+});
@@ -0,0 +1,56 @@
+import { describe, it, expect, beforeEach, vi } from 'vitest';
+
+const mockInvoke = vi.fn();
+
+vi.mock('@tauri-apps/api/core', () => ({
+  invoke: mockInvoke,
+}));
+
+const fingerprint = { mtime_ms: 1, size: 2, hash: 'abc' };
+
+describe('SaveProblem - Refused Saves', () => {
+  beforeEach(async () => {
+    vi.clearAllMocks();
+    const { saveProblem } = await import('../store/saveProblem');
+    saveProblem.clear();
+  });
+
+  it('should report a conflict with the file changed on disk', async () => {
+    const { saveProblem } = await import('../store/saveProblem');
+    saveProblem.report('/notes/a.md', 'mine', {
+      kind: 'conflict',
+      detail: { path: '/notes/a.md', expected: fingerprint, actual: { ...fingerprint, hash: 'def' } },
+    });
+
+    expect(saveProblem.problem()).toMatchObject({ path: '/notes/a.md', conflict: true, deleted: false });
+  });
+
+  it('should report a conflict with the file deleted on disk', async () => {
+    const { saveProblem } = await import('../store/saveProblem');
+    saveProblem.report('/notes/a.md', 'mine', {
+      kind: 'conflict',
+      detail: { path: '/notes/a.md', expected: fingerprint, actual: null },
+    });
+
+    expect(saveProblem.problem()).toMatchObject({ conflict: true, deleted: true });
+  });
+
+  it('should report other save errors with their message', async () => {
+    const { saveProblem } = await import('../store/saveProblem');
+    saveProblem.report('/notes/a.md', 'mine', { kind: 'write', detail: 'disk full' });
+
+    expect(saveProblem.problem()).toMatchObject({ conflict: false, message: 'could not be saved: disk full' });
+  });
+
+  it('should diff the refused content with the file on disk', async () => {
+    const { saveProblem } = await import('../store/saveProblem');
+    const hunks = [{ old_start: 1, old_len: 1, new_start: 1, new_len: 1, lines: [] }];
+    mockInvoke.mockResolvedValue(hunks);
+
+    saveProblem.report('/notes/a.md', 'mine', { kind: 'conflict', detail: { actual: fingerprint } });
+    await saveProblem.showDiff();
+
+    expect(mockInvoke).toHaveBeenCalledWith('diff_with_disk', { path: '/notes/a.md', content: 'mine' });
+    expect(saveProblem.problem()?.hunks).toEqual(hunks);
+  });
+});
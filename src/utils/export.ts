@@ -1,37 +1,22 @@
+import { invoke } from '@tauri-apps/api/core';
 import { save } from '@tauri-apps/plugin-dialog';
 import { writeTextFile } from '@tauri-apps/plugin-fs';
 
 import DOMPurify from 'dompurify';
 import { Marked } from 'marked';
 
-export const exportToHtml = async (content: string, title: string = 'export') => {
+// The page is rendered by the backend, the same one the command line export writes
+export const exportToHtml = async (markdown: string, title: string = 'export') => {
 
     try {
-        const styles = Array.from(document.querySelectorAll('link[rel="stylesheet"], style'))
-            .map(el => el.outerHTML)
-            .join('\n');
-
-        const fullHtml = `
-            <!DOCTYPE html>
-            <html lang="en">
-            <head>
-                <meta charset="UTF-8">
-                <title>${title}</title>
-                ${styles}
-            </head>
-            <body>
-                <div class="preview">${content}</div>
-            </body>
-            </html>
-        `;
-
         const path = await save({
             filters: [{ name: 'HTML', extensions: ['html']}],
             defaultPath: `${title}.html`
         });
 
         if (path) {
-            await writeTextFile(path, fullHtml);
+            const html = await invoke<string>('render_html', { markdown, title });
+            await writeTextFile(path, html);
         }
     }
     catch (error) {
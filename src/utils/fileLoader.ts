@@ -1,5 +1,5 @@
 import { invoke } from '@tauri-apps/api/core';
-import { listen } from '@tauri-apps/api/event';
+import { getCurrentWebviewWindow } from '@tauri-apps/api/webviewWindow';
 
 interface FileChunk {
   content: string;
@@ -11,9 +11,9 @@ export async function loadFileChunked(path: string): Promise<string> {
     let fullContent = "";
     let unlisten: () => void;
 
-    // 1. Setup Listener
+    // 1. Setup Listener, for the chunks sent to this window only
     try {
-      unlisten = await listen<FileChunk>('file-chunk', (event) => {
+      unlisten = await getCurrentWebviewWindow().listen<FileChunk>('file-chunk', (event) => {
         const { content, is_last } = event.payload;
         fullContent += content;
 